@@ -0,0 +1,289 @@
+use std::convert::TryFrom;
+use std::future;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Error};
+use async_trait::async_trait;
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509Builder, X509NameBuilder};
+use structopt::StructOpt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time::{self, Instant};
+use tokio_native_tls::native_tls::{Certificate, Identity, TlsAcceptor, TlsConnector};
+use tokio_native_tls::TlsStream;
+
+use input::{Axis, Event, Manager};
+use net::{Encoding, Message, PROTOCOL_VERSION};
+use rkvm_core::{Handle, Kvm};
+
+const NAME: &str = "rkvm-bench";
+
+// Stands in for the input devices, the events come from the generator instead.
+struct BenchManager {
+    events: UnboundedReceiver<Event>,
+}
+
+#[async_trait]
+impl Manager for BenchManager {
+    async fn read(&mut self) -> Result<Event, io::Error> {
+        match self.events.recv().await {
+            Some(event) => Ok(event),
+            None => future::pending().await,
+        }
+    }
+
+    async fn write(&mut self, _event: Event) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn notify(&mut self, _message: String) {}
+
+    fn set_indicator_led(&mut self, _on: bool) {}
+}
+
+// Runs a server and a client in one process, connected over loopback TLS, and pushes synthetic pointer motion from
+// one to the other. Each event carries its index as the delta, so that it can be matched with the time it was sent.
+async fn run(args: Args) -> Result<(), Error> {
+    if args.events == 0 || i32::try_from(args.events).is_err() {
+        return Err(Error::msg("The number of events must be between 1 and 2147483647"));
+    }
+
+    let (identity, certificate) = identity().context("Failed to generate an identity")?;
+    let acceptor: tokio_native_tls::TlsAcceptor = TlsAcceptor::new(identity)
+        .context("Failed to create acceptor")?
+        .into();
+    let connector: tokio_native_tls::TlsConnector = TlsConnector::builder()
+        .add_root_certificate(certificate)
+        .build()
+        .context("Failed to create connector")?
+        .into();
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let address = listener.local_addr()?;
+
+    let (events, receiver) = mpsc::unbounded_channel();
+    let (kvm, handle) = Kvm::builder(BenchManager { events: receiver }, "rkvm-bench-server".to_owned()).build();
+
+    let bench = async {
+        let (_, stream) = tokio::try_join!(
+            accept(listener, acceptor, &handle),
+            connect(address, connector, args.compact)
+        )?;
+
+        // The client is added by the running server, switching fails until it has been.
+        while handle.switch(NAME).await.is_err() {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let rate = if args.rate == 0 { None } else { Some(args.rate) };
+        let (sent, received) = tokio::try_join!(
+            generate(events, args.events, rate),
+            receive(stream, args.events, args.compact)
+        )?;
+
+        report(&sent, &received);
+        Ok(())
+    };
+
+    tokio::select! {
+        result = kvm.run() => {
+            result?;
+            Err(Error::msg("Server stopped"))
+        }
+        result = bench => result,
+    }
+}
+
+// A throwaway self-signed certificate for localhost.
+fn identity() -> Result<(Identity, Certificate), Error> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, "localhost")?;
+    let name = name.build();
+
+    let mut builder = X509Builder::new()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&*BigNum::from_u32(1)?.to_asn1_integer()?)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    builder.set_not_before(&*Asn1Time::days_from_now(0)?)?;
+    builder.set_not_after(&*Asn1Time::days_from_now(1)?)?;
+    let alt_name = SubjectAlternativeName::new()
+        .dns("localhost")
+        .build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(alt_name)?;
+    builder.sign(&key, MessageDigest::sha256())?;
+    let x509 = builder.build();
+
+    let identity = Identity::from_pkcs8(&x509.to_pem()?, &key.private_key_to_pem_pkcs8()?)?;
+    let certificate = Certificate::from_der(&x509.to_der()?)?;
+    Ok((identity, certificate))
+}
+
+// The server's side of the handshake, the connection is then handed over to the KVM like a real client's.
+async fn accept(listener: TcpListener, acceptor: tokio_native_tls::TlsAcceptor, handle: &Handle) -> Result<(), Error> {
+    let (stream, address) = listener.accept().await?;
+    net::tcp::Options::default().apply(&stream)?;
+
+    let mut stream = acceptor.accept(stream).await.context("TLS handshake failed")?;
+    net::write_version(&mut stream, PROTOCOL_VERSION).await?;
+    net::read_version(&mut stream).await?;
+
+    match net::read_message(&mut stream, net::DEFAULT_MAX_MESSAGE_SIZE, Encoding::Protobuf).await? {
+        Message::Hello(name) => {
+            handle.add_client(name, address, stream);
+            Ok(())
+        }
+        message => Err(anyhow::anyhow!("Expected hello, got {:?}", message)),
+    }
+}
+
+async fn connect(
+    address: SocketAddr,
+    connector: tokio_native_tls::TlsConnector,
+    compact: bool,
+) -> Result<TlsStream<TcpStream>, Error> {
+    let stream = TcpStream::connect(address).await?;
+    net::tcp::Options::default().apply(&stream)?;
+
+    let mut stream = connector
+        .connect("localhost", stream)
+        .await
+        .context("Failed to connect")?;
+    net::write_version(&mut stream, PROTOCOL_VERSION).await?;
+    net::read_version(&mut stream).await?;
+    net::write_message(&mut stream, &Message::Hello(NAME.to_owned()), Encoding::Protobuf).await?;
+
+    if !compact {
+        return Ok(stream);
+    }
+
+    // The server offers the encodings right after the handshake, the switch is done before measuring.
+    loop {
+        let message = read(&mut stream, Encoding::Protobuf).await?;
+        match message {
+            Message::Encodings(encodings) if encodings.contains(&Encoding::Compact) => {
+                net::write_message(&mut stream, &Message::SetEncoding(Encoding::Compact), Encoding::Protobuf).await?;
+            }
+            Message::Encodings(_) => return Err(Error::msg("The server doesn't support the compact encoding")),
+            Message::SetEncoding(_) => return Ok(stream),
+            _ => {}
+        }
+    }
+}
+
+// Sends the given number of events per second, or as fast as possible without a rate.
+async fn generate(events: UnboundedSender<Event>, count: usize, rate: Option<u32>) -> Result<Vec<Instant>, Error> {
+    let start = Instant::now();
+    let mut sent = Vec::with_capacity(count);
+    for index in 0..count {
+        if let Some(rate) = rate {
+            time::sleep_until(start + Duration::from_secs_f64(index as f64 / f64::from(rate))).await;
+        }
+
+        sent.push(Instant::now());
+        let event = Event::MouseMove {
+            axis: Axis::X,
+            delta: index as i32,
+        };
+        events.send(event).map_err(|_| Error::msg("Server stopped"))?;
+    }
+
+    Ok(sent)
+}
+
+async fn receive(mut stream: TlsStream<TcpStream>, count: usize, compact: bool) -> Result<Vec<Instant>, Error> {
+    let encoding = if compact { Encoding::Compact } else { Encoding::Protobuf };
+
+    let mut received = vec![None; count];
+    let mut remaining = count;
+    while remaining > 0 {
+        match read(&mut stream, encoding).await? {
+            Message::Event(Event::MouseMove { delta, .. }) | Message::SequencedEvent(_, Event::MouseMove { delta, .. }) => {
+                if let Some(slot @ None) = received.get_mut(delta as usize) {
+                    *slot = Some(Instant::now());
+                    remaining -= 1;
+                }
+            }
+            // The server gives up on clients which don't answer.
+            Message::Ping(timestamp) => {
+                let time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|since| since.as_micros() as u64)
+                    .unwrap_or(0);
+                net::write_message(&mut stream, &Message::Pong(timestamp, time), encoding).await?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(received.into_iter().flatten().collect())
+}
+
+async fn read(stream: &mut TlsStream<TcpStream>, encoding: Encoding) -> Result<Message, Error> {
+    let message = time::timeout(
+        net::MESSAGE_TIMEOUT,
+        net::read_message(stream, net::DEFAULT_MAX_MESSAGE_SIZE, encoding),
+    )
+    .await
+    .context("Read timed out")??;
+    Ok(message)
+}
+
+fn report(sent: &[Instant], received: &[Instant]) {
+    let mut latencies: Vec<_> = sent
+        .iter()
+        .zip(received)
+        .map(|(sent, received)| received.duration_since(*sent))
+        .collect();
+    latencies.sort_unstable();
+
+    let elapsed = received.iter().max().unwrap().duration_since(sent[0]);
+    println!(
+        "{} events in {:.3} s, {:.0} events/s",
+        latencies.len(),
+        elapsed.as_secs_f64(),
+        latencies.len() as f64 / elapsed.as_secs_f64()
+    );
+
+    for &(label, percentile) in &[("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("p99.9", 0.999), ("max", 1.0)] {
+        let index = ((latencies.len() - 1) as f64 * percentile).round() as usize;
+        println!("{:>6} {:>10.1} us", label, latencies[index].as_secs_f64() * 1e6);
+    }
+}
+
+#[derive(StructOpt)]
+#[structopt(
+    name = "rkvm-bench",
+    about = "Measures throughput and latency of events going through a server and a connection over loopback TLS"
+)]
+struct Args {
+    #[structopt(long, default_value = "100000", help = "Number of events to send")]
+    events: usize,
+    #[structopt(long, default_value = "1000", help = "Events per second, 0 sends as fast as possible")]
+    rate: u32,
+    #[structopt(long, help = "Switch to the compact encoding instead of staying on protobuf")]
+    compact: bool,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::from_args();
+    if let Err(err) = run(args).await {
+        eprintln!("Error: {:#}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use anyhow::{Context, Error};
+use input::{Backend, Platform};
+use tokio::net::TcpStream;
+use tokio::time;
+
+use crate::config::Config;
+
+// Goes through what the client needs to run, printing what passed and what to do about what didn't, instead of
+// failing on the first problem. Returns whether everything passed.
+pub async fn run(config: &Config) -> bool {
+    let mut passed = true;
+    // In the same order as when running, the writer drops privileges.
+    if !config.kill_keys.is_empty() || !config.focus_keys.is_empty() {
+        passed &= report(
+            "Grabbing the input devices for the local hotkeys",
+            Platform::manager().await.map(drop).map_err(Error::from),
+            DEVICES_HINT,
+        );
+    }
+    passed &= report(
+        "Creating the virtual input device",
+        Platform::writer().await.map(drop).map_err(Error::from),
+        WRITER_HINT,
+    );
+
+    let connector = match crate::connector(config).await {
+        Ok(connector) => {
+            report("Server certificate", Ok(()), "");
+            connector
+        }
+        Err(err) => {
+            report("Server certificate", Err(err), "Copy the certificate generated with the server's identity and check certificate-path");
+            return false;
+        }
+    };
+
+    let server = config.server.hostname.as_str();
+    let port = config.server.port;
+    let timeout = Duration::from_millis(config.message_timeout_ms);
+    let stream = time::timeout(timeout, TcpStream::connect((server, port)))
+        .await
+        .context("Timed out")
+        .and_then(|result| result.map_err(Error::from));
+    let stream = match stream {
+        Ok(stream) => {
+            report(&format!("Connecting to {}:{}", server, port), Ok(()), "");
+            stream
+        }
+        Err(err) => {
+            report(
+                &format!("Connecting to {}:{}", server, port),
+                Err(err),
+                "Is the server running, and does the firewall let the port through?",
+            );
+            return false;
+        }
+    };
+
+    let handshake = time::timeout(timeout, connector.connect(server, stream))
+        .await
+        .context("Timed out")
+        .and_then(|result| result.map(drop).map_err(Error::from));
+    passed &= report(
+        "TLS handshake",
+        handshake,
+        "Does the certificate belong to the server's identity, and is it issued for the hostname in the config?",
+    );
+
+    passed
+}
+
+#[cfg(target_os = "linux")]
+const DEVICES_HINT: &str = "Run as root or a user with access to /dev/input, or leave out kill-keys and focus-keys";
+
+#[cfg(not(target_os = "linux"))]
+const DEVICES_HINT: &str = "Run as a user with access to the input devices, or leave out kill-keys and focus-keys";
+
+#[cfg(target_os = "linux")]
+const WRITER_HINT: &str = "Run as root or a user with access to /dev/uinput, and load uinput with modprobe uinput";
+
+#[cfg(not(target_os = "linux"))]
+const WRITER_HINT: &str = "Run as a user allowed to inject input";
+
+fn report(what: &str, result: Result<(), Error>, hint: &str) -> bool {
+    match result {
+        Ok(()) => {
+            println!("ok    {}", what);
+            true
+        }
+        Err(err) => {
+            println!("FAIL  {}: {:#}", what, err);
+            println!("      {}", hint);
+            false
+        }
+    }
+}
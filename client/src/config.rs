@@ -1,13 +1,88 @@
+use anyhow::Error;
+use input::pointer::Corner;
+use input::Key;
+use log::LevelFilter;
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer};
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::fmt::{self, Formatter};
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tokio_native_tls::native_tls::Protocol;
 
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub server: Server,
     pub certificate_path: PathBuf,
+    // The hostname if unset.
+    pub name: Option<String>,
+    #[serde(default)]
+    pub osd_command: Vec<String>,
+    pub park_pointer: Option<Corner>,
+    #[serde(default)]
+    pub headless: bool,
+    #[serde(default)]
+    pub kill_keys: HashSet<Key>,
+    #[serde(default)]
+    pub focus_keys: HashSet<Key>,
+    pub relay: Option<Relay>,
+    #[serde(default)]
+    pub unreliable_motion: bool,
+    pub display_scale: Option<f64>,
+    #[serde(default)]
+    pub sanitize_clipboard: bool,
+    #[serde(default)]
+    pub commands: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub tls: Tls,
+    #[serde(default)]
+    pub tcp: net::tcp::Options,
+    pub log_level: Option<LevelFilter>,
+    #[serde(default = "default_max_message_size")]
+    pub max_message_size: u32,
+    pub message_key: Option<String>,
+    #[serde(default = "default_message_timeout_ms")]
+    pub message_timeout_ms: u64,
+}
+
+// Restricts the TLS handshake, anything left unset follows the platform's TLS library.
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Tls {
+    pub min_version: Option<TlsVersion>,
+    pub max_version: Option<TlsVersion>,
+    // Protocols offered through ALPN, the server has to accept one of them.
+    #[serde(default)]
+    pub alpn: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    V1_2,
+    #[serde(rename = "1.3")]
+    V1_3,
+}
+
+impl TlsVersion {
+    pub fn protocol(self) -> Protocol {
+        match self {
+            TlsVersion::V1_2 => Protocol::Tlsv12,
+            TlsVersion::V1_3 => Protocol::Tlsv13,
+        }
+    }
+}
+
+// Lets machines further down connect to this one, see relay.rs.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Relay {
+    pub listen_address: SocketAddr,
+    pub identity_path: PathBuf,
+    pub identity_password: String,
+    pub switch_keys: HashSet<Key>,
 }
 
 pub struct Server {
@@ -56,3 +131,43 @@ impl<'de> Visitor<'de> for ServerVisitor {
         })
     }
 }
+
+fn default_max_message_size() -> u32 {
+    net::DEFAULT_MAX_MESSAGE_SIZE
+}
+
+fn default_message_timeout_ms() -> u64 {
+    net::MESSAGE_TIMEOUT.as_millis() as u64
+}
+
+// Expands environment variable references in every string of the config, see rkvm_core::expand_env_vars.
+pub fn expand_env_vars(value: &mut toml::Value) -> Result<(), Error> {
+    match value {
+        toml::Value::String(text) => {
+            *text = rkvm_core::expand_env_vars(text, |name| env::var(name).ok()).map_err(Error::msg)?;
+        }
+        toml::Value::Array(values) => {
+            for value in values {
+                expand_env_vars(value)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                expand_env_vars(value)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// YAML and JSON configs are told apart by their extension, anything else is TOML. They all end up as TOML values,
+// which the rest of the config handling works with.
+pub fn parse(path: &Path, text: &str) -> Result<toml::Value, Error> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("yaml") | Some("yml") => serde_yaml::from_str(text).map_err(Error::from),
+        Some("json") => serde_json::from_str(text).map_err(Error::from),
+        _ => toml::from_str(text).map_err(Error::from),
+    }
+}
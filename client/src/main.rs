@@ -1,88 +1,614 @@
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::Infallible;
+use std::future;
 use std::path::{Path, PathBuf};
 use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Error};
 use gethostname::gethostname;
 use log::{LevelFilter, warn};
 use structopt::StructOpt;
 use tokio::fs;
-use tokio::io::BufReader;
-use tokio::net::TcpStream;
+use tokio::io::{self, AsyncRead, BufReader};
+use tokio::net::{TcpStream, UdpSocket};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::{self, UnboundedSender};
 use tokio::time;
 use tokio_native_tls::native_tls::{Certificate, TlsConnector};
 
-use config::Config;
-use input::{clipboard, EventWriter};
-use net::{self, Message, PROTOCOL_VERSION};
+use config::{Config, TlsVersion};
+use input::osd::OsdCommand;
+use input::{clipboard, Backend, Direction, Event, EventManager, EventWriter, Key, KeyKind, Manager, Platform, Writer};
+use net::clipboard::Reassembler;
+use net::motion::{Motion, Opener};
+use net::sealed::Side;
+use relay::{Local, Relay};
+use net::{self, Encoding, Handshake, Message, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
 
+mod check;
 mod config;
+mod relay;
+mod system_info;
+#[cfg(windows)]
+mod service;
 
-async fn run(server: &str, port: u16, certificate_path: &Path) -> Result<Infallible, Error> {
-    let mut writer = EventWriter::new().await?;
+// The example config, which explains every option.
+const DEFAULT_CONFIG: &str = include_str!("../../example/client.toml");
 
-    let certificate = fs::read(certificate_path)
-        .await
-        .context("Failed to read certificate")?;
-    let certificate = Certificate::from_der(&certificate)
-        .or_else(|_| Certificate::from_pem(&certificate))
-        .context("Failed to parse certificate")?;
+// How often the server is told about the battery level and such, see system_info.
+const SYSTEM_INFO_INTERVAL: Duration = Duration::from_secs(60);
 
-    let connector: tokio_native_tls::TlsConnector = TlsConnector::builder()
-        .add_root_certificate(certificate)
-        .build()
-        .context("Failed to create connector")?
-        .into();
+// How long an event waits for the motion sent over UDP before it, which may have been lost, see net::motion.
+const MOTION_WAIT: Duration = Duration::from_millis(20);
+// Motion waiting for the event it was sent after, beyond this the oldest is dropped.
+const MAX_EARLY_MOTION: usize = 256;
+
+#[tracing::instrument(name = "connection", skip_all, fields(server = %config.server.hostname))]
+// Returns once stopped by a signal, losing the server is an error.
+async fn run(config: &Config) -> Result<(), Error> {
+    let server = config.server.hostname.as_str();
+    let port = config.server.port;
+    // Grabbed before creating the writer, which drops privileges.
+    let manager = if config.kill_keys.is_empty() && config.focus_keys.is_empty() {
+        None
+    } else {
+        Some(Platform::manager().await.context("Failed to grab the local devices for the local hotkeys")?)
+    };
+    let (focus_sender, mut focus_requests) = mpsc::unbounded_channel();
+    let local_keys = watch_local_keys(manager, &config.kill_keys, &config.focus_keys, focus_sender);
+    tokio::pin!(local_keys);
+
+    let mut writer = Platform::writer().await?;
+    let mut osd = OsdCommand::new(config.osd_command.clone());
+
+    let connector = connector(config).await?;
 
     let stream = TcpStream::connect((server, port)).await?;
+    config.tcp.apply(&stream).context("Failed to set socket options")?;
+    let local_ip = stream.local_addr()?.ip();
     let stream = BufReader::new(stream);
     let mut stream = connector
         .connect(server, stream)
         .await
         .context("Failed to connect")?;
 
+    if !config.tls.alpn.is_empty() {
+        match stream.get_ref().negotiated_alpn()? {
+            Some(protocol) => log::info!("Negotiated ALPN protocol {}", String::from_utf8_lossy(&protocol)),
+            None => return Err(Error::msg("The server accepted none of the ALPN protocols")),
+        }
+    }
+
     log::info!("Connected to {}:{}", server, port);
 
     net::write_version(&mut stream, PROTOCOL_VERSION).await?;
 
-    let version = net::read_version(&mut stream).await?;
-    if version != PROTOCOL_VERSION {
-        return Err(anyhow::anyhow!(
-            "Incompatible protocol version (got {}, expecting {})",
-            version,
-            PROTOCOL_VERSION
-        ));
+    let offered = net::read_version(&mut stream).await?;
+    net::negotiate_version(offered).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Incompatible protocol version (got {}, expecting at least {})",
+            offered,
+            MIN_PROTOCOL_VERSION
+        )
+    })?;
+    let key = config.message_key.as_deref().map(str::as_bytes);
+    let mut stream = net::sealed::seal(stream, key, Side::Client)
+        .await
+        .context("Failed to set up message authentication")?;
+    net::confirm_version(&mut stream, PROTOCOL_VERSION, offered).await?;
+
+    let name = config
+        .name
+        .clone()
+        .unwrap_or_else(|| gethostname().to_string_lossy().into_owned());
+    net::write_message(&mut stream, &Message::Hello(name.clone()), Encoding::Protobuf).await?;
+    // Servers which don't know the message skip it.
+    let handshake = Handshake {
+        id: system_info::machine_id(),
+        platform: std::env::consts::OS.to_owned(),
+        display_scale: config.display_scale,
+        features: net::FEATURES.iter().map(|feature| (*feature).to_owned()).collect(),
+    };
+    net::write_message(&mut stream, &Message::Handshake(handshake), Encoding::Protobuf).await?;
+    // So that the server drops what the virtual device can't write, rather than it failing here.
+    if let Some(capabilities) = Writer::capabilities(&mut writer) {
+        net::write_message(&mut stream, &Message::Capabilities(capabilities), Encoding::Protobuf).await?;
     }
 
-    net::write_message(&mut stream, &Message::Hello(gethostname().to_str().unwrap().to_string())).await?;
+    // Messages are read on their own and handed over, a read cancelled halfway through a frame by another branch of the
+    // select below would lose its start and get the stream out of step.
+    let (reader, mut stream) = io::split(stream);
+    let (message_sender, mut messages) = mpsc::unbounded_channel();
+    let timeout = Duration::from_millis(config.message_timeout_ms);
+    let read = read_messages(reader, message_sender, timeout, config.max_message_size);
+    tokio::pin!(read);
+
+    // See net::Encoding for how switching works.
+    let mut write_encoding = Encoding::Protobuf;
+    let mut chunks = Reassembler::default();
+    let mut focused = false;
+    // Motion received over UDP, see net::motion, and what arrived ahead of the event it was sent after.
+    let mut motion: Option<(UdpSocket, Opener)> = None;
+    let mut early_motion = VecDeque::new();
+    let mut motion_acknowledged: Option<time::Instant> = None;
+    let mut packet = [0; net::motion::PACKET_SIZE];
+    // The first tick completes right away, so the server gets the system info as soon as we're connected.
+    let mut system_info_interval = time::interval(SYSTEM_INFO_INTERVAL);
+    // The number of the last event received from the server, and the keys it has pressed on this machine.
+    let mut sequence = None;
+    let mut held = HashSet::new();
+
+    let (mut relay, relay_task) = relay::start(config, &name).await?;
+    tokio::pin!(relay_task);
+    let stop = terminated();
+    tokio::pin!(stop);
 
     loop {
-        let message = time::timeout(net::MESSAGE_TIMEOUT, net::read_message(&mut stream))
-            .await
-            .context("Read timed out")??;
+        let message = tokio::select! {
+            Some(message) = messages.recv() => message,
+            result = &mut read => match result? {},
+            result = &mut local_keys => {
+                result?;
+                return Err(Error::msg("Kill keys pressed, disconnecting"));
+            }
+            result = &mut relay_task => match result? {},
+            // Forged, stale and broken datagrams are skipped.
+            Some(arrived) = async {
+                let (socket, opener) = motion.as_mut()?;
+                let size = socket.recv(&mut packet).await.ok()?;
+                opener.open(&packet[..size])
+            }, if motion.is_some() => {
+                // The server sends motion over the connection until it hears that datagrams get through.
+                if motion_acknowledged.map(|at| at.elapsed() >= net::motion::ACK_INTERVAL).unwrap_or(true) {
+                    let last = motion.as_ref().map(|(_, opener)| opener.last()).unwrap_or(0);
+                    net::write_message(&mut stream, &Message::MotionAck(last), write_encoding).await?;
+                    motion_acknowledged = Some(time::Instant::now());
+                }
+                deliver_motion(&relay, &mut writer, &mut held, &mut early_motion, Some(arrived), sequence, config.headless).await;
+                continue;
+            }
+            Some(local) = async { relay.as_mut()?.local.recv().await }, if relay.is_some() => {
+                match local {
+                    Local::Event(event) => write_event(&mut writer, event, config.headless).await,
+                    Local::Notify(message) if !config.headless => writer.notify(message),
+                    Local::Notify(_) => {}
+                }
+                continue;
+            }
+            _ = system_info_interval.tick() => {
+                let info = system_info::read();
+                net::write_message(&mut stream, &Message::SystemInfo(info), write_encoding).await?;
+                continue;
+            }
+            result = &mut stop => {
+                result.context("Failed to set up signal handlers")?;
+                log::info!("Shutting down on signal");
+                // Keys the server has pressed here would stay down otherwise.
+                for kind in held.clone() {
+                    let event = Event::Key {
+                        direction: Direction::Up,
+                        kind,
+                    };
+                    deliver(&relay, &mut writer, &mut held, event, config.headless).await;
+                }
+                net::write_message(&mut stream, &Message::Goodbye, write_encoding).await?;
+                return Ok(());
+            }
+            Some(()) = focus_requests.recv() => {
+                net::write_message(&mut stream, &Message::RequestFocus(!focused), write_encoding).await?;
+                continue;
+            }
+        };
         match message {
-            Message::Event(event) => {
-                writer.write(event).await?
-            },
+            Message::Event(event) => deliver(&relay, &mut writer, &mut held, event, config.headless).await,
+            // After a gap the held keys can't be trusted, the server answers with the ones it has pressed.
+            // Events arriving out of order are dropped, anything they'd have changed is covered by the resync.
+            Message::SequencedEvent(number, event) => {
+                let expected = sequence.map(|last| last + 1).unwrap_or(number);
+                if number != expected {
+                    warn!("Expected event {}, got {}, resynchronizing held keys", expected, number);
+                    net::write_message(&mut stream, &Message::RequestKeyState, write_encoding).await?;
+                }
+
+                if number >= expected {
+                    sequence = Some(number);
+                    deliver(&relay, &mut writer, &mut held, event, config.headless).await;
+                    deliver_motion(&relay, &mut writer, &mut held, &mut early_motion, None, sequence, config.headless).await;
+                }
+            }
+            Message::KeyStateSync(keys) => {
+                let wanted: HashSet<_> = keys.into_iter().collect();
+                let released = held.difference(&wanted).map(|kind| (Direction::Up, *kind));
+                let pressed = wanted.difference(&held).map(|kind| (Direction::Down, *kind));
+                let events: Vec<_> = released
+                    .chain(pressed)
+                    .map(|(direction, kind)| Event::Key { direction, kind })
+                    .collect();
+                if !events.is_empty() {
+                    log::info!("Resynchronizing {} held keys with the server", events.len());
+                }
+                for event in events {
+                    deliver(&relay, &mut writer, &mut held, event, config.headless).await;
+                }
+            }
+            // Headless clients leave the clipboard alone and show no notifications.
+            Message::GetClipboardData
+            | Message::ClipboardOffer(_)
+            | Message::ClipboardRequest(_)
+            | Message::ClipboardChunk(..)
+            | Message::ClipboardData(..)
+            | Message::CancelTransfer
+            | Message::Notify(_)
+                if config.headless => {}
             Message::KeepAlive => {}
+            Message::Ping(timestamp) => {
+                let time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|since| since.as_micros() as u64)
+                    .unwrap_or(0);
+                net::write_message(&mut stream, &Message::Pong(timestamp, time), write_encoding).await?;
+            }
             Message::Notify(msg) => {
                 writer.notify(msg);
             }
             Message::GetClipboardData => {
-                if let Some(text) = clipboard::get_text() {
-                    if let Err(e) = net::write_message(&mut stream, &Message::SetClipboardData(text)).await {
-                        warn!("Failed to send clip {}", e);
+                let offer = Message::ClipboardOffer(clipboard::formats());
+                net::write_message(&mut stream, &offer, write_encoding).await?;
+            }
+            Message::ClipboardOffer(mime_types) => {
+                if let Some(mime_type) = clipboard::pick(&mime_types) {
+                    net::write_message(&mut stream, &Message::ClipboardRequest(mime_type), write_encoding).await?;
+                }
+            }
+            Message::ClipboardRequest(mime_type) => {
+                if let Some(data) = clipboard::get(&mime_type) {
+                    if data.len() > net::clipboard::CHUNK_SIZE {
+                        writer.notify(format!("Sending {} of clipboard content", net::clipboard::describe(data.len() as u64)));
+                    }
+                    for message in net::clipboard::split(mime_type, data) {
+                        if let Err(e) = net::write_message(&mut stream, &message, write_encoding).await {
+                            warn!("Failed to send clip {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+            Message::ClipboardChunk(total, data) => {
+                if !chunks.in_progress() {
+                    writer.notify(format!("Receiving {} of clipboard content", net::clipboard::describe(total)));
+                }
+                chunks.push(total, data, config.max_message_size)?;
+            }
+            Message::ClipboardData(mime_type, data) => {
+                let total = chunks.in_progress().then(|| chunks.total());
+                if let Some(data) = chunks.finish(data) {
+                    clipboard::set(&mime_type, data, config.sanitize_clipboard);
+                    if let Some(total) = total {
+                        writer.notify(format!("Clipboard transfer done ({})", net::clipboard::describe(total)));
+                    }
+                }
+            }
+            // Dropping what has been received is all there is to do.
+            Message::CancelTransfer if chunks.abort() => {
+                writer.notify("Clipboard transfer cancelled".to_string());
+            }
+            Message::Focus(true) => {
+                focused = true;
+                osd.show(&name);
+            }
+            Message::LockState(wanted) => {
+                if let Some(current) = Writer::lock_state(&mut writer) {
+                    for event in current.toggles(wanted) {
+                        write_event(&mut writer, event, config.headless).await;
+                    }
+                }
+            }
+            Message::Activity => {
+                for event in input::pointer::nudge() {
+                    write_event(&mut writer, event, config.headless).await;
+                }
+            }
+            Message::Focus(false) => {
+                focused = false;
+                osd.hide();
+                if let Some(state) = Writer::lock_state(&mut writer) {
+                    net::write_message(&mut stream, &Message::LockState(state), write_encoding).await?;
+                }
+                if let Some(corner) = config.park_pointer {
+                    for event in corner.events() {
+                        write_event(&mut writer, event, config.headless).await;
                     }
                 }
             }
-            Message::SetClipboardData(text) => {
-                clipboard::set_text(text);
+            // The event coming next waits a moment for the motion sent before it.
+            Message::MotionSent(last) => {
+                let deadline = time::Instant::now() + MOTION_WAIT;
+                while let Some((socket, opener)) = motion.as_mut().filter(|motion| motion.1.last() < last) {
+                    let size = match time::timeout_at(deadline, socket.recv(&mut packet)).await {
+                        Ok(Ok(size)) => size,
+                        _ => break,
+                    };
+                    let arrived = opener.open(&packet[..size]);
+                    deliver_motion(&relay, &mut writer, &mut held, &mut early_motion, arrived, sequence, config.headless).await;
+                }
+            }
+            Message::MotionKey(key) if config.unreliable_motion => {
+                let socket = UdpSocket::bind((local_ip, 0)).await?;
+                let port = socket.local_addr()?.port();
+                net::write_message(&mut stream, &Message::MotionChannel(port), write_encoding).await?;
+                log::debug!("Receiving motion over UDP on port {}", port);
+                motion = Some((socket, Opener::new(key)));
+                motion_acknowledged = None;
+            }
+            Message::Encodings(encodings) if encodings.contains(&Encoding::Compact) => {
+                net::write_message(&mut stream, &Message::SetEncoding(Encoding::Compact), write_encoding).await?;
+                write_encoding = Encoding::Compact;
+            }
+            // Already followed by read_messages.
+            Message::SetEncoding(encoding) => log::debug!("Switching to {:?} encoding", encoding),
+            Message::RunCommand(name) => run_allowed_command(&config.commands, &name),
+            Message::Suspend => {
+                log::info!("Suspending as asked by the server");
+                input::session::suspend();
+            }
+            Message::Lock => {
+                log::info!("Locking the screen as asked by the server");
+                input::session::lock();
+            }
+            Message::SecureAttention => {
+                log::info!("Going through the secure attention sequence as asked by the server");
+                for event in input::session::secure_attention() {
+                    write_event(&mut writer, event, config.headless).await;
+                }
             }
+            // Keys held on the old device are released by the kernel as it goes away.
+            Message::Capabilities(capabilities) if !config.headless => {
+                held.clear();
+                match Writer::mirror(&mut writer, &capabilities).await {
+                    Ok(()) => log::info!("Mirroring {} keys of the server's devices", capabilities.keys.len()),
+                    Err(err) => warn!("Failed to mirror the server's devices, keeping the generic one: {}", err),
+                }
+                if let Some(capabilities) = Writer::capabilities(&mut writer) {
+                    net::write_message(&mut stream, &Message::Capabilities(capabilities), write_encoding).await?;
+                }
+            }
+            Message::Goodbye => return Err(Error::msg("The server shut down")),
             _ => {}
         }
     }
 }
 
+// Trusts the server certificate from the config and nothing else.
+async fn connector(config: &Config) -> Result<tokio_native_tls::TlsConnector, Error> {
+    let certificate = fs::read(&config.certificate_path)
+        .await
+        .context("Failed to read certificate")?;
+    let certificate = Certificate::from_der(&certificate)
+        .or_else(|_| Certificate::from_pem(&certificate))
+        .context("Failed to parse certificate")?;
+
+    let alpn: Vec<&str> = config.tls.alpn.iter().map(String::as_str).collect();
+    TlsConnector::builder()
+        .add_root_certificate(certificate)
+        .min_protocol_version(config.tls.min_version.map(TlsVersion::protocol))
+        .max_protocol_version(config.tls.max_version.map(TlsVersion::protocol))
+        .request_alpns(&alpn)
+        .build()
+        .context("Failed to create connector")
+        .map(Into::into)
+}
+
+// Only commands listed in the config can be run, the server just picks one by name.
+fn run_allowed_command(commands: &HashMap<String, Vec<String>>, name: &str) {
+    let (program, args) = match commands.get(name).and_then(|command| command.split_first()) {
+        Some(command) => command,
+        None => {
+            warn!("The server asked to run {}, which isn't in the commands allowed by the config", name);
+            return;
+        }
+    };
+
+    log::info!("Running {} as asked by the server", name);
+    let mut child = match tokio::process::Command::new(program).args(args).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to run {}: {}", name, e);
+            return;
+        }
+    };
+
+    let name = name.to_owned();
+    tokio::spawn(async move {
+        match child.wait().await {
+            Ok(status) if !status.success() => warn!("{} exited with {}", name, status),
+            Ok(_) => {}
+            Err(e) => warn!("Failed to wait for {}: {}", name, e),
+        }
+    });
+}
+
+// Watches the local keyboards for hotkeys of someone sitting at this machine. Completes once the kill keys are held
+// down, taking the machine back from the server, the focus keys send a request to move focus here or back.
+// Local input still reaches this machine, by way of the manager's virtual device.
+#[tracing::instrument(name = "local_keys", skip_all)]
+async fn watch_local_keys(
+    manager: Option<EventManager>,
+    kill_keys: &HashSet<Key>,
+    focus_keys: &HashSet<Key>,
+    focus_requests: UnboundedSender<()>,
+) -> Result<(), Error> {
+    let mut manager = match manager {
+        Some(manager) => manager,
+        None => return future::pending().await,
+    };
+
+    let mut pressed = HashSet::new();
+    loop {
+        let event = Manager::read(&mut manager).await?;
+        Manager::write(&mut manager, event).await?;
+
+        let key = match event {
+            Event::Key { direction: Direction::Down, kind: KeyKind::Key(key) } => key,
+            Event::Key { direction: Direction::Up, kind: KeyKind::Key(key) } => {
+                pressed.remove(&key);
+                continue;
+            }
+            _ => continue,
+        };
+
+        // Only the key completing a chord triggers it, not every key pressed while it's held.
+        pressed.insert(key);
+        let completes = |keys: &HashSet<Key>| keys.contains(&key) && keys.is_subset(&pressed);
+        if completes(kill_keys) {
+            return Ok(());
+        }
+        if completes(focus_keys) {
+            let _ = focus_requests.send(());
+        }
+    }
+}
+
+// A failed write is retried with a new virtual device. If that fails too the event is dropped,
+// staying connected keeps the clipboard working and lets the device recover later.
+// Whatever isn't switched further down by the relay comes back as Local::Event.
+async fn deliver(
+    relay: &Option<Relay>,
+    writer: &mut EventWriter,
+    held: &mut HashSet<KeyKind>,
+    event: Event,
+    headless: bool,
+) {
+    if let Event::Key { direction, kind } = event {
+        match direction {
+            Direction::Down => held.insert(kind),
+            Direction::Up => held.remove(&kind),
+        };
+    }
+
+    match relay {
+        Some(relay) => {
+            let _ = relay.events.send(event);
+        }
+        None => write_event(writer, event, headless).await,
+    }
+}
+
+// Reads messages from the server and hands them over, switching encodings as the server announces it.
+async fn read_messages<R>(
+    mut reader: R,
+    sender: UnboundedSender<Message>,
+    timeout: Duration,
+    max_size: u32,
+) -> Result<Infallible, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut encoding = Encoding::Protobuf;
+    loop {
+        let message = time::timeout(timeout, net::read_message(&mut reader, max_size, encoding))
+            .await
+            .context("Read timed out")??;
+        if let Message::SetEncoding(new) = message {
+            encoding = new;
+        }
+
+        // The receiver lives as long as the connection.
+        let _ = sender.send(message);
+    }
+}
+
+// Keeps motion received over UDP in order with the events received over the connection, given the number of the last
+// one delivered. Motion sent before an event which has already been delivered is dropped, motion sent after one which
+// hasn't been waits for it.
+async fn deliver_motion(
+    relay: &Option<Relay>,
+    writer: &mut EventWriter,
+    held: &mut HashSet<KeyKind>,
+    early: &mut VecDeque<Motion>,
+    arrived: Option<Motion>,
+    sequence: Option<u64>,
+    headless: bool,
+) {
+    let delivered = sequence.unwrap_or(0);
+    if let Some(motion) = arrived.filter(|motion| motion.after >= delivered) {
+        if early.len() == MAX_EARLY_MOTION {
+            early.pop_front();
+        }
+        early.push_back(motion);
+    }
+
+    while let Some(motion) = early.front().filter(|motion| motion.after <= delivered).copied() {
+        early.pop_front();
+        deliver(relay, writer, held, motion.event, headless).await;
+    }
+}
+
+async fn write_event(writer: &mut EventWriter, event: Event, headless: bool) {
+    let e = match writer.write(event).await {
+        Ok(()) => return,
+        Err(e) => e,
+    };
+
+    log::warn!("Failed to write event: {}.  Recreating the virtual device", e);
+    let result = match Writer::recreate(writer).await {
+        Ok(()) => writer.write(event).await,
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        log::error!("Dropping event: {}", e);
+        if !headless {
+            writer.notify(format!("Input is failing: {}", e));
+        }
+    }
+}
+
+// Resolves on SIGTERM, which systemd stops the service with, or SIGINT.
+#[cfg(unix)]
+async fn terminated() -> Result<(), Error> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = interrupt.recv() => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn terminated() -> Result<(), Error> {
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+#[cfg(unix)]
+// SIGUSR1 toggles debug logging, so that the event flow can be inspected without a restart.
+fn toggle_debug_on_signal(level: LevelFilter) {
+    tokio::spawn(async move {
+        let mut signal = match signal(SignalKind::user_defined1()) {
+            Ok(signal) => signal,
+            Err(err) => {
+                log::warn!("Failed to set up SIGUSR1 handler: {}", err);
+                return;
+            }
+        };
+
+        while signal.recv().await.is_some() {
+            let new_level = if log::max_level() < LevelFilter::Debug {
+                LevelFilter::Debug
+            } else {
+                level
+            };
+
+            log::set_max_level(new_level);
+            log::info!("Log level set to {}", new_level);
+        }
+    });
+}
+
 #[derive(StructOpt)]
 #[structopt(name = "rkvm-client", about = "The rkvm client application")]
 struct Args {
@@ -91,51 +617,101 @@ struct Args {
         target_os = "linux",
         structopt(default_value = "/etc/rkvm/client.toml")
     )]
+    #[cfg_attr(
+        target_os = "freebsd",
+        structopt(default_value = "/usr/local/etc/rkvm/client.toml")
+    )]
     #[cfg_attr(
         target_os = "windows",
         structopt(default_value = "C:/rkvm/client.toml")
     )]
     config_path: PathBuf,
+    #[structopt(long, help = "Disable clipboard sync and notifications")]
+    headless: bool,
+    #[structopt(long, help = "Print a default config with every option explained and exit")]
+    print_default_config: bool,
+    #[structopt(long, help = "Check the config, access to the input devices and the connection to the server, then exit")]
+    check: bool,
+    #[cfg(windows)]
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[cfg(windows)]
+#[derive(StructOpt)]
+enum Command {
+    #[structopt(about = "Manage the Windows service")]
+    Service(service::ServiceCommand),
+}
+
+async fn read_config(path: &Path) -> Result<Config, Error> {
+    let config = fs::read_to_string(path).await.context("Error loading config")?;
+    let mut config = config::parse(path, &config).context("Error parsing config")?;
+    config::expand_env_vars(&mut config).context("Error parsing config")?;
+    config.try_into().context("Error parsing config")
+}
+
+#[cfg(windows)]
+fn run_command(command: &Command, config_path: &Path) -> Result<(), Error> {
+    match command {
+        Command::Service(service::ServiceCommand::Install) => service::install(config_path),
+        Command::Service(service::ServiceCommand::Uninstall) => service::uninstall(),
+        // The dispatcher blocks until the service stops and runs the client on a runtime of its own.
+        Command::Service(service::ServiceCommand::Run) => tokio::task::block_in_place(service::run),
+    }
 }
 
 #[tokio::main]
 async fn main() {
+    #[cfg(feature = "console")]
+    console_subscriber::init();
+
     env_logger::builder()
         .format_timestamp(None)
-        .filter(None, LevelFilter::Info)
+        .filter(None, LevelFilter::Trace)
         .init();
+    // The actual level is controlled through the global max level, so that it can be changed at runtime.
+    log::set_max_level(LevelFilter::Info);
 
     let args = Args::from_args();
-    let config = match fs::read_to_string(&args.config_path).await {
-        Ok(config) => config,
-        Err(err) => {
-            log::error!("Error loading config: {}", err);
-            process::exit(1);
+    if args.print_default_config {
+        print!("{}", DEFAULT_CONFIG);
+        return;
+    }
+
+    #[cfg(windows)]
+    {
+        if let Some(command) = &args.command {
+            if let Err(err) = run_command(command, &args.config_path) {
+                log::error!("Error: {:#}", err);
+                process::exit(1);
+            }
+
+            return;
         }
-    };
+    }
 
-    let config: Config = match toml::from_str(&config) {
+    let mut config = match read_config(&args.config_path).await {
         Ok(config) => config,
         Err(err) => {
-            log::error!("Error parsing config: {}", err);
+            log::error!("{:#}", err);
             process::exit(1);
         }
     };
+    config.headless |= args.headless;
 
-    tokio::select! {
-        result = run(&config.server.hostname, config.server.port, &config.certificate_path) => {
-            if let Err(err) = result {
-                log::error!("Error: {:#}", err);
-                process::exit(1);
-            }
-        }
-        result = tokio::signal::ctrl_c() => {
-            if let Err(err) = result {
-                log::error!("Error setting up signal handler: {}", err);
-                process::exit(1);
-            }
+    if args.check {
+        let passed = check::run(&config).await;
+        process::exit(if passed { 0 } else { 1 });
+    }
 
-            log::info!("Exiting on signal");
-        }
+    let log_level = config.log_level.unwrap_or(LevelFilter::Info);
+    log::set_max_level(log_level);
+    #[cfg(unix)]
+    toggle_debug_on_signal(log_level);
+
+    if let Err(err) = run(&config).await {
+        log::error!("Error: {:#}", err);
+        process::exit(1);
     }
 }
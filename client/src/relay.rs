@@ -0,0 +1,218 @@
+use std::convert::Infallible;
+use std::future::{self, Future};
+use std::io;
+use std::time::Duration;
+
+use anyhow::{Context, Error};
+use async_trait::async_trait;
+use tokio::fs;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time;
+use tokio_native_tls::native_tls::{Identity, TlsAcceptor};
+
+use input::{Event, Manager};
+use net::sealed::Side;
+use net::{Encoding, Message, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use rkvm_core::{Action, ConnectionSettings, Handle, Kvm};
+
+use crate::config::{Config, Relay as RelayConfig, Tls, TlsVersion};
+
+// What the relay leaves to this machine, handled by the client with its own virtual device.
+pub enum Local {
+    Event(Event),
+    Notify(String),
+}
+
+// The ends of the relay the client talks to: events received from the server go in, the ones staying here come out.
+pub struct Relay {
+    pub events: UnboundedSender<Event>,
+    pub local: UnboundedReceiver<Local>,
+}
+
+// The input of the relay, the events received from the server as if they came from local devices.
+struct RelayManager {
+    upstream: UnboundedReceiver<Event>,
+    local: UnboundedSender<Local>,
+}
+
+#[async_trait]
+impl Manager for RelayManager {
+    async fn read(&mut self) -> Result<Event, io::Error> {
+        self.upstream
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::other("Disconnected from the server"))
+    }
+
+    async fn write(&mut self, event: Event) -> Result<(), io::Error> {
+        self.local
+            .send(Local::Event(event))
+            .map_err(|_| io::Error::other("Client stopped"))
+    }
+
+    fn notify(&mut self, message: String) {
+        let _ = self.local.send(Local::Notify(message));
+    }
+
+    fn set_indicator_led(&mut self, _on: bool) {}
+}
+
+// Starts relaying to machines further down if configured, so that they can be reached through this one. The relay is
+// a server of its own with the switch keys cycling through this machine and its clients. The returned future runs it
+// and never completes without a relay.
+pub async fn start(
+    config: &Config,
+    name: &str,
+) -> Result<(Option<Relay>, impl Future<Output = Result<Infallible, Error>>), Error> {
+    let relay = match &config.relay {
+        Some(relay) => relay,
+        None => return Ok((None, serve(None, net::tcp::Options::default(), false, None))),
+    };
+
+    // The relay's clients are held to the same TLS settings as the connection to the server.
+    let acceptor = acceptor(relay, &config.tls).await?;
+    let listener = TcpListener::bind(relay.listen_address)
+        .await
+        .context("Failed to bind the relay")?;
+    log::info!("Relaying on {}", relay.listen_address);
+
+    let (events, upstream) = mpsc::unbounded_channel();
+    let (local_sender, local) = mpsc::unbounded_channel();
+    let manager = RelayManager {
+        upstream,
+        local: local_sender,
+    };
+
+    let settings = ConnectionSettings {
+        max_message_size: config.max_message_size,
+        timeout: Duration::from_millis(config.message_timeout_ms),
+        ..ConnectionSettings::default()
+    };
+    let (kvm, handle) = Kvm::builder(manager, name.to_owned())
+        .hotkey(Action::Switch, &relay.switch_keys)
+        .sanitize_clipboard(config.sanitize_clipboard)
+        .connection_settings(settings)
+        .build();
+
+    let relay = Some((kvm, handle, listener, acceptor, settings));
+    Ok((
+        Some(Relay { events, local }),
+        serve(relay, config.tcp.clone(), !config.tls.alpn.is_empty(), config.message_key.clone()),
+    ))
+}
+
+async fn serve(
+    relay: Option<(Kvm<RelayManager>, Handle, TcpListener, tokio_native_tls::TlsAcceptor, ConnectionSettings)>,
+    tcp: net::tcp::Options,
+    require_alpn: bool,
+    message_key: Option<String>,
+) -> Result<Infallible, Error> {
+    let (kvm, handle, listener, acceptor, settings) = match relay {
+        Some(relay) => relay,
+        None => return future::pending().await,
+    };
+
+    tokio::select! {
+        result = kvm.run() => result?,
+        result = accept(listener, acceptor, handle, settings, tcp, require_alpn, message_key) => return result,
+    }
+
+    Err(Error::msg("Relay stopped"))
+}
+
+async fn acceptor(relay: &RelayConfig, tls: &Tls) -> Result<tokio_native_tls::TlsAcceptor, Error> {
+    let identity = fs::read(&relay.identity_path)
+        .await
+        .context("Failed to read relay identity")?;
+    let identity = Identity::from_pkcs12(&identity, &relay.identity_password)
+        .context("Failed to parse relay identity")?;
+    let mut acceptor = TlsAcceptor::builder(identity);
+    acceptor
+        .min_protocol_version(tls.min_version.map(TlsVersion::protocol))
+        .max_protocol_version(tls.max_version.map(TlsVersion::protocol));
+    if !tls.alpn.is_empty() {
+        acceptor.accept_alpn(&tls.alpn);
+    }
+    acceptor
+        .build()
+        .context("Failed to create relay TLS acceptor")
+        .map(Into::into)
+}
+
+// The same handshake as the server's, clients can't tell a relay from a server. They share the message key with the
+// server too.
+#[tracing::instrument(name = "relay_accept", skip_all)]
+async fn accept(
+    listener: TcpListener,
+    acceptor: tokio_native_tls::TlsAcceptor,
+    handle: Handle,
+    settings: ConnectionSettings,
+    tcp: net::tcp::Options,
+    require_alpn: bool,
+    message_key: Option<String>,
+) -> Result<Infallible, Error> {
+    loop {
+        let (stream, address) = listener.accept().await?;
+        if let Err(err) = tcp.apply(&stream) {
+            log::warn!("Failed to set socket options for {}: {}", address, err);
+        }
+
+        let mut stream = match acceptor.accept(stream).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::warn!("Relay TLS handshake with {} failed: {}", address, err);
+                continue;
+            }
+        };
+
+        if require_alpn && !matches!(stream.get_ref().negotiated_alpn(), Ok(Some(_))) {
+            log::warn!("No ALPN protocol negotiated with {}", address);
+            continue;
+        }
+
+        if let Err(err) = net::write_version(&mut stream, PROTOCOL_VERSION).await {
+            log::warn!("Failed to write version to {}: {}", address, err);
+            continue;
+        }
+
+        let offered = match net::read_version(&mut stream).await {
+            Ok(offered) => match net::negotiate_version(offered) {
+                Some(_) => offered,
+                None => {
+                    log::warn!("Incompatible protocol version from {} (got {}, expecting at least {})", address, offered, MIN_PROTOCOL_VERSION);
+                    continue;
+                }
+            },
+            Err(err) => {
+                log::warn!("Failed to read version from {}: {}", address, err);
+                continue;
+            }
+        };
+
+        // Both under the message timeout, like on the server.
+        let key = message_key.as_deref().map(str::as_bytes);
+        let sealing = time::timeout(settings.timeout, net::sealed::seal(stream, key, Side::Server));
+        let mut stream = match sealing.await.unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())) {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::warn!("Failed to authenticate messages with {}: {}", address, err);
+                continue;
+            }
+        };
+
+        let confirming = time::timeout(settings.timeout, net::confirm_version(&mut stream, offered, PROTOCOL_VERSION));
+        if let Err(err) = confirming.await.unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())) {
+            log::warn!("Failed to confirm version with {}: {}", address, err);
+            continue;
+        }
+
+        match net::read_message(&mut stream, settings.max_message_size, Encoding::Protobuf).await {
+            Ok(Message::Hello(name)) => {
+                handle.add_client(name, address, stream);
+            }
+            Ok(message) => log::warn!("Expected hello from {}, got {:?}", address, message),
+            Err(err) => log::warn!("Failed to read name from {}: {}", address, err),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::env;
+use std::ffi::OsString;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Error};
+use structopt::StructOpt;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo,
+    ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle};
+use windows_service::service_dispatcher;
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+
+use crate::Args;
+
+const SERVICE_NAME: &str = "rkvm-client";
+
+#[derive(StructOpt)]
+pub enum ServiceCommand {
+    #[structopt(about = "Register the client as a service starting with Windows")]
+    Install,
+    #[structopt(about = "Remove the service")]
+    Uninstall,
+    #[structopt(about = "Run as a service, only meant to be started by the service control manager")]
+    Run,
+}
+
+pub fn install(config_path: &Path) -> Result<(), Error> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .context("Failed to connect to the service control manager")?;
+
+    // The config path is resolved now, the service doesn't start in the directory we're run from.
+    let config_path = config_path
+        .canonicalize()
+        .with_context(|| format!("Failed to find {}", config_path.display()))?;
+    let info = ServiceInfo {
+        name: SERVICE_NAME.into(),
+        display_name: "rkvm client".into(),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: env::current_exe().context("Failed to find the client executable")?,
+        launch_arguments: vec![config_path.into(), "service".into(), "run".into()],
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    };
+
+    manager
+        .create_service(&info, ServiceAccess::empty())
+        .context("Failed to create the service")?;
+    log::info!("Installed the {} service", SERVICE_NAME);
+    Ok(())
+}
+
+pub fn uninstall() -> Result<(), Error> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .context("Failed to connect to the service control manager")?;
+    let service = manager
+        .open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)
+        .context("Failed to open the service")?;
+
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop().context("Failed to stop the service")?;
+    }
+
+    service.delete().context("Failed to delete the service")?;
+    log::info!("Uninstalled the {} service", SERVICE_NAME);
+    Ok(())
+}
+
+// Blocks until the service is stopped.
+pub fn run() -> Result<(), Error> {
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+        .context("Failed to start the service dispatcher, the service can only be started by Windows")
+}
+
+windows_service::define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(err) = run_service() {
+        log::error!("Error: {:#}", err);
+    }
+}
+
+fn run_service() -> Result<(), Error> {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let handler = move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown | ServiceControl::Pause | ServiceControl::Continue => {
+            let _ = sender.send(control);
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let status = service_control_handler::register(SERVICE_NAME, handler)?;
+    set_state(&status, ServiceState::StartPending)?;
+
+    // The launch arguments registered by install are our regular command line.
+    let args = Args::from_args();
+    let runtime = Runtime::new()?;
+    let result = runtime.block_on(serve(&args, &status, &mut receiver));
+
+    set_state(&status, ServiceState::Stopped)?;
+    result
+}
+
+async fn serve(
+    args: &Args,
+    status: &ServiceStatusHandle,
+    receiver: &mut mpsc::UnboundedReceiver<ServiceControl>,
+) -> Result<(), Error> {
+    let mut config = crate::read_config(&args.config_path).await?;
+    config.headless |= args.headless;
+    set_state(status, ServiceState::Running)?;
+
+    // Pausing disconnects from the server, so that the machine can be used on its own for a while.
+    let mut paused = false;
+    loop {
+        if paused {
+            match receiver.recv().await {
+                Some(ServiceControl::Continue) => {
+                    log::info!("Continuing");
+                    paused = false;
+                    set_state(status, ServiceState::Running)?;
+                }
+                Some(ServiceControl::Pause) => {}
+                _ => return Ok(()),
+            }
+
+            continue;
+        }
+
+        tokio::select! {
+            result = crate::run(&config) => return result,
+            control = receiver.recv() => match control {
+                Some(ServiceControl::Pause) => {
+                    log::info!("Pausing");
+                    paused = true;
+                    set_state(status, ServiceState::Paused)?;
+                }
+                Some(ServiceControl::Continue) => {}
+                _ => return Ok(()),
+            },
+        }
+    }
+}
+
+fn set_state(status: &ServiceStatusHandle, state: ServiceState) -> Result<(), Error> {
+    let controls_accepted = match state {
+        ServiceState::Running | ServiceState::Paused => ServiceControlAccept::STOP
+            | ServiceControlAccept::SHUTDOWN
+            | ServiceControlAccept::PAUSE_CONTINUE,
+        _ => ServiceControlAccept::empty(),
+    };
+
+    status.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(0),
+        checkpoint: 0,
+        wait_hint: Duration::from_secs(if state == ServiceState::StartPending { 10 } else { 0 }),
+        process_id: None,
+    })?;
+    Ok(())
+}
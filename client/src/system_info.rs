@@ -0,0 +1,141 @@
+use net::SystemInfo;
+
+// What the server shows about this machine in rkvm-ctl list, anything which can't be read is left out.
+pub fn read() -> SystemInfo {
+    let (battery_percent, charging) = match battery() {
+        Some((percent, charging)) => (Some(percent), charging),
+        None => (None, None),
+    };
+
+    SystemInfo {
+        os: os(),
+        uptime_secs: uptime_secs(),
+        battery_percent,
+        charging,
+    }
+}
+
+// A UUID derived from the machine ID, which is hashed rather than sent as is since it's meant to stay private.
+#[cfg(unix)]
+pub fn machine_id() -> Option<String> {
+    let path = if cfg!(target_os = "freebsd") { "/etc/hostid" } else { "/etc/machine-id" };
+    let machine_id = std::fs::read_to_string(path).ok()?;
+    let machine_id = machine_id.trim();
+    if machine_id.is_empty() {
+        return None;
+    }
+
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(&openssl::sha::sha256(format!("rkvm:{}", machine_id).as_bytes())[..16]);
+    // Version 4 and the RFC 4122 variant, as if it was random.
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Some(format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..]))
+}
+
+#[cfg(windows)]
+pub fn machine_id() -> Option<String> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn os() -> String {
+    std::fs::read_to_string("/etc/os-release")
+        .ok()
+        .and_then(|release| {
+            release
+                .lines()
+                .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+                .map(|name| name.trim_matches('"').to_owned())
+        })
+        .unwrap_or_else(|| "Linux".to_owned())
+}
+
+#[cfg(target_os = "freebsd")]
+fn os() -> String {
+    "FreeBSD".to_owned()
+}
+
+#[cfg(target_os = "windows")]
+fn os() -> String {
+    "Windows".to_owned()
+}
+
+#[cfg(target_os = "linux")]
+fn uptime_secs() -> Option<u64> {
+    let uptime = std::fs::read_to_string("/proc/uptime").ok()?;
+    let seconds = uptime.split_whitespace().next()?.parse::<f64>().ok()?;
+    Some(seconds as u64)
+}
+
+#[cfg(target_os = "freebsd")]
+fn uptime_secs() -> Option<u64> {
+    None
+}
+
+#[cfg(target_os = "windows")]
+fn uptime_secs() -> Option<u64> {
+    Some(unsafe { winapi::um::sysinfoapi::GetTickCount64() } / 1000)
+}
+
+// The first battery found, with whether it's charging if that's known.
+#[cfg(target_os = "linux")]
+fn battery() -> Option<(u8, Option<bool>)> {
+    use std::fs;
+
+    for entry in fs::read_dir("/sys/class/power_supply").ok()?.flatten() {
+        let path = entry.path();
+        let kind = fs::read_to_string(path.join("type")).unwrap_or_default();
+        if kind.trim() != "Battery" {
+            continue;
+        }
+
+        let percent = match fs::read_to_string(path.join("capacity")).ok().and_then(|capacity| capacity.trim().parse().ok()) {
+            Some(percent) => percent,
+            None => continue,
+        };
+        let charging = match fs::read_to_string(path.join("status")).unwrap_or_default().trim() {
+            "Charging" | "Full" => Some(true),
+            "Discharging" => Some(false),
+            _ => None,
+        };
+
+        return Some((percent, charging));
+    }
+
+    None
+}
+
+#[cfg(target_os = "freebsd")]
+fn battery() -> Option<(u8, Option<bool>)> {
+    None
+}
+
+#[cfg(target_os = "windows")]
+fn battery() -> Option<(u8, Option<bool>)> {
+    use std::mem::MaybeUninit;
+    use winapi::um::winbase::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+    let mut status = MaybeUninit::<SYSTEM_POWER_STATUS>::uninit();
+    let status = unsafe {
+        if GetSystemPowerStatus(status.as_mut_ptr()) == 0 {
+            return None;
+        }
+
+        status.assume_init()
+    };
+
+    // 128 is no battery, 255 unknown.
+    if status.BatteryFlag & 128 != 0 || status.BatteryLifePercent == 255 {
+        return None;
+    }
+
+    let charging = match status.ACLineStatus {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None,
+    };
+    Some((status.BatteryLifePercent, charging))
+}
@@ -0,0 +1,86 @@
+use input::{Axis, Event};
+
+// Speeds up fast pointer motion going to clients, which often apply no acceleration of their own to motion coming
+// from a virtual device, so that it feels like on the server. Deltas up to the threshold pass unchanged, the part
+// above it is multiplied by the gain. The fractions lost to rounding are carried over per axis.
+pub(crate) struct Acceleration {
+    threshold: i32,
+    gain: f64,
+    x: f64,
+    y: f64,
+}
+
+impl Acceleration {
+    pub fn new(threshold: i32, gain: f64) -> Self {
+        Self {
+            threshold,
+            gain,
+            x: 0.0,
+            y: 0.0,
+        }
+    }
+
+    pub fn apply(&mut self, event: Event) -> Event {
+        let (axis, delta) = match event {
+            Event::MouseMove { axis, delta } => (axis, delta),
+            event => return event,
+        };
+
+        let excess = delta.abs() - self.threshold;
+        if excess <= 0 {
+            return event;
+        }
+
+        let remainder = match axis {
+            Axis::X => &mut self.x,
+            Axis::Y => &mut self.y,
+        };
+
+        let accelerated = f64::from(delta.signum()) * (f64::from(self.threshold) + f64::from(excess) * self.gain);
+        let total = accelerated + *remainder;
+        let delta = total.trunc();
+        *remainder = total - delta;
+
+        Event::MouseMove {
+            axis,
+            delta: delta as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delta(event: Event) -> i32 {
+        match event {
+            Event::MouseMove { delta, .. } => delta,
+            _ => panic!("Not motion"),
+        }
+    }
+
+    fn motion(delta: i32) -> Event {
+        Event::MouseMove { axis: Axis::X, delta }
+    }
+
+    #[test]
+    fn slow_motion_is_unchanged() {
+        let mut acceleration = Acceleration::new(4, 2.0);
+        assert_eq!(delta(acceleration.apply(motion(3))), 3);
+        assert_eq!(delta(acceleration.apply(motion(-4))), -4);
+    }
+
+    #[test]
+    fn fast_motion_is_accelerated() {
+        let mut acceleration = Acceleration::new(4, 2.0);
+        assert_eq!(delta(acceleration.apply(motion(10))), 16);
+        assert_eq!(delta(acceleration.apply(motion(-10))), -16);
+    }
+
+    #[test]
+    fn fractions_are_carried_over() {
+        let mut acceleration = Acceleration::new(0, 1.5);
+        assert_eq!(delta(acceleration.apply(motion(1))), 1);
+        assert_eq!(delta(acceleration.apply(motion(1))), 2);
+    }
+}
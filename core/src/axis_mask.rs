@@ -0,0 +1,27 @@
+use input::{Axis, Event};
+use std::collections::HashSet;
+
+// The relative axes which can be kept from a client, e.g. the wheel for one whose applications misbehave when
+// scrolling.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum RelativeAxis {
+    X,
+    Y,
+    Wheel,
+}
+
+impl RelativeAxis {
+    pub fn of(event: &Event) -> Option<Self> {
+        match event {
+            Event::MouseMove { axis: Axis::X, .. } => Some(Self::X),
+            Event::MouseMove { axis: Axis::Y, .. } => Some(Self::Y),
+            Event::MouseScroll { .. } => Some(Self::Wheel),
+            Event::Key { .. } | Event::Scan { .. } | Event::Switch { .. } => None,
+        }
+    }
+}
+
+// Whether the event moves one of the masked axes and has to be dropped.
+pub(crate) fn is_masked(mask: &HashSet<RelativeAxis>, event: &Event) -> bool {
+    RelativeAxis::of(event).map(|axis| mask.contains(&axis)).unwrap_or(false)
+}
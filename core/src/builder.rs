@@ -0,0 +1,301 @@
+use crate::acceleration::Acceleration;
+use crate::axis_mask::RelativeAxis;
+use crate::connection::ConnectionSettings;
+use crate::duplicate_names::DuplicateNames;
+use crate::handle::Handle;
+use crate::hotkey::{Action, Hotkeys};
+use crate::kvm::Kvm;
+use crate::mouse_keys::MouseKeys;
+use crate::path_translation::PathTranslation;
+use crate::switch_policy::SwitchPolicy;
+use crate::policy::Policy;
+use crate::sharing::Sharing;
+use crate::state::State;
+use input::osd::OsdCommand;
+use input::pointer::Corner;
+use input::{Key, Manager};
+use net::wol::MacAddress;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, AtomicUsize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+
+pub struct KvmBuilder<M> {
+    manager: M,
+    hostname: String,
+    hotkeys: Hotkeys,
+    mouse_keys: Option<MouseKeys>,
+    focus_led: bool,
+    osd_command: Vec<String>,
+    park_corner: Option<Corner>,
+    sync_activity: bool,
+    idle_timeout: Option<Duration>,
+    policies: Vec<Box<dyn Policy>>,
+    sharing: Sharing,
+    duplicate_names: DuplicateNames,
+    switch_policy: SwitchPolicy,
+    local_keys: HashSet<Key>,
+    axis_masks: HashMap<String, HashSet<RelativeAxis>>,
+    key_substitutions: HashMap<String, HashMap<Key, Key>>,
+    acceleration: Option<Acceleration>,
+    display_scale: f64,
+    sanitize_clipboard: bool,
+    path_translations: Vec<PathTranslation>,
+    groups: BTreeMap<String, HashSet<String>>,
+    focus_commands: HashMap<String, String>,
+    wake_addresses: HashMap<String, MacAddress>,
+    settings: ConnectionSettings,
+}
+
+impl<M: Manager> KvmBuilder<M> {
+    // The hostname is the name the server itself is addressed by.
+    pub fn new(manager: M, hostname: String) -> Self {
+        Self {
+            manager,
+            hostname,
+            hotkeys: Hotkeys::new(),
+            mouse_keys: None,
+            focus_led: false,
+            osd_command: Vec::new(),
+            park_corner: None,
+            sync_activity: false,
+            idle_timeout: None,
+            policies: Vec::new(),
+            sharing: Sharing::default(),
+            duplicate_names: DuplicateNames::default(),
+            switch_policy: SwitchPolicy::default(),
+            local_keys: HashSet::new(),
+            axis_masks: HashMap::new(),
+            key_substitutions: HashMap::new(),
+            acceleration: None,
+            display_scale: 1.0,
+            sanitize_clipboard: false,
+            path_translations: Vec::new(),
+            groups: BTreeMap::new(),
+            focus_commands: HashMap::new(),
+            wake_addresses: HashMap::new(),
+            settings: ConnectionSettings::default(),
+        }
+    }
+
+    // Binds an action to a set of keys which have to be held at the same time, an empty set leaves it unbound.
+    pub fn hotkey(mut self, action: Action, keys: &HashSet<Key>) -> Self {
+        self.hotkeys.add(action, keys);
+        self
+    }
+
+    // Ignores an action triggered again within the cooldown, see Hotkeys.
+    pub fn hotkey_cooldown(mut self, action: Action, cooldown: Duration) -> Self {
+        self.hotkeys.set_cooldown(action, cooldown);
+        self
+    }
+
+    // Maps the numpad to pointer motion, moving the pointer by speed every step.
+    pub fn mouse_keys(mut self, speed: i32) -> Self {
+        self.mouse_keys = Some(MouseKeys::new(speed));
+        self
+    }
+
+    // Lights the Scroll Lock LED while input goes to a client.
+    pub fn focus_led(mut self, enabled: bool) -> Self {
+        self.focus_led = enabled;
+        self
+    }
+
+    // Run while the server has focus, see OsdCommand.
+    pub fn osd_command(mut self, command: Vec<String>) -> Self {
+        self.osd_command = command;
+        self
+    }
+
+    // Moves the local pointer into a corner whenever focus moves from the server to a client.
+    pub fn park_pointer(mut self, corner: Corner) -> Self {
+        self.park_corner = Some(corner);
+        self
+    }
+
+    // Keeps the machines without focus from going idle while the user is active on another one.
+    pub fn sync_activity(mut self, enabled: bool) -> Self {
+        self.sync_activity = enabled;
+        self
+    }
+
+    // Disconnects clients which haven't had focus or sent anything for this long.
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    // Policies are consulted in the order they were added, the first one to object wins.
+    pub fn policy<P: Policy + 'static>(mut self, policy: P) -> Self {
+        self.policies.push(Box::new(policy));
+        self
+    }
+
+    // Restricts what's forwarded to clients to the keyboard or the mouse, see Sharing.
+    pub fn sharing(mut self, sharing: Sharing) -> Self {
+        self.sharing = sharing;
+        self
+    }
+
+    // What to do with a client connecting under the name of one already connected, see DuplicateNames.
+    pub fn duplicate_names(mut self, duplicate_names: DuplicateNames) -> Self {
+        self.duplicate_names = duplicate_names;
+        self
+    }
+
+    // What to do with the lid and tablet mode switches of the grabbed devices, see SwitchPolicy.
+    pub fn switch_policy(mut self, switch_policy: SwitchPolicy) -> Self {
+        self.switch_policy = switch_policy;
+        self
+    }
+
+    // Keys which are never forwarded, e.g. the power key or one the local window manager is driven with.
+    pub fn local_keys(mut self, keys: &HashSet<Key>) -> Self {
+        self.local_keys.extend(keys);
+        self
+    }
+
+    // Drops motion along the given axes instead of forwarding it to the client with the given name.
+    pub fn axis_mask(mut self, client: String, axes: HashSet<RelativeAxis>) -> Self {
+        self.axis_masks.insert(client, axes);
+        self
+    }
+
+    // Sends the client with the given name another key in place of one, e.g. for an application there which only
+    // listens to some keys.
+    pub fn key_substitution(mut self, client: String, from: Key, to: Key) -> Self {
+        self.key_substitutions.entry(client).or_default().insert(from, to);
+        self
+    }
+
+    // Accelerates pointer motion going to clients, multiplying the part of a delta above the threshold by the gain.
+    pub fn acceleration(mut self, threshold: i32, gain: f64) -> Self {
+        self.acceleration = Some(Acceleration::new(threshold, gain));
+        self
+    }
+
+    // The server's own desktop scale, motion going to clients reporting theirs is scaled by the ratio of the two.
+    pub fn display_scale(mut self, scale: f64) -> Self {
+        self.display_scale = scale;
+        self
+    }
+
+    // Sanitizes text put into the server's clipboard, see input::clipboard::sanitize.
+    pub fn sanitize_clipboard(mut self, enabled: bool) -> Self {
+        self.sanitize_clipboard = enabled;
+        self
+    }
+
+    // Rewrites paths in clipboard text moving between machines, applied in the order they were added.
+    pub fn path_translation(mut self, translation: PathTranslation) -> Self {
+        self.path_translations.push(translation);
+        self
+    }
+
+    // A named set of clients, which can be made the only ones taking part in cycling, see Handle::set_profile.
+    pub fn group(mut self, name: String, clients: HashSet<String>) -> Self {
+        self.groups.insert(name, clients);
+        self
+    }
+
+    // Asks the client with the given name to run one of the commands it allows whenever it gets focus.
+    pub fn focus_command(mut self, client: String, command: String) -> Self {
+        self.focus_commands.insert(client, command);
+        self
+    }
+
+    // Lets the client with the given name be woken up with Wake-on-LAN, see Handle::wake.
+    pub fn wake_on_lan(mut self, client: String, address: MacAddress) -> Self {
+        self.wake_addresses.insert(client, address);
+        self
+    }
+
+    pub fn connection_settings(mut self, settings: ConnectionSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn build(self) -> (Kvm<M>, Handle) {
+        let (command_sender, commands) = mpsc::unbounded_channel();
+        let (client_sender, clients) = mpsc::unbounded_channel();
+        let (message_sender, messages) = mpsc::unbounded_channel();
+        let (disconnect_sender, disconnects) = mpsc::unbounded_channel();
+        let (transfer_sender, transfers) = mpsc::unbounded_channel();
+        let (action_sender, actions) = mpsc::unbounded_channel();
+        let (notifications, _) = broadcast::channel(16);
+
+        let handle = Handle {
+            commands: command_sender,
+            clients: client_sender,
+            messages: message_sender,
+            disconnects: disconnect_sender,
+            transfers: transfer_sender,
+            notifications: notifications.clone(),
+            next_id: Arc::new(AtomicU64::new(1)),
+            connections: Arc::new(AtomicUsize::new(0)),
+            settings: self.settings,
+        };
+
+        let state = State {
+            hostname: self.hostname,
+            manager: self.manager,
+            osd: OsdCommand::new(self.osd_command),
+            clients: BTreeMap::new(),
+            focus: None,
+            history: VecDeque::new(),
+            paused: false,
+            clipboard_paused: false,
+            notifications,
+            hotkeys: self.hotkeys,
+            mouse_keys: self.mouse_keys,
+            focus_led: self.focus_led,
+            park_corner: self.park_corner,
+            sync_activity: self.sync_activity,
+            key_states: HashMap::new(),
+            last_activity: None,
+            local_failing: false,
+            policies: self.policies,
+            access_override: false,
+            sharing: self.sharing,
+            duplicate_names: self.duplicate_names,
+            switch_policy: self.switch_policy,
+            pending_scan: None,
+            local_keys: self.local_keys,
+            axis_masks: self.axis_masks,
+            key_substitutions: self.key_substitutions,
+            acceleration: self.acceleration,
+            display_scale: self.display_scale,
+            scales: HashMap::new(),
+            sanitize_clipboard: self.sanitize_clipboard,
+            path_translations: self.path_translations,
+            clipboard_owner: None,
+            actions: action_sender,
+            groups: self.groups,
+            profile: None,
+            focus_commands: self.focus_commands,
+            wake_addresses: self.wake_addresses,
+            suspended: None,
+            system_infos: HashMap::new(),
+            handshakes: HashMap::new(),
+            idle_timeout: self.idle_timeout,
+            last_used: HashMap::new(),
+            capabilities: HashMap::new(),
+            unsupported: HashSet::new(),
+            forwarded: HashMap::new(),
+        };
+
+        let kvm = Kvm {
+            state,
+            commands,
+            clients,
+            messages,
+            disconnects,
+            transfers,
+            actions,
+        };
+
+        (kvm, handle)
+    }
+}
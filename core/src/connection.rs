@@ -0,0 +1,416 @@
+use crate::state::ClientId;
+use input::{Axis, Event};
+use net::clipboard::Reassembler;
+use net::motion::Sealer;
+use net::{self, Encoding, Message};
+use std::collections::VecDeque;
+use std::future;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{self, AsyncRead, AsyncWrite};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time;
+
+// Settings shared by all client connections.
+#[derive(Clone, Copy, Debug)]
+pub struct ConnectionSettings {
+    // The largest message accepted from a client.
+    pub max_message_size: u32,
+    // How often clients are pinged, which also keeps idle connections alive.
+    pub heartbeat_interval: Duration,
+    // How long to wait for a message before giving up on a client.
+    pub timeout: Duration,
+    // Relative motion waiting longer than this is dropped while more than stale_motion_queue messages are queued, so
+    // that the pointer doesn't replay old movement after a stall. Everything else is always sent.
+    pub stale_motion_age: Option<Duration>,
+    pub stale_motion_queue: usize,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        Self {
+            max_message_size: net::DEFAULT_MAX_MESSAGE_SIZE,
+            heartbeat_interval: net::HEARTBEAT_INTERVAL,
+            timeout: net::MESSAGE_TIMEOUT,
+            stale_motion_age: None,
+            stale_motion_queue: 32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Timing {
+    // Round trip time of the last ping.
+    pub rtt: Option<Duration>,
+    // Smoothed difference between the client's and the server's clock in microseconds, positive if the client is ahead.
+    pub clock_offset: Option<i64>,
+}
+
+impl Timing {
+    // The server's clock at the time the client answered is estimated as the midpoint of the round trip.
+    fn update(&mut self, rtt: Duration, client_time: u64) {
+        self.rtt = Some(rtt);
+
+        let server_time = unix_micros() - rtt.as_micros() as i64 / 2;
+        let sample = client_time as i64 - server_time;
+        self.clock_offset = Some(match self.clock_offset {
+            Some(offset) => offset + (sample - offset) / 8,
+            None => sample,
+        });
+    }
+}
+
+// Progress of clipboard content spanning several chunks, reported by the connection tasks.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Transfer {
+    Sent { done: u64, total: u64 },
+    Received { done: u64, total: u64 },
+    Cancelled,
+}
+
+fn unix_micros() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_micros() as i64)
+        .unwrap_or(0)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn handle_connection<T>(
+    id: ClientId,
+    address: SocketAddr,
+    stream: T,
+    receiver: UnboundedReceiver<Message>,
+    sender: UnboundedSender<(ClientId, Message)>,
+    transfers: UnboundedSender<(ClientId, Transfer)>,
+    timing: Arc<Mutex<Timing>>,
+    settings: ConnectionSettings,
+) -> Result<(), Error>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    // Reading and writing are driven separately, so that a partially read message is never dropped
+    // when an outgoing one arrives.
+    let (reader, writer) = io::split(stream);
+    let start = Instant::now();
+    // Encoding switches requested by the client, which the writer acknowledges.
+    let (encoding_sender, encoding_receiver) = mpsc::unbounded_channel();
+    // Clipboard content coming from the client, which the writer discards when a transfer is cancelled.
+    let incoming = Arc::new(Mutex::new(Reassembler::default()));
+    // Where the client wants motion sent over UDP, if it does.
+    let motion_target = Arc::new(Mutex::new(MotionPath::default()));
+    let key = net::motion::key()?;
+    let motion = Motion {
+        sealer: Sealer::new(key.clone()),
+        key,
+        target: motion_target.clone(),
+        socket: None,
+        unsent: None,
+        probed: None,
+    };
+    // Outgoing messages are stamped as they're queued rather than when the writer gets to them, which can be much later.
+    let (stamped, stamped_receiver) = mpsc::unbounded_channel();
+    let length = Arc::new(AtomicUsize::new(0));
+    let queue = Queue {
+        receiver: stamped_receiver,
+        length: length.clone(),
+    };
+
+    tokio::select! {
+        result = stamp_messages(receiver, stamped, length) => result,
+        result = write_messages(id, writer, queue, encoding_receiver, incoming.clone(), transfers.clone(), motion, start, settings) => result,
+        result = read_messages(id, address, reader, sender, encoding_sender, incoming, transfers, motion_target, timing, start, settings) => result,
+    }
+}
+
+// Where the client wants motion sent over UDP, and when it last acknowledged receiving some, which the reader updates.
+#[derive(Default)]
+struct MotionPath {
+    target: Option<SocketAddr>,
+    acknowledged: Option<Instant>,
+}
+
+// Sends pointer motion over UDP once the client has asked for it, see net::motion.
+struct Motion {
+    key: Vec<u8>,
+    target: Arc<Mutex<MotionPath>>,
+    socket: Option<(UdpSocket, SocketAddr)>,
+    // Outlives the socket, the client would take the numbers starting over for replays.
+    sealer: Sealer,
+    // The sequence number of the last motion sent over UDP since the last MotionSent.
+    unsent: Option<u64>,
+    // When a datagram was last sent to find out whether they get through.
+    probed: Option<Instant>,
+}
+
+impl Motion {
+    // Returns false if the motion has to go over the connection instead. after is the number of the last event sent
+    // over the connection.
+    async fn send(&mut self, after: u64, axis: Axis, delta: i32) -> bool {
+        let (target, working) = match &*self.target.lock().unwrap() {
+            MotionPath {
+                target: Some(target),
+                acknowledged,
+            } => (*target, acknowledged.map(|at| at.elapsed() < net::motion::ACK_TIMEOUT).unwrap_or(false)),
+            _ => return false,
+        };
+
+        if self.socket.as_ref().map(|(_, current)| *current != target).unwrap_or(true) {
+            let local: SocketAddr = match target {
+                SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+                SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+            };
+            match UdpSocket::bind(local).await {
+                Ok(socket) => self.socket = Some((socket, target)),
+                Err(err) => {
+                    log::warn!("Failed to bind the motion socket: {}", err);
+                    self.target.lock().unwrap().target = None;
+                    return false;
+                }
+            }
+        }
+
+        // Until the client acknowledges datagrams, or once it stops to, e.g. because a firewall drops them, motion goes
+        // over the connection. Now and then motion which doesn't move the pointer goes over UDP to try again.
+        if !working {
+            if self.probed.map(|at| at.elapsed() >= net::motion::ACK_INTERVAL).unwrap_or(true) {
+                self.probed = Some(Instant::now());
+                if let Err(err) = self.seal(after, axis, 0).await {
+                    log::debug!("Failed to send motion over UDP: {}", err);
+                }
+            }
+            return false;
+        }
+
+        if let Err(err) = self.seal(after, axis, delta).await {
+            log::debug!("Failed to send motion over UDP: {}", err);
+            return false;
+        }
+
+        self.unsent = Some(self.sealer.sequence());
+        true
+    }
+
+    async fn seal(&mut self, after: u64, axis: Axis, delta: i32) -> Result<(), Error> {
+        let (socket, target) = self.socket.as_mut().unwrap();
+        let packet = self.sealer.seal(after, axis, delta)?;
+        socket.send_to(&packet, *target).await.map(|_| ())
+    }
+
+    // What to send ahead of an event over the connection, so that it doesn't overtake the motion sent over UDP.
+    fn sent(&mut self) -> Option<Message> {
+        self.unsent.take().map(Message::MotionSent)
+    }
+}
+
+async fn stamp_messages(
+    mut receiver: UnboundedReceiver<Message>,
+    sender: UnboundedSender<(Instant, Message)>,
+    length: Arc<AtomicUsize>,
+) -> Result<(), Error> {
+    while let Some(message) = receiver.recv().await {
+        length.fetch_add(1, Ordering::Relaxed);
+        if sender.send((Instant::now(), message)).is_err() {
+            break;
+        }
+    }
+
+    // The writer stops once it has sent what's left.
+    drop(sender);
+    future::pending().await
+}
+
+// Messages waiting to be written, with the time each one was queued.
+struct Queue {
+    receiver: UnboundedReceiver<(Instant, Message)>,
+    length: Arc<AtomicUsize>,
+}
+
+impl Queue {
+    async fn recv(&mut self, settings: &ConnectionSettings) -> Option<Message> {
+        let mut dropped = 0;
+        loop {
+            let (time, message) = self.receiver.recv().await?;
+            let waiting = self.length.fetch_sub(1, Ordering::Relaxed);
+            let stale = match (settings.stale_motion_age, &message) {
+                (Some(age), Message::Event(Event::MouseMove { .. })) => {
+                    waiting > settings.stale_motion_queue && time.elapsed() > age
+                }
+                _ => false,
+            };
+
+            if !stale {
+                if dropped > 0 {
+                    log::debug!("Dropped {} stale motion events", dropped);
+                }
+
+                return Some(message);
+            }
+
+            dropped += 1;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn write_messages<W>(
+    id: ClientId,
+    mut writer: W,
+    mut queue: Queue,
+    mut encodings: UnboundedReceiver<Encoding>,
+    incoming: Arc<Mutex<Reassembler>>,
+    transfers: UnboundedSender<(ClientId, Transfer)>,
+    mut motion: Motion,
+    start: Instant,
+    settings: ConnectionSettings,
+) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut encoding = Encoding::Protobuf;
+    net::write_message(&mut writer, &Message::MotionKey(motion.key.clone()), encoding).await?;
+
+    // Pings are sent regardless of other traffic, since the answers tell us the client is still alive.
+    let mut interval = time::interval(settings.heartbeat_interval);
+    // Clipboard content waiting to be sent, one chunk at a time whenever nothing else is queued.
+    let mut bulk = VecDeque::new();
+    // Bytes of the current clipboard content already sent.
+    let mut sent = 0;
+    // The number of the last event sent over the connection, motion going over UDP is numbered on its own.
+    let mut sequence = 0;
+    loop {
+        let (message, switch) = tokio::select! {
+            biased;
+
+            message = queue.recv(&settings) => match message {
+                Some(Message::ClipboardData(mime_type, data)) => {
+                    bulk.extend(net::clipboard::split(mime_type, data));
+                    continue;
+                }
+                Some(Message::CancelTransfer) => {
+                    // The client drops whatever it has received, the rest is never sent.
+                    let outgoing = sent > 0 || !bulk.is_empty();
+                    bulk.clear();
+                    sent = 0;
+                    // The client keeps sending its content, which is dropped as it arrives.
+                    if incoming.lock().unwrap().discard() || outgoing {
+                        let _ = transfers.send((id, Transfer::Cancelled));
+                    }
+                    (Message::CancelTransfer, None)
+                }
+                Some(Message::Event(Event::MouseMove { axis, delta })) if motion.send(sequence, axis, delta).await => continue,
+                Some(Message::Event(event)) => {
+                    if let Some(sent) = motion.sent() {
+                        time::timeout(settings.timeout, net::write_message(&mut writer, &sent, encoding))
+                            .await
+                            .map_err(|_| Error::new(ErrorKind::TimedOut, "Write timed out"))??;
+                    }
+
+                    sequence += 1;
+                    (Message::SequencedEvent(sequence, event), None)
+                }
+                Some(message) => (message, None),
+                None => return Ok(()),
+            },
+            _ = interval.tick() => (Message::Ping(start.elapsed().as_micros() as u64), None),
+            Some(new) = encodings.recv() => (Message::SetEncoding(new), Some(new)),
+            Some(message) = async { bulk.pop_front() }, if !bulk.is_empty() => (message, None),
+        };
+
+        time::timeout(settings.timeout, net::write_message(&mut writer, &message, encoding))
+            .await
+            .map_err(|_| Error::new(ErrorKind::TimedOut, "Write timed out"))??;
+
+        if let Some(new) = switch {
+            encoding = new;
+        }
+
+        // Anything clipboard related at this point came out of bulk.
+        match message {
+            Message::ClipboardChunk(total, data) => {
+                sent += data.len() as u64;
+                let _ = transfers.send((id, Transfer::Sent { done: sent, total }));
+            }
+            Message::ClipboardData(_, rest) if sent > 0 => {
+                let total = sent + rest.len() as u64;
+                sent = 0;
+                let _ = transfers.send((id, Transfer::Sent { done: total, total }));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn read_messages<R>(
+    id: ClientId,
+    address: SocketAddr,
+    mut reader: R,
+    sender: UnboundedSender<(ClientId, Message)>,
+    encodings: UnboundedSender<Encoding>,
+    incoming: Arc<Mutex<Reassembler>>,
+    transfers: UnboundedSender<(ClientId, Transfer)>,
+    motion_target: Arc<Mutex<MotionPath>>,
+    timing: Arc<Mutex<Timing>>,
+    start: Instant,
+    settings: ConnectionSettings,
+) -> Result<(), Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let stopped = || Error::other("The KVM has stopped");
+
+    let mut encoding = Encoding::Protobuf;
+    loop {
+        let read = net::read_message(&mut reader, settings.max_message_size, encoding);
+        let message = time::timeout(settings.timeout, read)
+            .await
+            .map_err(|_| Error::new(ErrorKind::TimedOut, "Read timed out"))??;
+        match message {
+            Message::SetEncoding(new) => {
+                log::debug!("Switching to {:?} encoding", new);
+                encoding = new;
+                encodings.send(new).map_err(|_| stopped())?;
+            }
+            Message::MotionChannel(port) => {
+                log::debug!("Sending motion over UDP to port {}", port);
+                *motion_target.lock().unwrap() = MotionPath {
+                    target: Some(SocketAddr::new(address.ip(), port)),
+                    acknowledged: None,
+                };
+            }
+            Message::MotionAck(sequence) => {
+                log::trace!("Motion over UDP acknowledged up to {}", sequence);
+                motion_target.lock().unwrap().acknowledged = Some(Instant::now());
+            }
+            Message::Pong(sent, client_time) => {
+                let rtt = start.elapsed().saturating_sub(Duration::from_micros(sent));
+                timing.lock().unwrap().update(rtt, client_time);
+            }
+            Message::ClipboardChunk(total, data) => {
+                let done = incoming.lock().unwrap().push(total, data, settings.max_message_size)?;
+                if done > 0 {
+                    let _ = transfers.send((id, Transfer::Received { done, total }));
+                }
+            }
+            Message::ClipboardData(mime_type, rest) => {
+                let (total, data) = {
+                    let mut incoming = incoming.lock().unwrap();
+                    (incoming.in_progress().then(|| incoming.total()), incoming.finish(rest))
+                };
+                if let Some(data) = data {
+                    if let Some(total) = total {
+                        let _ = transfers.send((id, Transfer::Received { done: total, total }));
+                    }
+                    sender.send((id, Message::ClipboardData(mime_type, data))).map_err(|_| stopped())?;
+                }
+            }
+
+            message => sender.send((id, message)).map_err(|_| stopped())?,
+        }
+    }
+}
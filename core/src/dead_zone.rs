@@ -0,0 +1,72 @@
+use crate::policy::Policy;
+use input::{Axis, Event};
+
+// Drops pointer motion too small to be deliberate, like the jitter of a high resolution sensor resting on the desk.
+// Small deltas are summed up per axis and only let through once they add up to the threshold, so jitter cancels
+// itself out while slow deliberate motion still gets through.
+pub struct DeadZone {
+    threshold: i32,
+    x: i32,
+    y: i32,
+}
+
+impl DeadZone {
+    pub fn new(threshold: i32) -> Self {
+        Self { threshold, x: 0, y: 0 }
+    }
+}
+
+impl Policy for DeadZone {
+    fn filter_event(&mut self, event: Event) -> Option<Event> {
+        let (axis, delta) = match event {
+            Event::MouseMove { axis, delta } => (axis, delta),
+            event => return Some(event),
+        };
+
+        let pending = match axis {
+            Axis::X => &mut self.x,
+            Axis::Y => &mut self.y,
+        };
+
+        *pending += delta;
+        if pending.abs() < self.threshold {
+            return None;
+        }
+
+        let delta = std::mem::take(pending);
+        Some(Event::MouseMove { axis, delta })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn motion(delta: i32) -> Event {
+        Event::MouseMove { axis: Axis::X, delta }
+    }
+
+    fn delta(event: Option<Event>) -> Option<i32> {
+        match event? {
+            Event::MouseMove { delta, .. } => Some(delta),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn jitter_is_dropped() {
+        let mut dead_zone = DeadZone::new(3);
+        for delta in [1, -1, 1, -1, 2, -2].iter() {
+            assert!(dead_zone.filter_event(motion(*delta)).is_none());
+        }
+    }
+
+    #[test]
+    fn slow_motion_adds_up() {
+        let mut dead_zone = DeadZone::new(3);
+        assert_eq!(delta(dead_zone.filter_event(motion(1))), None);
+        assert_eq!(delta(dead_zone.filter_event(motion(1))), None);
+        assert_eq!(delta(dead_zone.filter_event(motion(1))), Some(3));
+        assert_eq!(delta(dead_zone.filter_event(motion(5))), Some(5));
+    }
+}
@@ -0,0 +1,12 @@
+// What happens when a client connects with the name of one already connected. Both staying under the same name would
+// make them impossible to tell apart, in notifications as well as when switching by name.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DuplicateNames {
+    // The new connection takes over, e.g. a client reconnecting before its old connection timed out.
+    #[default]
+    Replace,
+    // The new client is turned away.
+    Reject,
+    // The new client is renamed, e.g. to "laptop (2)".
+    Suffix,
+}
@@ -0,0 +1,81 @@
+// Expands ${VAR} in config values with the variable's value, so that one config can be shared between machines and
+// containers. ${VAR:-default} falls back to the default if the variable isn't set, $$ stands for a literal $.
+// Anything else, including a lone $, is left as it is.
+pub fn expand_env_vars(text: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+
+        let reference = match rest.strip_prefix("${") {
+            Some(reference) => reference,
+            None => {
+                expanded.push('$');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+
+        let end = reference
+            .find('}')
+            .ok_or_else(|| format!("Unterminated environment variable reference in {:?}", text))?;
+        let (name, default) = match reference[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&reference[..end], None),
+        };
+
+        match (lookup(name), default) {
+            (Some(value), _) => expanded.push_str(&value),
+            (None, Some(default)) => expanded.push_str(default),
+            (None, None) => return Err(format!("Environment variable {} isn't set", name)),
+        }
+        rest = &reference[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(text: &str) -> Result<String, String> {
+        expand_env_vars(text, |name| match name {
+            "HOST" => Some("desk".to_owned()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn variables_are_expanded() {
+        assert_eq!(expand("/etc/rkvm/${HOST}.p12").unwrap(), "/etc/rkvm/desk.p12");
+        assert_eq!(expand("${HOST}${EMPTY}-${HOST}").unwrap(), "desk-desk");
+        assert_eq!(expand("no variables").unwrap(), "no variables");
+    }
+
+    #[test]
+    fn defaults_are_used_for_unset_variables() {
+        assert_eq!(expand("${PORT:-5258}").unwrap(), "5258");
+        assert_eq!(expand("${HOST:-other}").unwrap(), "desk");
+    }
+
+    #[test]
+    fn dollars_are_escaped() {
+        assert_eq!(expand("pa$$word $HOST $").unwrap(), "pa$word $HOST $");
+    }
+
+    #[test]
+    fn missing_variables_are_errors() {
+        assert!(expand("${PASSWORD}").is_err());
+        assert!(expand("${HOST").is_err());
+    }
+}
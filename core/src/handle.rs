@@ -0,0 +1,195 @@
+use crate::connection::{self, ConnectionSettings, Timing, Transfer};
+use crate::hotkey::Action;
+use crate::state::{Client, ClientId};
+use input::Key;
+use net::control::{ClientStatus, Notification, SwitchRecord};
+use net::{Encoding, Message};
+use std::collections::HashSet;
+use std::io::Error;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{broadcast, oneshot};
+use tracing::Instrument;
+
+// Requests from handles which need the state owned by the running KVM.
+pub(crate) enum Command {
+    Switch(String, oneshot::Sender<Result<(), Error>>),
+    Clients(oneshot::Sender<Vec<ClientStatus>>),
+    SetHotkey(Action, HashSet<Key>, oneshot::Sender<()>),
+    SetProfile(Option<String>, oneshot::Sender<Result<(), Error>>),
+    Run(String, String, oneshot::Sender<Result<(), Error>>),
+    Wake(String, oneshot::Sender<Result<(), Error>>),
+    UndoSwitch(oneshot::Sender<Result<(), Error>>),
+    History(oneshot::Sender<Vec<SwitchRecord>>),
+    Shutdown,
+}
+
+// Used to talk to a running KVM, can be cloned freely.
+#[derive(Clone)]
+pub struct Handle {
+    pub(crate) commands: UnboundedSender<Command>,
+    pub(crate) clients: UnboundedSender<Client>,
+    pub(crate) messages: UnboundedSender<(ClientId, Message)>,
+    pub(crate) disconnects: UnboundedSender<ClientId>,
+    pub(crate) transfers: UnboundedSender<(ClientId, Transfer)>,
+    pub(crate) notifications: broadcast::Sender<Notification>,
+    pub(crate) next_id: Arc<AtomicU64>,
+    // Connections whose tasks are still running, including ones the KVM has already dropped but which are still
+    // flushing.
+    pub(crate) connections: Arc<AtomicUsize>,
+    pub(crate) settings: ConnectionSettings,
+}
+
+impl Handle {
+    // Takes over a client connection once the handshake is done, returning the id the client was assigned.
+    // A client without a name goes by its address.
+    pub fn add_client<T>(&self, name: String, address: SocketAddr, stream: T) -> u64
+    where
+        T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let name = match name.trim() {
+            "" => address.ip().to_string(),
+            _ => name,
+        };
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let _ = sender.send(Message::Encodings(vec![Encoding::Protobuf, Encoding::Compact]));
+        let timing = Arc::new(Mutex::new(Timing::default()));
+
+        let client = Client {
+            id,
+            name: name.clone(),
+            address,
+            connected_since: SystemTime::now(),
+            sender,
+            timing: timing.clone(),
+        };
+        if self.clients.send(client).is_err() {
+            return id;
+        }
+
+        let messages = self.messages.clone();
+        let disconnects = self.disconnects.clone();
+        let transfers = self.transfers.clone();
+        let connections = self.connections.clone();
+        connections.fetch_add(1, Ordering::Relaxed);
+        let settings = self.settings;
+        let span = tracing::info_span!("connection", id, name = %name, %address);
+        let task = async move {
+            log::info!("{} {}: connected", name, address);
+            let message = connection::handle_connection(id, address, stream, receiver, messages, transfers, timing, settings)
+                .await
+                .err()
+                .map(|err| format!(" ({})", err))
+                .unwrap_or_else(String::new);
+            log::info!("{} {}: disconnected{}", name, address, message);
+            let _ = disconnects.send(id);
+            connections.fetch_sub(1, Ordering::Relaxed);
+        };
+        tokio::spawn(task.instrument(span));
+
+        id
+    }
+
+    // The number of client connections currently open.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    // Moves focus to the client with the given name, the server itself is addressed by its hostname.
+    pub async fn switch(&self, name: &str) -> Result<(), Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.commands
+            .send(Command::Switch(name.to_owned(), sender))
+            .map_err(|_| stopped())?;
+
+        receiver.await.map_err(|_| stopped())?
+    }
+
+    pub async fn clients(&self) -> Result<Vec<ClientStatus>, Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.commands
+            .send(Command::Clients(sender))
+            .map_err(|_| stopped())?;
+
+        receiver.await.map_err(|_| stopped())
+    }
+
+    // Rebinds an action while running, an empty key set leaves it unbound.
+    pub async fn set_hotkey(&self, action: Action, keys: HashSet<Key>) -> Result<(), Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.commands
+            .send(Command::SetHotkey(action, keys, sender))
+            .map_err(|_| stopped())?;
+
+        receiver.await.map_err(|_| stopped())
+    }
+
+    // Restricts cycling to the clients in the group with the given name, None lets all clients take part again.
+    pub async fn set_profile(&self, group: Option<String>) -> Result<(), Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.commands
+            .send(Command::SetProfile(group, sender))
+            .map_err(|_| stopped())?;
+
+        receiver.await.map_err(|_| stopped())?
+    }
+
+    // Asks the client with the given name to run one of the commands it allows, see Message::RunCommand.
+    pub async fn run_command(&self, name: &str, command: String) -> Result<(), Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.commands
+            .send(Command::Run(name.to_owned(), command, sender))
+            .map_err(|_| stopped())?;
+
+        receiver.await.map_err(|_| stopped())?
+    }
+
+    // Sends a Wake-on-LAN packet to the client with the given name, which doesn't have to be connected.
+    pub async fn wake(&self, name: &str) -> Result<(), Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.commands
+            .send(Command::Wake(name.to_owned(), sender))
+            .map_err(|_| stopped())?;
+
+        receiver.await.map_err(|_| stopped())?
+    }
+
+    // Moves focus back to where it was before the last switch.
+    pub async fn undo_switch(&self) -> Result<(), Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.commands
+            .send(Command::UndoSwitch(sender))
+            .map_err(|_| stopped())?;
+
+        receiver.await.map_err(|_| stopped())?
+    }
+
+    // The last focus changes, oldest first.
+    pub async fn history(&self) -> Result<Vec<SwitchRecord>, Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.commands
+            .send(Command::History(sender))
+            .map_err(|_| stopped())?;
+
+        receiver.await.map_err(|_| stopped())
+    }
+
+    // Has the KVM release the keys held down and say goodbye to the clients, after which it stops.
+    pub fn shutdown(&self) {
+        let _ = self.commands.send(Command::Shutdown);
+    }
+
+    // Clients connecting and disconnecting, and focus changes.
+    pub fn subscribe(&self) -> broadcast::Receiver<Notification> {
+        self.notifications.subscribe()
+    }
+}
+
+fn stopped() -> Error {
+    Error::other("The KVM has stopped")
+}
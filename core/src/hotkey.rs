@@ -0,0 +1,179 @@
+use input::{Direction, Key};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Action {
+    Switch,
+    Kill,
+    Pause,
+    Roster,
+    CancelTransfer,
+    ToggleClipboard,
+    Pick,
+    NextProfile,
+    OverrideAccess,
+    LockAll,
+    Suspend,
+    Wake,
+    SecureAttention,
+    UndoSwitch,
+}
+
+// A set of keys which have to be held down at the same time to trigger an action.
+struct Chord {
+    states: HashMap<Key, bool>,
+}
+
+impl Chord {
+    fn new(keys: &HashSet<Key>) -> Self {
+        Self {
+            states: keys.iter().map(|key| (*key, false)).collect(),
+        }
+    }
+
+    // Returns true if the key completed the chord, in which case the state is reset.
+    fn update(&mut self, key: Key, direction: Direction) -> bool {
+        let state = match self.states.get_mut(&key) {
+            Some(state) => state,
+            None => return false,
+        };
+
+        *state = direction == Direction::Down;
+        if !self.states.values().all(|state| *state) {
+            return false;
+        }
+
+        for state in self.states.values_mut() {
+            *state = false;
+        }
+
+        true
+    }
+}
+
+pub struct Hotkeys {
+    chords: Vec<(Action, Chord)>,
+    // Minimum time between two triggers of an action, so a bouncing or sloppily released chord fires once.
+    cooldowns: HashMap<Action, Duration>,
+    triggered: HashMap<Action, Instant>,
+}
+
+impl Hotkeys {
+    pub fn new() -> Self {
+        Self {
+            chords: Vec::new(),
+            cooldowns: HashMap::new(),
+            triggered: HashMap::new(),
+        }
+    }
+
+    pub fn set_cooldown(&mut self, action: Action, cooldown: Duration) {
+        self.cooldowns.insert(action, cooldown);
+    }
+
+    // An empty key set leaves the action unbound.
+    pub fn add(&mut self, action: Action, keys: &HashSet<Key>) {
+        if !keys.is_empty() {
+            self.chords.push((action, Chord::new(keys)));
+        }
+    }
+
+    // Replaces the keys bound to an action.
+    pub fn set(&mut self, action: Action, keys: &HashSet<Key>) {
+        self.chords.retain(|(bound, _)| *bound != action);
+        self.add(action, keys);
+    }
+
+    // Feeds a key event to all chords, returning the first action that was triggered.
+    pub fn update(&mut self, key: Key, direction: Direction) -> Option<Action> {
+        let mut triggered = None;
+        for (action, chord) in &mut self.chords {
+            if chord.update(key, direction) && triggered.is_none() {
+                triggered = Some(*action);
+            }
+        }
+
+        let action = triggered?;
+        let now = Instant::now();
+        if let (Some(cooldown), Some(last)) = (self.cooldowns.get(&action), self.triggered.get(&action)) {
+            if now.duration_since(*last) < *cooldown {
+                log::debug!("Ignoring {:?} hotkey during its cooldown", action);
+                return None;
+            }
+        }
+
+        self.triggered.insert(action, now);
+        Some(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(keys: &[Key]) -> HashSet<Key> {
+        keys.iter().copied().collect()
+    }
+
+    #[test]
+    fn chord_triggers_once_all_keys_are_held() {
+        let mut hotkeys = Hotkeys::new();
+        hotkeys.add(Action::Switch, &keys(&[Key::LeftAlt, Key::LeftCtrl]));
+
+        assert_eq!(hotkeys.update(Key::LeftAlt, Direction::Down), None);
+        assert_eq!(hotkeys.update(Key::LeftAlt, Direction::Up), None);
+        assert_eq!(hotkeys.update(Key::LeftCtrl, Direction::Down), None);
+        assert_eq!(hotkeys.update(Key::LeftAlt, Direction::Down), Some(Action::Switch));
+    }
+
+    #[test]
+    fn chord_resets_after_triggering() {
+        let mut hotkeys = Hotkeys::new();
+        hotkeys.add(Action::Switch, &keys(&[Key::LeftAlt]));
+
+        assert_eq!(hotkeys.update(Key::LeftAlt, Direction::Down), Some(Action::Switch));
+        assert_eq!(hotkeys.update(Key::LeftAlt, Direction::Up), None);
+        assert_eq!(hotkeys.update(Key::LeftAlt, Direction::Down), Some(Action::Switch));
+    }
+
+    #[test]
+    fn overlapping_chords_trigger_first_added() {
+        let mut hotkeys = Hotkeys::new();
+        hotkeys.add(Action::Pause, &keys(&[Key::Pause]));
+        hotkeys.add(Action::Roster, &keys(&[Key::Pause]));
+
+        assert_eq!(hotkeys.update(Key::Pause, Direction::Down), Some(Action::Pause));
+    }
+
+    #[test]
+    fn set_replaces_binding() {
+        let mut hotkeys = Hotkeys::new();
+        hotkeys.add(Action::Switch, &keys(&[Key::LeftAlt]));
+        hotkeys.set(Action::Switch, &keys(&[Key::RightAlt]));
+
+        assert_eq!(hotkeys.update(Key::LeftAlt, Direction::Down), None);
+        assert_eq!(hotkeys.update(Key::RightAlt, Direction::Down), Some(Action::Switch));
+    }
+
+    #[test]
+    fn cooldown_suppresses_repeated_triggers() {
+        let mut hotkeys = Hotkeys::new();
+        hotkeys.add(Action::Switch, &keys(&[Key::LeftAlt]));
+        hotkeys.add(Action::Pause, &keys(&[Key::Pause]));
+        hotkeys.set_cooldown(Action::Switch, Duration::from_secs(3600));
+
+        assert_eq!(hotkeys.update(Key::LeftAlt, Direction::Down), Some(Action::Switch));
+        assert_eq!(hotkeys.update(Key::LeftAlt, Direction::Up), None);
+        assert_eq!(hotkeys.update(Key::LeftAlt, Direction::Down), None);
+        assert_eq!(hotkeys.update(Key::Pause, Direction::Down), Some(Action::Pause));
+    }
+
+    #[test]
+    fn empty_chord_is_unbound() {
+        let mut hotkeys = Hotkeys::new();
+        hotkeys.add(Action::Kill, &HashSet::new());
+
+        assert_eq!(hotkeys.update(Key::Esc, Direction::Down), None);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::builder::KvmBuilder;
+use crate::connection::Transfer;
+use crate::handle::Command;
+use crate::mouse_keys::{self, MouseKeys};
+use crate::state::{self, Client, ClientId, State};
+use input::Manager;
+use net::Message;
+use std::io::Error;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time;
+
+pub struct Kvm<M> {
+    pub(crate) state: State<M>,
+    pub(crate) commands: UnboundedReceiver<Command>,
+    pub(crate) clients: UnboundedReceiver<Client>,
+    pub(crate) messages: UnboundedReceiver<(ClientId, Message)>,
+    pub(crate) disconnects: UnboundedReceiver<ClientId>,
+    pub(crate) transfers: UnboundedReceiver<(ClientId, Transfer)>,
+    // Buttons clicked in notifications, see State::handle_action.
+    pub(crate) actions: UnboundedReceiver<String>,
+}
+
+impl<M: Manager> Kvm<M> {
+    pub fn builder(manager: M, hostname: String) -> KvmBuilder<M> {
+        KvmBuilder::new(manager, hostname)
+    }
+
+    // Runs until the kill hotkey is pressed or it's shut down through a handle, or fails if the input devices do.
+    #[tracing::instrument(name = "event_loop", skip_all, fields(hostname = %self.state.hostname))]
+    pub async fn run(mut self) -> Result<(), Error> {
+        let state = &mut self.state;
+        state.osd.show(&state.hostname);
+
+        let mut mouse_keys_interval = time::interval(mouse_keys::INTERVAL);
+        let mut activity_interval = time::interval(state::ACTIVITY_INTERVAL);
+        let mut idle_interval = time::interval(state::IDLE_CHECK_INTERVAL);
+        loop {
+            let focus = state.focus;
+            tokio::select! {
+                Some((id, message)) = self.messages.recv() => state.handle_message(id, message).await?,
+                event = state.manager.read() => {
+                    if !state.handle_local(event?).await? {
+                        return Ok(());
+                    }
+                }
+                _ = mouse_keys_interval.tick(), if state.mouse_keys.as_ref().map(MouseKeys::is_moving).unwrap_or(false) => {
+                    for event in state.mouse_keys.as_ref().unwrap().tick() {
+                        state.forward(event).await?;
+                    }
+                }
+                _ = activity_interval.tick(), if state.sync_activity => state.sync_activity().await?,
+                _ = idle_interval.tick(), if state.idle_timeout.is_some() => state.disconnect_idle(),
+                Some(client) = self.clients.recv() => {
+                    // The disconnect notification might have overtaken the client itself.
+                    if !client.sender.is_closed() {
+                        state.add(client);
+                    }
+                }
+                Some(id) = self.disconnects.recv() => state.remove(id),
+                Some((id, transfer)) = self.transfers.recv() => state.handle_transfer(id, transfer),
+                Some(action) = self.actions.recv() => state.handle_action(&action),
+                Some(command) = self.commands.recv() => match command {
+                    Command::Switch(name, responder) => {
+                        let _ = responder.send(state.switch_to_name(&name));
+                    }
+                    Command::Clients(responder) => {
+                        let _ = responder.send(state.status());
+                    }
+                    Command::SetHotkey(action, keys, responder) => {
+                        log::info!("Setting {:?} keys to {:?}", action, keys);
+                        state.hotkeys.set(action, &keys);
+                        let _ = responder.send(());
+                    }
+                    Command::SetProfile(group, responder) => {
+                        let _ = responder.send(state.set_profile(group.as_deref()));
+                    }
+                    Command::Run(name, command, responder) => {
+                        let _ = responder.send(state.run_command(&name, command));
+                    }
+                    Command::Wake(name, responder) => {
+                        let _ = responder.send(state.wake(&name));
+                    }
+                    Command::UndoSwitch(responder) => {
+                        let _ = responder.send(state.undo_switch());
+                    }
+                    Command::History(responder) => {
+                        let _ = responder.send(state.history.iter().cloned().collect());
+                    }
+                    Command::Shutdown => {
+                        state.shutdown().await;
+                        return Ok(());
+                    }
+                },
+            }
+
+            // Switching can't wait for the manager, so the server's pointer is parked once it's done.
+            if focus.is_none() && state.focus.is_some() {
+                state.park_pointer().await?;
+            }
+        }
+    }
+}
@@ -0,0 +1,37 @@
+// The server side of rkvm as a library: reads the local input devices and routes events to whichever machine
+// has focus. Accepting connections is left to the embedder, which hands clients over through a Handle once
+// the handshake is done.
+mod acceleration;
+mod axis_mask;
+mod builder;
+mod connection;
+mod dead_zone;
+mod duplicate_names;
+mod env_vars;
+mod handle;
+mod hotkey;
+mod kvm;
+#[cfg(test)]
+mod mock;
+mod mouse_keys;
+mod path_translation;
+mod policy;
+mod scale;
+mod sharing;
+mod state;
+mod switch_policy;
+mod unsupported;
+
+pub use axis_mask::RelativeAxis;
+pub use builder::KvmBuilder;
+pub use connection::ConnectionSettings;
+pub use dead_zone::DeadZone;
+pub use duplicate_names::DuplicateNames;
+pub use env_vars::expand_env_vars;
+pub use handle::Handle;
+pub use hotkey::Action;
+pub use kvm::Kvm;
+pub use path_translation::PathTranslation;
+pub use policy::Policy;
+pub use sharing::Sharing;
+pub use switch_policy::SwitchPolicy;
@@ -0,0 +1,71 @@
+use async_trait::async_trait;
+use input::{Capabilities, Event, LockState, Manager};
+use std::collections::VecDeque;
+use std::future;
+use std::io::Error;
+
+// Hands out queued events and records everything the loop does with the local machine.
+#[derive(Default)]
+pub struct MockManager {
+    pub events: VecDeque<Event>,
+    pub written: Vec<Event>,
+    pub notifications: Vec<String>,
+    // The identifiers of the buttons of the last notification which had any.
+    pub actions: Vec<String>,
+    pub led: Option<bool>,
+    pub locked: bool,
+    pub lock_state: Option<LockState>,
+    pub capabilities: Option<Capabilities>,
+    // Makes every write fail, like a virtual device that went away.
+    pub broken: bool,
+}
+
+#[async_trait]
+impl Manager for MockManager {
+    // Once the queue is empty, waits forever like an idle keyboard would.
+    async fn read(&mut self) -> Result<Event, Error> {
+        match self.events.pop_front() {
+            Some(event) => Ok(event),
+            None => future::pending().await,
+        }
+    }
+
+    async fn write(&mut self, event: Event) -> Result<(), Error> {
+        if self.broken {
+            return Err(Error::other("Broken"));
+        }
+
+        self.written.push(event);
+        Ok(())
+    }
+
+    fn notify(&mut self, message: String) {
+        self.notifications.push(message);
+    }
+
+    fn notify_with_actions(
+        &mut self,
+        message: String,
+        actions: &[(&str, &str)],
+        _callback: Box<dyn FnOnce(String) + Send>,
+    ) {
+        self.notifications.push(message);
+        self.actions = actions.iter().map(|(identifier, _)| (*identifier).to_owned()).collect();
+    }
+
+    fn lock_session(&mut self) {
+        self.locked = true;
+    }
+
+    fn set_indicator_led(&mut self, on: bool) {
+        self.led = Some(on);
+    }
+
+    fn lock_state(&mut self) -> Option<LockState> {
+        self.lock_state
+    }
+
+    fn capabilities(&mut self) -> Option<Capabilities> {
+        self.capabilities.clone()
+    }
+}
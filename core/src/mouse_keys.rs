@@ -0,0 +1,214 @@
+use input::{Axis, Button, Direction, Event, Key, KeyKind};
+use std::collections::HashSet;
+use std::time::Duration;
+
+// How often pointer motion is generated while a direction key is held.
+pub const INTERVAL: Duration = Duration::from_millis(20);
+
+// Translates numpad keys into pointer motion and clicks, for controlling the pointer with the keyboard only.
+pub struct MouseKeys {
+    speed: i32,
+    // The direction keys held down, and the click keys.
+    held: HashSet<Key>,
+    clicking: HashSet<Key>,
+}
+
+impl MouseKeys {
+    pub fn new(speed: i32) -> Self {
+        Self {
+            speed,
+            held: HashSet::new(),
+            clicking: HashSet::new(),
+        }
+    }
+
+    // Returns None if the key is not handled by mouse keys and should be passed through untouched. Keys are only
+    // pressed while active, but a key pressed then is also released here once it's no longer, so that nothing stays
+    // held on either side.
+    pub fn translate(&mut self, key: Key, direction: Direction, active: bool) -> Option<Vec<Event>> {
+        let taken = self.held.contains(&key) || self.clicking.contains(&key);
+        match direction {
+            Direction::Down if !active => return None,
+            Direction::Up if !taken => return None,
+            _ => {}
+        }
+
+        if let Some(button) = button_of(key) {
+            match direction {
+                Direction::Down => self.clicking.insert(key),
+                Direction::Up => self.clicking.remove(&key),
+            };
+
+            return Some(vec![Event::Key {
+                direction,
+                kind: KeyKind::Button(button),
+            }]);
+        }
+
+        direction_of(key)?;
+        match direction {
+            Direction::Down => {
+                self.held.insert(key);
+                Some(motion(key, self.speed))
+            }
+            Direction::Up => {
+                self.held.remove(&key);
+                Some(Vec::new())
+            }
+        }
+    }
+
+    pub fn is_moving(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    // Motion for the keys that are currently held, called every INTERVAL.
+    pub fn tick(&self) -> Vec<Event> {
+        self.held
+            .iter()
+            .flat_map(|key| motion(*key, self.speed))
+            .collect()
+    }
+}
+
+// Whether mouse keys have a use for the key, see translate.
+pub fn handles(key: Key) -> bool {
+    button_of(key).is_some() || direction_of(key).is_some()
+}
+
+fn button_of(key: Key) -> Option<Button> {
+    match key {
+        Key::Kp5 => Some(Button::Left),
+        Key::Kp0 => Some(Button::Right),
+        _ => None,
+    }
+}
+
+fn direction_of(key: Key) -> Option<(i32, i32)> {
+    let direction = match key {
+        Key::Kp1 => (-1, 1),
+        Key::Kp2 => (0, 1),
+        Key::Kp3 => (1, 1),
+        Key::Kp4 => (-1, 0),
+        Key::Kp6 => (1, 0),
+        Key::Kp7 => (-1, -1),
+        Key::Kp8 => (0, -1),
+        Key::Kp9 => (1, -1),
+        _ => return None,
+    };
+
+    Some(direction)
+}
+
+fn motion(key: Key, speed: i32) -> Vec<Event> {
+    let (x, y) = match direction_of(key) {
+        Some(direction) => direction,
+        None => return Vec::new(),
+    };
+
+    let mut events = Vec::new();
+    if x != 0 {
+        events.push(Event::MouseMove {
+            axis: Axis::X,
+            delta: x * speed,
+        });
+    }
+
+    if y != 0 {
+        events.push(Event::MouseMove {
+            axis: Axis::Y,
+            delta: y * speed,
+        });
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The total motion of the events on each axis, and the buttons pressed or released.
+    fn summary(events: &[Event]) -> ((i32, i32), Vec<(Direction, Button)>) {
+        let mut motion = (0, 0);
+        let mut buttons = Vec::new();
+        for event in events {
+            match *event {
+                Event::MouseMove { axis: Axis::X, delta } => motion.0 += delta,
+                Event::MouseMove { axis: Axis::Y, delta } => motion.1 += delta,
+                Event::Key { direction, kind: KeyKind::Button(button) } => buttons.push((direction, button)),
+                _ => panic!("Unexpected event {:?}", event),
+            }
+        }
+
+        (motion, buttons)
+    }
+
+    #[test]
+    fn keys_map_to_motion_and_clicks() {
+        let mut keys = MouseKeys::new(5);
+        let cases = [
+            (Key::Kp1, (-5, 5)),
+            (Key::Kp2, (0, 5)),
+            (Key::Kp3, (5, 5)),
+            (Key::Kp4, (-5, 0)),
+            (Key::Kp6, (5, 0)),
+            (Key::Kp7, (-5, -5)),
+            (Key::Kp8, (0, -5)),
+            (Key::Kp9, (5, -5)),
+        ];
+        for (key, expected) in cases.iter() {
+            let events = keys.translate(*key, Direction::Down, true).unwrap();
+            assert_eq!(summary(&events), (*expected, Vec::new()), "{:?}", key);
+            assert!(keys.translate(*key, Direction::Up, true).unwrap().is_empty());
+        }
+
+        let events = keys.translate(Key::Kp5, Direction::Down, true).unwrap();
+        assert_eq!(summary(&events), ((0, 0), vec![(Direction::Down, Button::Left)]));
+        let events = keys.translate(Key::Kp0, Direction::Down, true).unwrap();
+        assert_eq!(summary(&events), ((0, 0), vec![(Direction::Down, Button::Right)]));
+
+        assert!(keys.translate(Key::A, Direction::Down, true).is_none());
+        assert!(keys.translate(Key::A, Direction::Up, true).is_none());
+    }
+
+    #[test]
+    fn held_keys_keep_moving() {
+        let mut keys = MouseKeys::new(3);
+        assert!(!keys.is_moving());
+        assert!(keys.tick().is_empty());
+
+        keys.translate(Key::Kp6, Direction::Down, true).unwrap();
+        keys.translate(Key::Kp2, Direction::Down, true).unwrap();
+        assert!(keys.is_moving());
+        assert_eq!(summary(&keys.tick()), ((3, 3), Vec::new()));
+        assert_eq!(summary(&keys.tick()), ((3, 3), Vec::new()));
+
+        keys.translate(Key::Kp6, Direction::Up, true).unwrap();
+        assert_eq!(summary(&keys.tick()), ((0, 3), Vec::new()));
+
+        keys.translate(Key::Kp2, Direction::Up, true).unwrap();
+        assert!(!keys.is_moving());
+        assert!(keys.tick().is_empty());
+    }
+
+    #[test]
+    fn inactive_keys_pass_through_but_taken_ones_are_released() {
+        let mut keys = MouseKeys::new(5);
+        assert!(keys.translate(Key::Kp8, Direction::Down, false).is_none());
+        assert!(keys.translate(Key::Kp8, Direction::Up, false).is_none());
+        assert!(!keys.is_moving());
+
+        // Pressed while active, released after NumLock went on.
+        keys.translate(Key::Kp8, Direction::Down, true).unwrap();
+        keys.translate(Key::Kp5, Direction::Down, true).unwrap();
+        assert!(keys.translate(Key::Kp8, Direction::Up, false).unwrap().is_empty());
+        let events = keys.translate(Key::Kp5, Direction::Up, false).unwrap();
+        assert_eq!(summary(&events), ((0, 0), vec![(Direction::Up, Button::Left)]));
+        assert!(!keys.is_moving());
+
+        // And the other way around, a key pressed before has to be released as a key.
+        assert!(keys.translate(Key::Kp4, Direction::Down, false).is_none());
+        assert!(keys.translate(Key::Kp4, Direction::Up, true).is_none());
+    }
+}
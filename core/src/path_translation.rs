@@ -0,0 +1,105 @@
+// Rewrites paths in clipboard text moving from one machine to another, e.g. /home/me to smb://nas/me, so that a path
+// copied on one machine resolves where it's pasted. Only whole path components match: /home/me doesn't touch
+// /home/meg. When the replacement uses the other kind of separator, the rest of the path is converted too.
+#[derive(Clone, Debug)]
+pub struct PathTranslation {
+    // The machine names, None matching any machine.
+    from: Option<String>,
+    to: Option<String>,
+    prefix: String,
+    replacement: String,
+}
+
+impl PathTranslation {
+    pub fn new(from: Option<String>, to: Option<String>, prefix: String, replacement: String) -> Self {
+        Self {
+            from,
+            to,
+            prefix,
+            replacement,
+        }
+    }
+
+    pub(crate) fn applies(&self, from: &str, to: &str) -> bool {
+        self.from.as_deref().map(|name| name == from).unwrap_or(true)
+            && self.to.as_deref().map(|name| name == to).unwrap_or(true)
+    }
+
+    pub(crate) fn apply(&self, text: &str) -> String {
+        if self.prefix.is_empty() {
+            return text.to_owned();
+        }
+
+        let separator = match (self.prefix.contains('\\'), self.replacement.contains('\\')) {
+            (false, true) if !self.replacement.contains('/') => Some(('/', '\\')),
+            (true, false) => Some(('\\', '/')),
+            _ => None,
+        };
+
+        let mut translated = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(index) = rest.find(&self.prefix) {
+            let (before, after) = (&rest[..index], &rest[index + self.prefix.len()..]);
+            let starts = translated.chars().chain(before.chars()).last().map(is_delimiter).unwrap_or(true);
+            let ends = after.chars().next().map(|c| c == '/' || c == '\\' || is_delimiter(c)).unwrap_or(true);
+            translated.push_str(before);
+            if !(starts && ends) {
+                translated.push_str(&self.prefix);
+                rest = after;
+                continue;
+            }
+
+            translated.push_str(&self.replacement);
+            let end = after.find(is_delimiter).unwrap_or(after.len());
+            match separator {
+                Some((from, to)) => translated.extend(after[..end].chars().map(|c| if c == from { to } else { c })),
+                None => translated.push_str(&after[..end]),
+            }
+            rest = &after[end..];
+        }
+
+        translated.push_str(rest);
+        translated
+    }
+}
+
+// What can surround a path in text.
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '"' | '\'' | '`' | '(' | ')' | '<' | '>' | '=' | ',')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation(prefix: &str, replacement: &str) -> PathTranslation {
+        PathTranslation::new(None, None, prefix.to_owned(), replacement.to_owned())
+    }
+
+    #[test]
+    fn prefixes_are_replaced() {
+        let translation = translation("/home/me", "smb://nas/me");
+        assert_eq!(translation.apply("/home/me/doc.txt"), "smb://nas/me/doc.txt");
+        assert_eq!(translation.apply("see \"/home/me\" and /home/me/a"), "see \"smb://nas/me\" and smb://nas/me/a");
+    }
+
+    #[test]
+    fn only_whole_components_match() {
+        let translation = translation("/home/me", "smb://nas/me");
+        assert_eq!(translation.apply("/home/meg/doc.txt"), "/home/meg/doc.txt");
+        assert_eq!(translation.apply("/mnt/home/me/doc.txt"), "/mnt/home/me/doc.txt");
+    }
+
+    #[test]
+    fn separators_are_converted() {
+        let translation = translation("/home/me", "C:\\Users\\me");
+        assert_eq!(translation.apply("/home/me/docs/a.txt b/c"), "C:\\Users\\me\\docs\\a.txt b/c");
+    }
+
+    #[test]
+    fn machines_are_matched() {
+        let translation = PathTranslation::new(Some("laptop".to_owned()), None, "/a".to_owned(), "/b".to_owned());
+        assert!(translation.applies("laptop", "desk"));
+        assert!(!translation.applies("desk", "laptop"));
+    }
+}
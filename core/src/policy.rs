@@ -0,0 +1,26 @@
+use input::Event;
+
+// Hooks which let an embedder veto or alter what the KVM does. Everything is allowed by default.
+pub trait Policy: Send {
+    // Called before focus moves, None standing for the server itself. Returning false keeps focus where it is.
+    fn allow_switch(&mut self, _from: Option<&str>, _to: Option<&str>) -> bool {
+        true
+    }
+
+    // Called for every local event which isn't part of a hotkey, before it's routed. Returning None drops it.
+    fn filter_event(&mut self, event: Event) -> Option<Event> {
+        Some(event)
+    }
+
+    // Called for every event about to be sent to the focused client. Returning false keeps the event on the server,
+    // as if it had focus.
+    fn allow_forward(&mut self, _to: &str) -> bool {
+        true
+    }
+
+    // Called before clipboard content is handed from one machine to another, None standing for the server itself.
+    // Returning false drops the content.
+    fn allow_clipboard(&mut self, _from: Option<&str>, _to: Option<&str>, _mime_type: &str, _data: &[u8]) -> bool {
+        true
+    }
+}
@@ -0,0 +1,67 @@
+use input::{Axis, Event};
+
+// Scales pointer motion going to a client by the ratio of its desktop scale to the server's, so that the pointer
+// covers the same distance on a high DPI laptop as on a regular desktop. The fractions lost to rounding are carried
+// over per axis.
+pub(crate) struct Scale {
+    factor: f64,
+    x: f64,
+    y: f64,
+}
+
+impl Scale {
+    pub fn new(factor: f64) -> Self {
+        Self { factor, x: 0.0, y: 0.0 }
+    }
+
+    pub fn apply(&mut self, event: Event) -> Event {
+        let (axis, delta) = match event {
+            Event::MouseMove { axis, delta } => (axis, delta),
+            event => return event,
+        };
+
+        let remainder = match axis {
+            Axis::X => &mut self.x,
+            Axis::Y => &mut self.y,
+        };
+
+        let total = f64::from(delta) * self.factor + *remainder;
+        let delta = total.trunc();
+        *remainder = total - delta;
+
+        Event::MouseMove {
+            axis,
+            delta: delta as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delta(event: Event) -> i32 {
+        match event {
+            Event::MouseMove { delta, .. } => delta,
+            _ => panic!("Not motion"),
+        }
+    }
+
+    fn motion(delta: i32) -> Event {
+        Event::MouseMove { axis: Axis::Y, delta }
+    }
+
+    #[test]
+    fn motion_is_scaled() {
+        let mut scale = Scale::new(2.0);
+        assert_eq!(delta(scale.apply(motion(3))), 6);
+        assert_eq!(delta(scale.apply(motion(-3))), -6);
+    }
+
+    #[test]
+    fn slow_motion_adds_up() {
+        let mut scale = Scale::new(0.5);
+        assert_eq!(delta(scale.apply(motion(1))), 0);
+        assert_eq!(delta(scale.apply(motion(1))), 1);
+    }
+}
@@ -0,0 +1,23 @@
+use input::{Event, KeyKind};
+
+// Which events go to the focused client, the rest always stays with the server. Useful when every machine has a mouse
+// of its own but they share one keyboard, or the other way around.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Sharing {
+    #[default]
+    All,
+    Keyboard,
+    Mouse,
+}
+
+impl Sharing {
+    // Mouse buttons count as mouse events even though they arrive as keys.
+    pub fn shares(self, event: &Event) -> bool {
+        let keyboard = matches!(event, Event::Key { kind: KeyKind::Key(_), .. } | Event::Scan { .. });
+        match self {
+            Self::All => true,
+            Self::Keyboard => keyboard,
+            Self::Mouse => !keyboard,
+        }
+    }
+}
@@ -0,0 +1,2021 @@
+use crate::acceleration::Acceleration;
+use crate::axis_mask::{self, RelativeAxis};
+use crate::connection::{Timing, Transfer};
+use crate::duplicate_names::DuplicateNames;
+use crate::hotkey::{Action, Hotkeys};
+use crate::mouse_keys::{self, MouseKeys};
+use crate::path_translation::PathTranslation;
+use crate::policy::Policy;
+use crate::scale::Scale;
+use crate::sharing::Sharing;
+use crate::switch_policy::SwitchPolicy;
+use crate::unsupported::Unsupported;
+use input::osd::OsdCommand;
+use input::pointer::Corner;
+use input::{clipboard, Capabilities, Direction, Event, Key, KeyKind, LockState, Manager};
+use net::control::{ClientStatus, Notification, SwitchRecord};
+use net::wol::{self, MacAddress};
+use net::{Handshake, Message, SystemInfo};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::{Error, ErrorKind};
+use std::mem;
+use std::net::SocketAddr;
+use std::ops::Bound;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::UnboundedSender;
+
+// How often activity is passed on to the machines which don't have focus, see State::sync_activity.
+pub(crate) const ACTIVITY_INTERVAL: Duration = Duration::from_secs(30);
+
+// How often clients are checked for having been idle too long, see State::disconnect_idle.
+pub(crate) const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+// How many focus changes are kept for undoing and rkvm-ctl history.
+const HISTORY_LENGTH: usize = 20;
+
+// Assigned in order of connection, never reused.
+pub(crate) type ClientId = u64;
+
+#[derive(Clone, Debug)]
+pub(crate) struct Client {
+    pub id: ClientId,
+    pub name: String,
+    pub address: SocketAddr,
+    pub connected_since: SystemTime,
+    pub sender: UnboundedSender<Message>,
+    // Updated by the connection task.
+    pub timing: Arc<Mutex<Timing>>,
+}
+
+pub(crate) struct State<M> {
+    pub hostname: String,
+    pub manager: M,
+    pub osd: OsdCommand,
+    pub clients: BTreeMap<ClientId, Client>,
+    // The focused client, None if the server itself has focus.
+    pub focus: Option<ClientId>,
+    // The last focus changes, oldest first.
+    pub history: VecDeque<SwitchRecord>,
+    pub paused: bool,
+    // Set while clipboard content isn't synced at all, e.g. while copying passwords locally.
+    pub clipboard_paused: bool,
+    pub notifications: broadcast::Sender<Notification>,
+    pub hotkeys: Hotkeys,
+    pub mouse_keys: Option<MouseKeys>,
+    pub focus_led: bool,
+    pub park_corner: Option<Corner>,
+    pub sync_activity: bool,
+    // The last direction seen for each key, to drop duplicates some devices send.
+    pub key_states: HashMap<KeyKind, Direction>,
+    // The last local input, used to tell whether the user is still around.
+    pub last_activity: Option<Instant>,
+    // Set while events can't be written locally, so the user is only told once.
+    pub local_failing: bool,
+    pub policies: Vec<Box<dyn Policy>>,
+    // Set while switches and forwarding aren't subject to policies, for the administrator.
+    pub access_override: bool,
+    pub sharing: Sharing,
+    pub duplicate_names: DuplicateNames,
+    pub switch_policy: SwitchPolicy,
+    // The scancode reported right before the key event being handled.
+    pub pending_scan: Option<Event>,
+    // Keys which always stay with this machine, whichever has focus.
+    pub local_keys: HashSet<Key>,
+    // Axes dropped for the clients with the given names.
+    pub axis_masks: HashMap<String, HashSet<RelativeAxis>>,
+    // Keys replaced with others on their way to the client with the given name.
+    pub key_substitutions: HashMap<String, HashMap<Key, Key>>,
+    // Only applied to motion going to clients, the server accelerates its own.
+    pub acceleration: Option<Acceleration>,
+    pub display_scale: f64,
+    // Motion scaling for the clients which reported their desktop scale.
+    pub scales: HashMap<ClientId, Scale>,
+    pub sanitize_clipboard: bool,
+    // Applied in order to clipboard text moving between machines.
+    pub path_translations: Vec<PathTranslation>,
+    // The client whose clipboard offer is current, None if it's the server's clipboard.
+    pub clipboard_owner: Option<ClientId>,
+    // Passed to notifications with buttons, see handle_action.
+    pub actions: UnboundedSender<String>,
+    // Named sets of client names, the active profile restricts cycling to one of them.
+    pub groups: BTreeMap<String, HashSet<String>>,
+    pub profile: Option<String>,
+    // Commands the clients with the given names are asked to run when they get focus.
+    pub focus_commands: HashMap<String, String>,
+    // Where to send the magic packet waking the clients with the given names.
+    pub wake_addresses: HashMap<String, MacAddress>,
+    // The last client suspended, which the wake hotkey wakes up.
+    pub suspended: Option<String>,
+    // The last system info reported by each client, and when it arrived.
+    pub system_infos: HashMap<ClientId, (SystemInfo, Instant)>,
+    // What each client said about itself after the hello.
+    pub handshakes: HashMap<ClientId, Handshake>,
+    // Clients without focus or traffic for this long are disconnected.
+    pub idle_timeout: Option<Duration>,
+    // When each client last had focus or sent anything besides keepalives and system info.
+    pub last_used: HashMap<ClientId, Instant>,
+    // What each client's virtual device can write, for clients which have said.
+    pub capabilities: HashMap<ClientId, Capabilities>,
+    // What has been dropped for each client for not being supported, so that it's only logged once.
+    pub unsupported: HashSet<(ClientId, Unsupported)>,
+    // The keys each client has been sent down and not yet up, as they were sent. Unlike key_states, this leaves out
+    // the keys which stayed here, e.g. local keys, mouse keys or keys pressed while paused, and has substituted keys
+    // as they were substituted.
+    pub forwarded: HashMap<ClientId, HashSet<KeyKind>>,
+}
+
+impl<M: Manager> State<M> {
+    // The clipboard exchange is relayed between the owner of the content and whichever machine has focus,
+    // with the server taking either role itself. The same goes for the lock state a client reports when losing focus.
+    pub async fn handle_message(&mut self, id: ClientId, message: Message) -> Result<(), Error> {
+        // System info is sent periodically, like the keepalives it says nothing about the client being used.
+        if !matches!(message, Message::SystemInfo(_)) {
+            self.last_used.insert(id, Instant::now());
+        }
+
+        match message {
+            Message::ClipboardOffer(mime_types) => {
+                self.clipboard_owner = Some(id);
+                match self.focus {
+                    Some(focus) if focus != id => self.send(focus, Message::ClipboardOffer(mime_types)),
+                    Some(_) => {}
+                    None => {
+                        if let Some(mime_type) = clipboard::pick(&mime_types) {
+                            self.send(id, Message::ClipboardRequest(mime_type));
+                        }
+                    }
+                }
+            }
+            Message::ClipboardRequest(mime_type) => match self.clipboard_owner {
+                Some(owner) => self.send(owner, Message::ClipboardRequest(mime_type)),
+                None => {
+                    if let Some(data) = clipboard::get(&mime_type) {
+                        if self.allow_clipboard(None, Some(id), &mime_type, &data) {
+                            let data = self.translate_paths(None, Some(id), &mime_type, data);
+                            self.send(id, Message::ClipboardData(mime_type, data));
+                        }
+                    }
+                }
+            },
+            Message::ClipboardData(mime_type, data) => {
+                if !self.allow_clipboard(Some(id), self.focus, &mime_type, &data) {
+                    return Ok(());
+                }
+
+                let data = self.translate_paths(Some(id), self.focus, &mime_type, data);
+                match self.focus {
+                    Some(focus) => self.send(focus, Message::ClipboardData(mime_type, data)),
+                    None => clipboard::set(&mime_type, data, self.sanitize_clipboard),
+                }
+            }
+            Message::LockState(wanted) => match self.focus {
+                Some(focus) if focus != id => self.send(focus, Message::LockState(wanted)),
+                Some(_) => {}
+                None => {
+                    if let Some(current) = self.manager.lock_state() {
+                        for event in current.toggles(wanted) {
+                            self.write_local(event).await;
+                        }
+                    }
+                }
+            },
+            // Goes through the policies like any other switch.
+            Message::RequestFocus(true) => {
+                log::info!("Client {} requested focus", id);
+                self.switch(Some(id));
+            }
+            Message::RequestFocus(false) if self.focus == Some(id) => {
+                log::info!("Client {} handed focus back", id);
+                self.switch(None);
+            }
+            // Only the focused client should have anything held, the others are told to release everything.
+            Message::RequestKeyState => {
+                log::info!("Client {} missed events, sending the keys held down", id);
+                let held = if self.focus == Some(id) {
+                    self.forwarded.get(&id).map(|keys| keys.iter().copied().collect()).unwrap_or_default()
+                } else {
+                    self.forwarded.remove(&id);
+                    Vec::new()
+                };
+                self.send(id, Message::KeyStateSync(held));
+            }
+            Message::SystemInfo(info) => {
+                log::debug!("Client {} reported {:?}", id, info);
+                self.system_infos.insert(id, (info, Instant::now()));
+            }
+            Message::Handshake(handshake) => {
+                log::info!("Client {} runs on {} and supports {:?}", id, handshake.platform, handshake.features);
+                if let Some(scale) = handshake.display_scale {
+                    self.set_display_scale(id, scale);
+                }
+                self.handshakes.insert(id, handshake);
+            }
+            Message::Capabilities(capabilities) => {
+                log::debug!("Client {} can write {} keys", id, capabilities.keys.len());
+                self.unsupported.retain(|(client, _)| *client != id);
+                self.capabilities.insert(id, capabilities);
+            }
+            Message::Goodbye => log::info!("Client {} is shutting down", id),
+            // Sent by clients from before the handshake.
+            Message::DisplayScale(scale) => self.set_display_scale(id, scale),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn set_display_scale(&mut self, id: ClientId, scale: f64) {
+        if !scale.is_finite() || scale <= 0.0 {
+            log::warn!("Client {} sent an invalid display scale of {}", id, scale);
+            return;
+        }
+
+        let factor = scale / self.display_scale;
+        log::info!("Client {} has a display scale of {}, scaling its motion by {:.2}", id, scale, factor);
+        self.scales.insert(id, Scale::new(factor));
+    }
+
+    // Clipboard transfers are reported to subscribers as they go, and announced when they start and end.
+    pub fn handle_transfer(&mut self, id: ClientId, transfer: Transfer) {
+        let name = match self.clients.get(&id) {
+            Some(client) => client.name.clone(),
+            None => return,
+        };
+
+        let (outgoing, done, total) = match transfer {
+            Transfer::Sent { done, total } => (true, done, total),
+            Transfer::Received { done, total } => (false, done, total),
+            Transfer::Cancelled => {
+                log::info!("Clipboard transfer with {} cancelled", name);
+                self.manager.notify(format!("Clipboard transfer with {} cancelled", name));
+                let _ = self.notifications.send(Notification::TransferCancelled { id, name });
+                return;
+            }
+        };
+
+        let size = net::clipboard::describe(total);
+        let (to, from) = if outgoing { ("to", "Sending") } else { ("from", "Receiving") };
+        if done == total {
+            self.manager.notify(format!("Clipboard transfer {} {} done ({})", to, name, size));
+        } else if done <= net::clipboard::CHUNK_SIZE as u64 {
+            self.manager.notify(format!("{} {} of clipboard content {} {}", from, size, to, name));
+        }
+
+        let _ = self.notifications.send(Notification::Transfer { id, name, outgoing, done, total });
+    }
+
+    // Stops all clipboard transfers in progress, see Message::CancelTransfer.
+    fn cancel_transfers(&self) {
+        for client in self.clients.values() {
+            if let Err(e) = client.sender.send(Message::CancelTransfer) {
+                log::warn!("{:?}", e);
+            }
+        }
+    }
+
+    fn allow_clipboard(&mut self, from: Option<ClientId>, to: Option<ClientId>, mime_type: &str, data: &[u8]) -> bool {
+        let clients = &self.clients;
+        let name = |id: Option<ClientId>| id.and_then(|id| clients.get(&id)).map(|client| client.name.as_str());
+        let (from, to) = (name(from), name(to));
+        if self.clipboard_paused {
+            log::info!("Clipboard content from {:?} to {:?} blocked while clipboard sync is paused", from, to);
+            return false;
+        }
+
+        if self.policies.iter_mut().all(|policy| policy.allow_clipboard(from, to, mime_type, data)) {
+            return true;
+        }
+
+        log::info!("Clipboard content from {:?} to {:?} blocked by policy", from, to);
+        false
+    }
+
+    fn translate_paths(&self, from: Option<ClientId>, to: Option<ClientId>, mime_type: &str, data: Vec<u8>) -> Vec<u8> {
+        if self.path_translations.is_empty() || mime_type != clipboard::TEXT {
+            return data;
+        }
+
+        let mut text = match String::from_utf8(data) {
+            Ok(text) => text,
+            Err(err) => return err.into_bytes(),
+        };
+
+        let name = |id: Option<ClientId>| {
+            id.and_then(|id| self.clients.get(&id))
+                .map(|client| client.name.as_str())
+                .unwrap_or(&self.hostname)
+        };
+        let (from, to) = (name(from), name(to));
+        for translation in self.path_translations.iter().filter(|translation| translation.applies(from, to)) {
+            text = translation.apply(&text);
+        }
+
+        text.into_bytes()
+    }
+
+    fn send(&self, id: ClientId, message: Message) {
+        if let Some(client) = self.clients.get(&id) {
+            if let Err(e) = client.sender.send(message) {
+                log::warn!("{:?}", e);
+            }
+        }
+    }
+
+    // Handles an event from the local input devices, either as a hotkey or by forwarding it.
+    // Returns false once the kill hotkey has been pressed.
+    pub async fn handle_local(&mut self, event: Event) -> Result<bool, Error> {
+        self.last_activity = Some(Instant::now());
+        // Scancodes come right before the key they belong to and go wherever it goes, see forward.
+        let scan = match event {
+            Event::Scan { .. } => {
+                self.pending_scan = Some(event);
+                return Ok(true);
+            }
+            Event::Switch { code, on } => {
+                self.handle_switch(event, code, on).await?;
+                return Ok(true);
+            }
+            _ => self.pending_scan.take(),
+        };
+        // A key going down twice confuses the chords and the devices events are written to.
+        if let Event::Key { direction, kind } = event {
+            if self.key_states.insert(kind, direction) == Some(direction) {
+                log::debug!("Dropping duplicate {:?}", event);
+                return Ok(true);
+            }
+        }
+
+        let action = match event {
+            Event::Key { direction, kind: KeyKind::Key(key) } => self.hotkeys.update(key, direction),
+            _ => None,
+        };
+
+        match action {
+            Some(Action::Switch) => {
+                self.switch(self.next());
+                return Ok(true);
+            }
+            Some(Action::Kill) => return Ok(false),
+            Some(Action::Suspend) => {
+                self.suspend_focused();
+                return Ok(true);
+            }
+            Some(Action::Wake) => {
+                if let Some(name) = self.suspended.clone() {
+                    if let Err(e) = self.wake(&name) {
+                        log::warn!("Failed to wake {}: {}", name, e);
+                        self.manager.notify(format!("Failed to wake {}: {}", name, e));
+                    }
+                }
+                return Ok(true);
+            }
+            Some(Action::UndoSwitch) => {
+                if let Err(e) = self.undo_switch() {
+                    log::info!("Not undoing the switch: {}", e);
+                }
+                return Ok(true);
+            }
+            // Typing it wouldn't work, the server's own system would catch it.
+            Some(Action::SecureAttention) => {
+                match self.focus {
+                    Some(focus) => {
+                        log::info!("Sending the secure attention sequence to client {}", focus);
+                        self.send(focus, Message::SecureAttention);
+                    }
+                    None => log::info!("Ignoring the secure attention keys, the server has focus"),
+                }
+                return Ok(true);
+            }
+            Some(Action::LockAll) => {
+                log::info!("Locking all machines");
+                for client in self.clients.values() {
+                    if let Err(e) = client.sender.send(Message::Lock) {
+                        log::warn!("{:?}", e);
+                    }
+                }
+                self.manager.lock_session();
+                return Ok(true);
+            }
+            Some(Action::OverrideAccess) => {
+                self.access_override = !self.access_override;
+                let state = if self.access_override { "overridden" } else { "enforced" };
+                log::info!("Access policies {}", state);
+                self.manager.notify(format!("Access policies {}", state));
+                return Ok(true);
+            }
+            Some(Action::NextProfile) => {
+                self.next_profile();
+                return Ok(true);
+            }
+            Some(Action::Pick) => {
+                self.show_picker();
+                return Ok(true);
+            }
+            Some(Action::Roster) => {
+                let roster = self.roster();
+                self.manager.notify(roster);
+                return Ok(true);
+            }
+            Some(Action::CancelTransfer) => {
+                self.cancel_transfers();
+                return Ok(true);
+            }
+            Some(Action::ToggleClipboard) => {
+                self.clipboard_paused = !self.clipboard_paused;
+                if self.clipboard_paused {
+                    self.cancel_transfers();
+                }
+
+                let state = if self.clipboard_paused { "paused" } else { "resumed" };
+                log::info!("Clipboard sync {}", state);
+                self.manager.notify(format!("Clipboard sync {}", state));
+                return Ok(true);
+            }
+            Some(Action::Pause) => {
+                self.paused = !self.paused;
+                self.update_indicator();
+
+                let paused = self.paused;
+                log::info!("Forwarding {}", if paused { "paused" } else { "resumed" });
+                self.manager.notify(if paused { "Forwarding paused" } else { "Forwarding resumed" }.to_string());
+                return Ok(true);
+            }
+            None => {}
+        }
+
+        let event = match self.policies.iter_mut().try_fold(event, |event, policy| policy.filter_event(event)) {
+            Some(event) => event,
+            None => return Ok(true),
+        };
+
+        if let Event::Key { direction, kind: KeyKind::Key(key) } = event {
+            if self.mouse_keys.is_some() && mouse_keys::handles(key) {
+                // Mouse keys are on while NumLock is off, with it on the numpad types digits as usual. The local lock
+                // state follows the focused client's.
+                let num_lock = matches!(self.manager.lock_state(), Some(LockState { num_lock: true, .. }));
+                let translated = self
+                    .mouse_keys
+                    .as_mut()
+                    .and_then(|mouse_keys| mouse_keys.translate(key, direction, !num_lock));
+                if let Some(events) = translated {
+                    for event in events {
+                        self.forward(event).await?;
+                    }
+                    return Ok(true);
+                }
+            }
+        }
+
+        self.pending_scan = scan;
+        self.forward(event).await?;
+        Ok(true)
+    }
+
+    pub async fn forward(&mut self, event: Event) -> Result<(), Error> {
+        let scan = self
+            .pending_scan
+            .take()
+            .filter(|_| matches!(event, Event::Key { kind: KeyKind::Key(_), .. }));
+        // While paused, everything goes to the local machine but the connections are kept alive.
+        let clients = &self.clients;
+        let focused = self.focus.and_then(|id| clients.get(&id));
+        let (override_access, policies) = (self.access_override, &mut self.policies);
+        let focused = focused
+            .filter(|client| override_access || policies.iter_mut().all(|policy| policy.allow_forward(&client.name)));
+        let local = matches!(event, Event::Key { kind: KeyKind::Key(key), .. } if self.local_keys.contains(&key));
+        if let (Some(client), false, true, false) = (focused, self.paused, self.sharing.shares(&event), local) {
+            let id = client.id;
+            if let Some(mask) = self.axis_masks.get(&client.name) {
+                if axis_mask::is_masked(mask, &event) {
+                    log::debug!("Dropping {:?} masked for client {}", event, id);
+                    return Ok(());
+                }
+            }
+
+            let original = event;
+            let event = match (event, self.key_substitutions.get(&client.name)) {
+                (Event::Key { direction, kind: KeyKind::Key(key) }, Some(substitutions)) => Event::Key {
+                    direction,
+                    kind: KeyKind::Key(*substitutions.get(&key).unwrap_or(&key)),
+                },
+                (event, _) => event,
+            };
+            if let Some(unsupported) = self.capabilities.get(&id).and_then(|capabilities| Unsupported::of(capabilities, &event)) {
+                if self.unsupported.insert((id, unsupported)) {
+                    log::warn!("Client {} can't write {:?}, dropping it", client.name, unsupported);
+                }
+                return Ok(());
+            }
+
+            // The scancode of a substituted key would be the one of the key it replaces.
+            let substituted = matches!((original, event), (Event::Key { kind: from, .. }, Event::Key { kind: to, .. }) if from != to);
+            if let Some(scan) = scan.filter(|_| !substituted) {
+                let _ = client.sender.send(Message::Event(scan));
+            }
+            let event = match &mut self.acceleration {
+                Some(acceleration) => acceleration.apply(event),
+                None => event,
+            };
+            let event = match self.scales.get_mut(&id) {
+                Some(scale) => scale.apply(event),
+                None => event,
+            };
+            if let Err(e) = client.sender.send(Message::Event(event)) {
+                log::warn!("{:?}.  Removing client {}", e, id);
+                self.remove(id);
+            } else {
+                log::debug!("Send client {} {:?}", id, event);
+                if let Event::Key { direction, kind } = event {
+                    let forwarded = self.forwarded.entry(id).or_default();
+                    match direction {
+                        Direction::Down => forwarded.insert(kind),
+                        Direction::Up => forwarded.remove(&kind),
+                    };
+                }
+                return Ok(());
+            }
+        }
+
+        log::debug!("Send manager {:?}", event);
+        if let Some(scan) = scan {
+            self.write_local(scan).await;
+        }
+        self.write_local(event).await;
+        Ok(())
+    }
+
+    // A virtual device which has gone away is recreated by the manager. If writing still fails the event is dropped, a
+    // broken virtual device shouldn't take the connections to the clients down with it.
+    async fn write_local(&mut self, event: Event) {
+        match self.manager.write(event).await {
+            Ok(()) => {
+                if mem::take(&mut self.local_failing) {
+                    log::info!("Writing local events works again");
+                }
+            }
+            Err(e) => {
+                log::error!("Dropping local event: {}", e);
+                if !self.local_failing {
+                    self.local_failing = true;
+                    self.manager.notify(format!("Local input is failing: {}", e));
+                }
+            }
+        }
+    }
+
+    // Called when the server is stopped on purpose. The keys held down are released wherever they went, so that none
+    // stays stuck, and the clients are told the server is going away rather than dropping off the network. Dropping
+    // them closes their connections once the goodbye is out.
+    pub async fn shutdown(&mut self) {
+        // Clients only get the keys they were sent released, see forwarded.
+        for (id, keys) in mem::take(&mut self.forwarded) {
+            for kind in keys {
+                let event = Event::Key {
+                    direction: Direction::Up,
+                    kind,
+                };
+                self.send(id, Message::Event(event));
+            }
+        }
+
+        if self.focus.is_none() {
+            let held: Vec<_> = self
+                .key_states
+                .iter()
+                .filter(|(_, direction)| **direction == Direction::Down)
+                .map(|(kind, _)| *kind)
+                .collect();
+            for kind in held {
+                let event = Event::Key {
+                    direction: Direction::Up,
+                    kind,
+                };
+                self.write_local(event).await;
+            }
+        }
+
+        log::info!("Saying goodbye to {} clients", self.clients.len());
+        for client in self.clients.values() {
+            let _ = client.sender.send(Message::Goodbye);
+        }
+        self.clients.clear();
+    }
+
+    // Called every IDLE_CHECK_INTERVAL. Drops the clients which have been idle for longer than the idle timeout, to
+    // keep the list of clients down to the ones in use. They are let in again like any other client when they
+    // reconnect.
+    pub fn disconnect_idle(&mut self) {
+        let timeout = match self.idle_timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+
+        if let Some(focus) = self.focus {
+            self.last_used.insert(focus, Instant::now());
+        }
+
+        let idle: Vec<_> = self
+            .last_used
+            .iter()
+            .filter(|(id, last)| Some(**id) != self.focus && last.elapsed() >= timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in idle {
+            if let Some(client) = self.clients.get(&id) {
+                log::info!("Disconnecting client {}, idle for {:?}", client.name, timeout);
+                let _ = client.sender.send(Message::Notify("Disconnected for being idle".to_owned()));
+            }
+            // Dropping the client closes its connection.
+            self.remove(id);
+        }
+    }
+
+    // Called every ACTIVITY_INTERVAL. While the user is active, the machines without focus are kept from blanking
+    // their screens or locking, including the server itself.
+    pub async fn sync_activity(&mut self) -> Result<(), Error> {
+        let active = self.last_activity.map(|last| last.elapsed() < ACTIVITY_INTERVAL).unwrap_or(false);
+        if !self.sync_activity || !active {
+            return Ok(());
+        }
+
+        for client in self.clients.values().filter(|client| Some(client.id) != self.focus) {
+            if let Err(e) = client.sender.send(Message::Activity) {
+                log::warn!("{:?}", e);
+            }
+        }
+
+        if self.focus.is_some() {
+            for event in input::pointer::nudge() {
+                self.write_local(event).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Called once focus has moved from the server to a client.
+    pub async fn park_pointer(&mut self) -> Result<(), Error> {
+        // The pointer stays in use on the server if the mouse isn't shared.
+        if self.sharing == Sharing::Keyboard {
+            return Ok(());
+        }
+
+        if let Some(corner) = self.park_corner {
+            for event in corner.events() {
+                self.write_local(event).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    // The machine following the focused one in connection order, wrapping around to the server.
+    // Clients outside the active profile are skipped, the server is always part of it.
+    pub fn next(&self) -> Option<ClientId> {
+        let start = self.focus.map(|id| id + 1).unwrap_or(0);
+        self.clients
+            .range(start..)
+            .map(|(_, client)| client)
+            .find(|client| self.in_profile(client))
+            .map(|client| client.id)
+    }
+
+    fn in_profile(&self, client: &Client) -> bool {
+        match self.profile.as_ref().and_then(|profile| self.groups.get(profile)) {
+            Some(group) => group.contains(&client.name),
+            None => true,
+        }
+    }
+
+    // None makes all machines take part in cycling again.
+    pub fn set_profile(&mut self, profile: Option<&str>) -> Result<(), Error> {
+        if let Some(profile) = profile {
+            if !self.groups.contains_key(profile) {
+                return Err(Error::new(ErrorKind::NotFound, format!("No group named {}", profile)));
+            }
+        }
+
+        self.profile = profile.map(ToOwned::to_owned);
+        let message = match &self.profile {
+            Some(profile) => format!("Profile {}", profile),
+            None => "All machines".to_owned(),
+        };
+        log::info!("{}", message);
+        self.manager.notify(message);
+        Ok(())
+    }
+
+    // Goes through the groups in order of their names, and then back to all machines.
+    fn next_profile(&mut self) {
+        let next = match &self.profile {
+            Some(profile) => self
+                .groups
+                .range::<String, _>((Bound::Excluded(profile), Bound::Unbounded))
+                .next(),
+            None => self.groups.iter().next(),
+        };
+
+        let next = next.map(|(name, _)| name.clone());
+        let _ = self.set_profile(next.as_deref());
+    }
+
+    pub fn switch(&mut self, target: Option<ClientId>) {
+        let previous = self.focus;
+        if previous == target {
+            return;
+        }
+
+        let clients = &self.clients;
+        let name = |id: Option<ClientId>| id.and_then(|id| clients.get(&id)).map(|client| client.name.as_str());
+        let (from, to) = (name(previous), name(target));
+        if !self.access_override && !self.policies.iter_mut().all(|policy| policy.allow_switch(from, to)) {
+            log::info!("Switch to client {:?} denied by policy", target);
+            return;
+        }
+
+        self.focus = target;
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(SwitchRecord {
+            from: from.unwrap_or(&self.hostname).to_owned(),
+            to: to.unwrap_or(&self.hostname).to_owned(),
+            at: SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0),
+        });
+        let _ = self.notifications.send(Notification::Focused {
+            id: target,
+            name: to.unwrap_or(&self.hostname).to_owned(),
+        });
+        log::info!("Switching to client {:?} from {:?}", target, previous);
+
+        let clients = &self.clients;
+        let previous = previous.and_then(|id| clients.get(&id));
+        if let Some(client) = previous {
+            if let Err(e) = client.sender.send(Message::Focus(false)) {
+                log::warn!("{:?}", e);
+            }
+        }
+
+        let back = format!("switch:{}", previous.map(|client| client.name.as_str()).unwrap_or(&self.hostname));
+        let target = target.and_then(|id| clients.get(&id));
+        match target {
+            None => {
+                self.osd.show(&self.hostname);
+                self.manager.notify("I'm over here now!".to_string());
+            }
+            Some(client) => {
+                self.osd.hide();
+                if let Err(e) = client.sender.send(Message::Focus(true)) {
+                    log::warn!("{:?}", e);
+                }
+                if let Some(command) = self.focus_commands.get(&client.name) {
+                    log::debug!("Asking client {} to run {}", client.id, command);
+                    let _ = client.sender.send(Message::RunCommand(command.clone()));
+                }
+                if let Err(e) = client.sender.send(Message::Notify("I'm over here now!".to_string())) {
+                    log::warn!("{:?}", e);
+                } else {
+                    let callback = self.action_callback();
+                    self.manager.notify_with_actions(
+                        format!("Switched to {}", client.name),
+                        &[(&back, "Switch back"), ("next", "Go to next")],
+                        callback,
+                    );
+                    log::debug!("Notify client {}", client.id);
+                }
+            }
+        }
+
+        // A client losing focus reports its lock state by itself.
+        if let (None, Some(client), Some(state)) = (previous, target, self.manager.lock_state()) {
+            if let Err(e) = client.sender.send(Message::LockState(state)) {
+                log::warn!("{:?}", e);
+            }
+        }
+
+        // The machine losing focus owns the clipboard now. The server offers its content right away,
+        // a client is asked to and its offer is relayed once it arrives.
+        match previous {
+            None => {
+                self.clipboard_owner = None;
+                let mime_types = clipboard::formats();
+                if let (Some(client), false) = (target, mime_types.is_empty()) {
+                    if let Err(e) = client.sender.send(Message::ClipboardOffer(mime_types)) {
+                        log::warn!("{:?}", e);
+                    }
+                }
+            }
+            Some(client) => {
+                if let Err(e) = client.sender.send(Message::GetClipboardData) {
+                    log::warn!("{:?}", e);
+                }
+            }
+        }
+
+        self.update_indicator();
+    }
+
+    pub fn add(&mut self, mut client: Client) {
+        let existing = self.clients.values().find(|existing| existing.name == client.name).map(|existing| existing.id);
+        if let Some(existing) = existing {
+            match self.duplicate_names {
+                DuplicateNames::Replace => {
+                    log::info!("Client {} reconnected, dropping its old connection", client.name);
+                    // Dropping the client closes its connection.
+                    self.remove(existing);
+                }
+                DuplicateNames::Reject => {
+                    log::warn!("Turning away client {}, one with the same name is already connected", client.name);
+                    let _ = client.sender.send(Message::Notify(format!("A client named {} is already connected", client.name)));
+                    return;
+                }
+                DuplicateNames::Suffix => {
+                    let name = (2..)
+                        .map(|number| format!("{} ({})", client.name, number))
+                        .find(|name| self.clients.values().all(|existing| existing.name != *name))
+                        .unwrap();
+                    log::info!("Client {} is already connected, naming the new one {}", client.name, name);
+                    client.name = name;
+                }
+            }
+        }
+
+        // Lets the client build a virtual device which can produce everything the grabbed devices can.
+        if let Some(capabilities) = self.manager.capabilities() {
+            let _ = client.sender.send(Message::Capabilities(capabilities));
+        }
+
+        self.last_used.insert(client.id, Instant::now());
+        self.manager.notify(format!("{} connected", client.name));
+        let _ = self.notifications.send(Notification::Connected {
+            id: client.id,
+            name: client.name.clone(),
+            address: client.address,
+        });
+
+        self.clients.insert(client.id, client);
+    }
+
+    // Drops a client whose connection has ended, focus returns to the server if it was on it.
+    pub fn remove(&mut self, id: ClientId) {
+        let client = match self.clients.remove(&id) {
+            Some(client) => client,
+            None => return,
+        };
+
+        if self.clipboard_owner == Some(id) {
+            self.clipboard_owner = None;
+        }
+        self.scales.remove(&id);
+        self.system_infos.remove(&id);
+        self.handshakes.remove(&id);
+        self.last_used.remove(&id);
+        self.capabilities.remove(&id);
+        self.unsupported.retain(|(client, _)| *client != id);
+        self.forwarded.remove(&id);
+
+        log::info!("Removing disconnected client {}", client.name);
+        let _ = self.notifications.send(Notification::Disconnected {
+            id,
+            name: client.name.clone(),
+        });
+
+        if self.focus == Some(id) {
+            self.focus = None;
+            let _ = self.notifications.send(Notification::Focused {
+                id: None,
+                name: self.hostname.clone(),
+            });
+            self.osd.show(&self.hostname);
+            self.update_indicator();
+            self.manager.notify(format!("{} disconnected, I'm over here now!", client.name));
+        } else {
+            self.manager.notify(format!("{} disconnected", client.name));
+        }
+    }
+
+    // The server can be addressed by its hostname.
+    pub fn switch_to_name(&mut self, name: &str) -> Result<(), Error> {
+        let target = if name == self.hostname {
+            None
+        } else {
+            match self.clients.values().find(|client| client.name == name) {
+                Some(client) => Some(client.id),
+                None => return Err(Error::new(ErrorKind::NotFound, format!("No client named {}", name))),
+            }
+        };
+
+        self.switch(target);
+        Ok(())
+    }
+
+    async fn handle_switch(&mut self, event: Event, code: u16, on: bool) -> Result<(), Error> {
+        match &self.switch_policy {
+            SwitchPolicy::Local => self.write_local(event).await,
+            SwitchPolicy::Drop => log::debug!("Dropping {:?}", event),
+            SwitchPolicy::Forward => self.forward(event).await?,
+            SwitchPolicy::Hook(command) => {
+                let (program, args) = match command.split_first() {
+                    Some(split) => split,
+                    None => return Ok(()),
+                };
+
+                log::info!("Running {} for switch {} turning {}", program, code, if on { "on" } else { "off" });
+                let mut command = process::Command::new(program);
+                command.args(args).arg(code.to_string()).arg(if on { "1" } else { "0" });
+                // Waited for on a thread of its own, so that a slow hook doesn't hold up input.
+                thread::spawn(move || match command.status() {
+                    Ok(status) if !status.success() => log::warn!("Switch hook failed with {}", status),
+                    Ok(_) => {}
+                    Err(e) => log::warn!("Failed to run the switch hook: {}", e),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // Goes back to where focus was before the last switch. Undoing isn't a switch of its own in the history, so undoing
+    // again goes further back instead of back and forth.
+    pub fn undo_switch(&mut self) -> Result<(), Error> {
+        let last = self
+            .history
+            .pop_back()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "No switch to undo"))?;
+        let length = self.history.len();
+        if let Err(e) = self.switch_to_name(&last.from) {
+            self.history.push_back(last);
+            return Err(e);
+        }
+
+        if self.history.len() > length {
+            self.history.pop_back();
+        } else if self.focus_name() != last.from {
+            self.history.push_back(last);
+            return Err(Error::new(ErrorKind::PermissionDenied, "Switch denied by policy"));
+        }
+
+        Ok(())
+    }
+
+    fn focus_name(&self) -> &str {
+        self.focus
+            .and_then(|id| self.clients.get(&id))
+            .map(|client| client.name.as_str())
+            .unwrap_or(&self.hostname)
+    }
+
+    // Focus comes back to the server, there's no point in typing into a sleeping machine.
+    fn suspend_focused(&mut self) {
+        let client = match self.focus.and_then(|id| self.clients.get(&id)) {
+            Some(client) => client,
+            None => return,
+        };
+
+        log::info!("Suspending client {}", client.id);
+        let (id, name) = (client.id, client.name.clone());
+        self.send(id, Message::Suspend);
+        self.switch(None);
+        self.manager.notify(format!("Suspending {}", name));
+        self.suspended = Some(name);
+    }
+
+    // Only works for clients with a known MAC address, on the same network as the server.
+    pub fn wake(&mut self, name: &str) -> Result<(), Error> {
+        let address = match self.wake_addresses.get(name) {
+            Some(address) => *address,
+            None => return Err(Error::new(ErrorKind::NotFound, format!("No MAC address known for {}", name))),
+        };
+
+        wol::wake(address)?;
+        log::info!("Sent a wake-up packet to {} ({})", name, address);
+        self.manager.notify(format!("Waking {}", name));
+        Ok(())
+    }
+
+    // Whether the command is allowed and what it runs is up to the client.
+    pub fn run_command(&mut self, name: &str, command: String) -> Result<(), Error> {
+        match self.clients.values().find(|client| client.name == name) {
+            Some(client) => {
+                self.send(client.id, Message::RunCommand(command));
+                Ok(())
+            }
+            None => Err(Error::new(ErrorKind::NotFound, format!("No client named {}", name))),
+        }
+    }
+
+    // Offers every machine but the focused one as a button, so that there's no need to cycle through all of them.
+    fn show_picker(&mut self) {
+        let mut names = Vec::new();
+        if self.focus.is_some() {
+            names.push(self.hostname.clone());
+        }
+        names.extend(
+            self.clients
+                .values()
+                .filter(|client| Some(client.id) != self.focus)
+                .map(|client| client.name.clone()),
+        );
+
+        let identifiers: Vec<_> = names.iter().map(|name| format!("switch:{}", name)).collect();
+        let actions: Vec<_> = identifiers
+            .iter()
+            .zip(&names)
+            .map(|(identifier, name)| (identifier.as_str(), name.as_str()))
+            .collect();
+        let callback = self.action_callback();
+        self.manager.notify_with_actions("Switch to".to_owned(), &actions, callback);
+    }
+
+    fn action_callback(&self) -> Box<dyn FnOnce(String) + Send> {
+        let actions = self.actions.clone();
+        Box::new(move |action| {
+            let _ = actions.send(action);
+        })
+    }
+
+    // Buttons of the switch notification and the picker: "switch:<name>" moves focus to the named machine, "next" on to
+    // the next one.
+    pub fn handle_action(&mut self, action: &str) {
+        if action == "next" {
+            self.switch(self.next());
+        } else if let Some(name) = action.strip_prefix("switch:") {
+            if let Err(e) = self.switch_to_name(name) {
+                log::warn!("Failed to switch back: {}", e);
+            }
+        } else {
+            log::debug!("Unknown notification action {}", action);
+        }
+    }
+
+    fn update_indicator(&mut self) {
+        if self.focus_led {
+            self.manager.set_indicator_led(self.focus.is_some() && !self.paused);
+        }
+    }
+
+    pub fn status(&self) -> Vec<ClientStatus> {
+        self.clients
+            .values()
+            .map(|client| {
+                let timing = *client.timing.lock().unwrap();
+                ClientStatus {
+                    id: client.id,
+                    name: client.name.clone(),
+                    address: client.address,
+                    connected_since: client
+                        .connected_since
+                        .duration_since(UNIX_EPOCH)
+                        .map(|since| since.as_secs())
+                        .unwrap_or(0),
+                    focused: self.focus == Some(client.id),
+                    rtt_ms: timing.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+                    clock_offset_ms: timing.clock_offset.map(|offset| offset as f64 / 1000.0),
+                    system_info: self.system_infos.get(&client.id).map(|(info, received)| SystemInfo {
+                        uptime_secs: info.uptime_secs.map(|uptime| uptime + received.elapsed().as_secs()),
+                        ..info.clone()
+                    }),
+                    handshake: self.handshakes.get(&client.id).cloned(),
+                }
+            })
+            .collect()
+    }
+
+    fn roster(&self) -> String {
+        let marker = |focused| if focused { "*" } else { " " };
+
+        let mut lines = vec![format!("{}0: {} (server)", marker(self.focus.is_none()), self.hostname)];
+        for client in self.clients.values() {
+            let latency = match client.timing.lock().unwrap().rtt {
+                Some(latency) => format!("{:.1} ms", latency.as_secs_f64() * 1000.0),
+                None => "? ms".to_string(),
+            };
+            lines.push(format!(
+                "{}{}: {} ({})",
+                marker(self.focus == Some(client.id)),
+                client.id,
+                client.name,
+                latency
+            ));
+        }
+
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::KvmBuilder;
+    use crate::mock::MockManager;
+    use input::{Axis, Button, Capabilities, Direction, Key, LockState};
+    use std::collections::HashSet;
+    use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+    fn keys(keys: &[Key]) -> HashSet<Key> {
+        keys.iter().copied().collect()
+    }
+
+    fn builder() -> KvmBuilder<MockManager> {
+        KvmBuilder::new(MockManager::default(), "server".to_owned())
+            .hotkey(Action::Switch, &keys(&[Key::LeftAlt, Key::LeftCtrl]))
+            .hotkey(Action::Kill, &keys(&[Key::Esc, Key::LeftCtrl]))
+            .hotkey(Action::Pause, &keys(&[Key::Pause]))
+            .focus_led(true)
+    }
+
+    fn state() -> State<MockManager> {
+        builder().build().0.state
+    }
+
+    fn client(id: ClientId, name: &str) -> (Client, UnboundedReceiver<Message>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let client = Client {
+            id,
+            name: name.to_owned(),
+            address: "127.0.0.1:1234".parse().unwrap(),
+            connected_since: SystemTime::now(),
+            sender,
+            timing: Arc::new(Mutex::new(Timing::default())),
+        };
+
+        (client, receiver)
+    }
+
+    fn key(key: Key, direction: Direction) -> Event {
+        Event::Key {
+            direction,
+            kind: KeyKind::Key(key),
+        }
+    }
+
+    fn motion() -> Event {
+        Event::MouseMove {
+            axis: Axis::X,
+            delta: 1,
+        }
+    }
+
+    fn events(receiver: &mut UnboundedReceiver<Message>) -> usize {
+        let mut count = 0;
+        while let Ok(message) = receiver.try_recv() {
+            if let Message::Event(_) = message {
+                count += 1;
+            }
+        }
+
+        count
+    }
+
+    #[tokio::test]
+    async fn events_stay_local_without_clients() {
+        let mut state = state();
+
+        state.handle_local(motion()).await.unwrap();
+        assert_eq!(state.manager.written.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failing_local_writes_are_survived() {
+        let mut state = state();
+        state.manager.broken = true;
+
+        assert!(state.handle_local(motion()).await.unwrap());
+        assert!(state.handle_local(motion()).await.unwrap());
+        assert_eq!(state.manager.notifications.len(), 1);
+
+        state.manager.broken = false;
+        state.handle_local(motion()).await.unwrap();
+        assert_eq!(state.manager.written.len(), 1);
+        assert!(!state.local_failing);
+    }
+
+    #[tokio::test]
+    async fn events_go_to_focused_client() {
+        let mut state = state();
+        let (first, mut first_receiver) = client(1, "first");
+        let (second, mut second_receiver) = client(2, "second");
+        state.add(first);
+        state.add(second);
+
+        state.switch(Some(2));
+        state.handle_local(motion()).await.unwrap();
+
+        assert_eq!(events(&mut first_receiver), 0);
+        assert_eq!(events(&mut second_receiver), 1);
+        assert!(state.manager.written.is_empty());
+        assert_eq!(state.manager.led, Some(true));
+    }
+
+    #[tokio::test]
+    async fn unshared_events_stay_local() {
+        let mut state = builder().sharing(Sharing::Keyboard).build().0.state;
+        let (first, mut first_receiver) = client(1, "first");
+        state.add(first);
+
+        state.switch(Some(1));
+        state.handle_local(motion()).await.unwrap();
+        state.handle_local(key(Key::A, Direction::Down)).await.unwrap();
+
+        assert_eq!(events(&mut first_receiver), 1);
+        assert_eq!(state.manager.written.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn masked_axes_are_dropped() {
+        let mask = [RelativeAxis::Wheel].iter().copied().collect();
+        let mut state = builder().axis_mask("first".to_owned(), mask).build().0.state;
+        let (first, mut first_receiver) = client(1, "first");
+        state.add(first);
+
+        state.switch(Some(1));
+        state.handle_local(Event::MouseScroll { delta: 1 }).await.unwrap();
+        state.handle_local(motion()).await.unwrap();
+
+        assert_eq!(events(&mut first_receiver), 1);
+        assert!(state.manager.written.is_empty());
+    }
+
+    #[tokio::test]
+    async fn motion_is_scaled_by_display_scale() {
+        let mut state = builder().display_scale(1.5).build().0.state;
+        let (first, mut first_receiver) = client(1, "first");
+        state.add(first);
+
+        state.handle_message(1, Message::DisplayScale(3.0)).await.unwrap();
+        state.switch(Some(1));
+        state.handle_local(motion()).await.unwrap();
+
+        let deltas: Vec<_> = std::iter::from_fn(|| first_receiver.try_recv().ok())
+            .filter_map(|message| match message {
+                Message::Event(Event::MouseMove { delta, .. }) => Some(delta),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(deltas, vec![2]);
+    }
+
+    #[tokio::test]
+    async fn handshake_sets_display_scale() {
+        let mut state = state();
+        let (first, _receiver) = client(1, "first");
+        state.add(first);
+
+        let handshake = Handshake {
+            platform: "linux".to_owned(),
+            display_scale: Some(2.0),
+            ..Handshake::default()
+        };
+        state.handle_message(1, Message::Handshake(handshake.clone())).await.unwrap();
+        assert!(state.scales.contains_key(&1));
+        assert_eq!(state.status()[0].handshake, Some(handshake));
+
+        state.remove(1);
+        assert!(state.handshakes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn system_info_is_reported_in_status() {
+        let mut state = state();
+        let (first, _receiver) = client(1, "first");
+        state.add(first);
+        assert_eq!(state.status()[0].system_info, None);
+
+        let info = SystemInfo {
+            os: "Linux".to_owned(),
+            uptime_secs: Some(60),
+            battery_percent: Some(15),
+            charging: Some(false),
+        };
+        state.handle_message(1, Message::SystemInfo(info.clone())).await.unwrap();
+        assert_eq!(state.status()[0].system_info, Some(info));
+
+        state.remove(1);
+        assert!(state.system_infos.is_empty());
+    }
+
+    #[tokio::test]
+    async fn switch_chord_cycles_through_clients_and_server() {
+        let mut state = state();
+        let (first, _first_receiver) = client(1, "first");
+        let (third, _third_receiver) = client(3, "third");
+        state.add(first);
+        state.add(third);
+
+        for expected in &[Some(1), Some(3), None] {
+            state.handle_local(key(Key::LeftAlt, Direction::Down)).await.unwrap();
+            state.handle_local(key(Key::LeftCtrl, Direction::Down)).await.unwrap();
+            state.handle_local(key(Key::LeftCtrl, Direction::Up)).await.unwrap();
+            state.handle_local(key(Key::LeftAlt, Direction::Up)).await.unwrap();
+            assert_eq!(state.focus, *expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn profile_restricts_cycling() {
+        let group = ["first", "third"].iter().map(|name| (*name).to_owned()).collect();
+        let mut state = builder().group("work".to_owned(), group).build().0.state;
+        let mut receivers = Vec::new();
+        for (id, name) in &[(1, "first"), (2, "second"), (3, "third")] {
+            let (client, receiver) = client(*id, name);
+            state.add(client);
+            receivers.push(receiver);
+        }
+
+        assert!(state.set_profile(Some("lab")).is_err());
+        state.set_profile(Some("work")).unwrap();
+        for expected in &[Some(1), Some(3), None] {
+            state.switch(state.next());
+            assert_eq!(state.focus, *expected);
+        }
+
+        state.next_profile();
+        assert_eq!(state.profile, None);
+        state.switch(state.next());
+        state.switch(state.next());
+        assert_eq!(state.focus, Some(2));
+    }
+
+    #[tokio::test]
+    async fn duplicate_key_events_are_dropped() {
+        let mut state = state();
+
+        state.handle_local(key(Key::A, Direction::Down)).await.unwrap();
+        state.handle_local(key(Key::A, Direction::Down)).await.unwrap();
+        state.handle_local(key(Key::A, Direction::Up)).await.unwrap();
+        state.handle_local(key(Key::A, Direction::Up)).await.unwrap();
+        assert_eq!(state.manager.written.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn held_keys_are_synced_on_request() {
+        let mut state = state();
+        let (first, mut receiver) = client(1, "first");
+        state.add(first);
+        state.switch(Some(1));
+
+        state.handle_local(key(Key::A, Direction::Down)).await.unwrap();
+        state.handle_local(key(Key::B, Direction::Down)).await.unwrap();
+        state.handle_local(key(Key::B, Direction::Up)).await.unwrap();
+        while receiver.try_recv().is_ok() {}
+
+        state.handle_message(1, Message::RequestKeyState).await.unwrap();
+        assert!(matches!(receiver.try_recv(), Ok(Message::KeyStateSync(keys)) if keys == vec![KeyKind::Key(Key::A)]));
+
+        state.switch(None);
+        while receiver.try_recv().is_ok() {}
+        state.handle_message(1, Message::RequestKeyState).await.unwrap();
+        assert!(matches!(receiver.try_recv(), Ok(Message::KeyStateSync(keys)) if keys.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn only_forwarded_keys_are_synced() {
+        let mut state = builder()
+            .local_keys(&keys(&[Key::LeftMeta]))
+            .key_substitution("first".to_owned(), Key::F11, Key::F)
+            .build()
+            .0
+            .state;
+        let (first, mut receiver) = client(1, "first");
+        state.add(first);
+        state.switch(Some(1));
+
+        state.handle_local(key(Key::LeftMeta, Direction::Down)).await.unwrap();
+        state.handle_local(key(Key::F11, Direction::Down)).await.unwrap();
+        state.paused = true;
+        state.handle_local(key(Key::A, Direction::Down)).await.unwrap();
+        while receiver.try_recv().is_ok() {}
+
+        state.handle_message(1, Message::RequestKeyState).await.unwrap();
+        assert!(matches!(receiver.try_recv(), Ok(Message::KeyStateSync(keys)) if keys == vec![KeyKind::Key(Key::F)]));
+    }
+
+    #[tokio::test]
+    async fn duplicate_names_follow_the_policy() {
+        for &policy in &[DuplicateNames::Replace, DuplicateNames::Reject, DuplicateNames::Suffix] {
+            let mut state = builder().duplicate_names(policy).build().0.state;
+            let (first, first_receiver) = client(1, "laptop");
+            let (second, _second_receiver) = client(2, "laptop");
+            state.add(first);
+            state.add(second);
+
+            let mut names: Vec<_> = state.clients.values().map(|client| (client.id, client.name.clone())).collect();
+            names.sort();
+            let expected = match policy {
+                DuplicateNames::Replace => vec![(2, "laptop".to_owned())],
+                DuplicateNames::Reject => vec![(1, "laptop".to_owned())],
+                DuplicateNames::Suffix => vec![(1, "laptop".to_owned()), (2, "laptop (2)".to_owned())],
+            };
+            assert_eq!(names, expected);
+            // The replaced connection is closed.
+            assert_eq!(first_receiver.is_closed(), policy == DuplicateNames::Replace);
+        }
+    }
+
+    #[tokio::test]
+    async fn idle_clients_are_disconnected() {
+        let mut state = builder().idle_timeout(Some(Duration::ZERO)).build().0.state;
+        let (first, _first_receiver) = client(1, "first");
+        let (second, mut second_receiver) = client(2, "second");
+        state.add(first);
+        state.add(second);
+        state.switch(Some(1));
+
+        state.disconnect_idle();
+        assert!(state.clients.contains_key(&1));
+        assert!(!state.clients.contains_key(&2));
+        assert!(!state.last_used.contains_key(&2));
+        while let Ok(message) = second_receiver.try_recv() {
+            if matches!(message, Message::Notify(_)) {
+                return;
+            }
+        }
+        panic!("The idle client wasn't told why it was disconnected");
+    }
+
+    #[tokio::test]
+    async fn shutdown_releases_keys_and_says_goodbye() {
+        let mut state = state();
+        let (client, mut receiver) = client(1, "client");
+        state.add(client);
+        state.switch(Some(1));
+        state.handle_local(key(Key::A, Direction::Down)).await.unwrap();
+        while receiver.try_recv().is_ok() {}
+
+        state.shutdown().await;
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(Message::Event(Event::Key { direction: Direction::Up, kind: KeyKind::Key(Key::A) }))
+        ));
+        assert!(matches!(receiver.try_recv(), Ok(Message::Goodbye)));
+        assert!(state.clients.is_empty());
+    }
+
+    #[tokio::test]
+    async fn shutdown_only_releases_forwarded_keys() {
+        let mut state = builder().local_keys(&keys(&[Key::LeftMeta])).build().0.state;
+        let (client, mut receiver) = client(1, "client");
+        state.add(client);
+        state.switch(Some(1));
+        state.handle_local(key(Key::LeftMeta, Direction::Down)).await.unwrap();
+        while receiver.try_recv().is_ok() {}
+
+        state.shutdown().await;
+        assert!(matches!(receiver.try_recv(), Ok(Message::Goodbye)));
+    }
+
+    #[tokio::test]
+    async fn local_keys_are_never_forwarded() {
+        let mut state = builder().local_keys(&keys(&[Key::LeftMeta])).build().0.state;
+        let (first, mut receiver) = client(1, "first");
+        state.add(first);
+        state.switch(Some(1));
+        events(&mut receiver);
+
+        state.handle_local(key(Key::LeftMeta, Direction::Down)).await.unwrap();
+        state.handle_local(key(Key::A, Direction::Down)).await.unwrap();
+        assert_eq!(events(&mut receiver), 1);
+        assert_eq!(state.manager.written.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn keys_are_substituted_per_client() {
+        let mut state = builder().key_substitution("first".to_owned(), Key::F11, Key::F).build().0.state;
+        let (first, mut first_receiver) = client(1, "first");
+        let (second, mut second_receiver) = client(2, "second");
+        state.add(first);
+        state.add(second);
+
+        for (id, receiver, expected) in [(1, &mut first_receiver, Key::F), (2, &mut second_receiver, Key::F11)] {
+            state.switch(Some(id));
+            while receiver.try_recv().is_ok() {}
+            state.handle_local(key(Key::F11, Direction::Down)).await.unwrap();
+            state.handle_local(key(Key::F11, Direction::Up)).await.unwrap();
+            let mut sent = Vec::new();
+            while let Ok(message) = receiver.try_recv() {
+                if let Message::Event(Event::Key { kind, .. }) = message {
+                    sent.push(kind);
+                }
+            }
+            assert_eq!(sent, vec![KeyKind::Key(expected); 2]);
+        }
+    }
+
+    #[tokio::test]
+    async fn kill_chord_stops_the_server() {
+        let mut state = state();
+
+        assert!(state.handle_local(key(Key::LeftCtrl, Direction::Down)).await.unwrap());
+        assert!(!state.handle_local(key(Key::Esc, Direction::Down)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn lock_chord_locks_every_machine() {
+        let mut state = builder().hotkey(Action::LockAll, &keys(&[Key::F9])).build().0.state;
+        let (first, mut first_receiver) = client(1, "first");
+        let (second, mut second_receiver) = client(2, "second");
+        state.add(first);
+        state.add(second);
+
+        state.handle_local(key(Key::F9, Direction::Down)).await.unwrap();
+        assert!(state.manager.locked);
+        for receiver in &mut [&mut first_receiver, &mut second_receiver] {
+            let mut locked = false;
+            while let Ok(message) = receiver.try_recv() {
+                locked |= matches!(message, Message::Lock);
+            }
+            assert!(locked);
+        }
+    }
+
+    #[tokio::test]
+    async fn suspend_chord_suspends_the_focused_client() {
+        let mut state = builder()
+            .hotkey(Action::Suspend, &keys(&[Key::F8]))
+            .hotkey(Action::Wake, &keys(&[Key::F7]))
+            .build()
+            .0
+            .state;
+        let (first, mut receiver) = client(1, "first");
+        state.add(first);
+        state.switch(Some(1));
+
+        state.handle_local(key(Key::F8, Direction::Down)).await.unwrap();
+        assert_eq!(state.focus, None);
+        assert_eq!(state.suspended.as_deref(), Some("first"));
+        let mut suspended = false;
+        while let Ok(message) = receiver.try_recv() {
+            suspended |= matches!(message, Message::Suspend);
+        }
+        assert!(suspended);
+
+        // Without a MAC address there's nothing to send the wake-up packet to.
+        state.handle_local(key(Key::F7, Direction::Down)).await.unwrap();
+        assert!(state.manager.notifications.last().unwrap().starts_with("Failed to wake first"));
+    }
+
+    #[tokio::test]
+    async fn secure_attention_chord_goes_to_the_focused_client() {
+        let mut state = builder().hotkey(Action::SecureAttention, &keys(&[Key::F6])).build().0.state;
+        let (first, mut receiver) = client(1, "first");
+        state.add(first);
+        state.switch(Some(1));
+        while receiver.try_recv().is_ok() {}
+
+        state.handle_local(key(Key::F6, Direction::Down)).await.unwrap();
+        assert!(matches!(receiver.try_recv(), Ok(Message::SecureAttention)));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn scancodes_go_with_their_keys() {
+        let mut state = state();
+        let (client, mut receiver) = client(1, "client");
+        state.add(client);
+        state.switch(Some(1));
+        while receiver.try_recv().is_ok() {}
+
+        state.handle_local(Event::Scan { code: 0x1e }).await.unwrap();
+        state.handle_local(key(Key::A, Direction::Down)).await.unwrap();
+        assert!(matches!(receiver.try_recv(), Ok(Message::Event(Event::Scan { code: 0x1e }))));
+        assert!(matches!(receiver.try_recv(), Ok(Message::Event(Event::Key { .. }))));
+
+        // The scancode of a key taken by a hotkey goes nowhere, and doesn't stick to the next key either.
+        state.handle_local(Event::Scan { code: 0x19 }).await.unwrap();
+        state.handle_local(key(Key::Pause, Direction::Down)).await.unwrap();
+        state.handle_local(key(Key::Pause, Direction::Up)).await.unwrap();
+        assert!(receiver.try_recv().is_err());
+        state.handle_local(key(Key::A, Direction::Up)).await.unwrap();
+        assert!(state.manager.written.iter().all(|event| !matches!(event, Event::Scan { .. })));
+    }
+
+    #[tokio::test]
+    async fn capabilities_are_sent_to_new_clients() {
+        let mut state = state();
+        let (first, mut receiver) = client(1, "first");
+        state.add(first);
+        assert!(receiver.try_recv().is_err());
+
+        let capabilities = Capabilities {
+            keys: [KeyKind::Key(Key::A), KeyKind::Button(Button::Left)].iter().copied().collect(),
+            motion: true,
+            scroll: false,
+        };
+        state.manager.capabilities = Some(capabilities.clone());
+        let (second, mut receiver) = client(2, "second");
+        state.add(second);
+        assert!(matches!(receiver.try_recv(), Ok(Message::Capabilities(sent)) if sent == capabilities));
+    }
+
+    #[tokio::test]
+    async fn switch_events_follow_the_policy() {
+        let lid = Event::Switch { code: 0, on: true };
+        for policy in [SwitchPolicy::Local, SwitchPolicy::Drop, SwitchPolicy::Forward].iter() {
+            let mut state = builder().switch_policy(policy.clone()).build().0.state;
+            let (client, mut receiver) = client(1, "client");
+            state.add(client);
+            state.switch(Some(1));
+            while receiver.try_recv().is_ok() {}
+
+            state.handle_local(lid).await.unwrap();
+            let written = state.manager.written.iter().any(|event| matches!(event, Event::Switch { .. }));
+            let forwarded = matches!(receiver.try_recv(), Ok(Message::Event(Event::Switch { code: 0, on: true })));
+            assert_eq!((written, forwarded), (*policy == SwitchPolicy::Local, *policy == SwitchPolicy::Forward));
+        }
+    }
+
+    #[tokio::test]
+    async fn switches_are_undone_in_reverse() {
+        let mut state = state();
+        let (first, _first) = client(1, "first");
+        let (second, _second) = client(2, "second");
+        state.add(first);
+        state.add(second);
+        state.switch(Some(1));
+        state.switch(Some(2));
+        assert_eq!(state.history.iter().map(|record| record.to.as_str()).collect::<Vec<_>>(), ["first", "second"]);
+
+        state.undo_switch().unwrap();
+        assert_eq!(state.focus, Some(1));
+        state.undo_switch().unwrap();
+        assert_eq!(state.focus, None);
+        assert!(state.history.is_empty());
+        assert!(state.undo_switch().is_err());
+    }
+
+    #[tokio::test]
+    async fn events_clients_cant_write_are_dropped() {
+        let mut state = state();
+        let (client, mut receiver) = client(1, "client");
+        state.add(client);
+        state.switch(Some(1));
+        let capabilities = Capabilities {
+            keys: [KeyKind::Key(Key::A)].iter().copied().collect(),
+            motion: true,
+            scroll: false,
+        };
+        state.handle_message(1, Message::Capabilities(capabilities)).await.unwrap();
+        while receiver.try_recv().is_ok() {}
+
+        state.handle_local(key(Key::A, Direction::Down)).await.unwrap();
+        state.handle_local(key(Key::F13, Direction::Down)).await.unwrap();
+        state.handle_local(Event::MouseScroll { delta: 1 }).await.unwrap();
+        state.handle_local(motion()).await.unwrap();
+        assert!(matches!(receiver.try_recv(), Ok(Message::Event(Event::Key { kind: KeyKind::Key(Key::A), .. }))));
+        assert!(matches!(receiver.try_recv(), Ok(Message::Event(Event::MouseMove { .. }))));
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(state.unsupported.len(), 2);
+        assert!(state.manager.written.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pause_keeps_events_local() {
+        let mut state = state();
+        let (client, mut receiver) = client(1, "client");
+        state.add(client);
+        state.switch(Some(1));
+
+        state.handle_local(key(Key::Pause, Direction::Down)).await.unwrap();
+        state.handle_local(motion()).await.unwrap();
+
+        assert!(state.paused);
+        assert_eq!(state.focus, Some(1));
+        assert_eq!(events(&mut receiver), 0);
+        assert_eq!(state.manager.written.len(), 1);
+        assert_eq!(state.manager.led, Some(false));
+    }
+
+    #[tokio::test]
+    async fn failed_send_removes_client_and_returns_focus() {
+        let mut state = state();
+        let (client, receiver) = client(1, "client");
+        state.add(client);
+        state.switch(Some(1));
+        drop(receiver);
+
+        state.handle_local(motion()).await.unwrap();
+
+        assert!(state.clients.is_empty());
+        assert_eq!(state.focus, None);
+        assert_eq!(state.manager.written.len(), 1);
+        assert_eq!(state.manager.led, Some(false));
+    }
+
+    #[tokio::test]
+    async fn events_are_read_from_the_manager() {
+        let mut state = state();
+        state.manager.events.push_back(motion());
+
+        let event = state.manager.read().await.unwrap();
+        state.handle_local(event).await.unwrap();
+        assert_eq!(state.manager.written.len(), 1);
+    }
+
+    #[test]
+    fn switch_to_name() {
+        let mut state = state();
+        let (client, _receiver) = client(1, "client");
+        state.add(client);
+
+        assert!(state.switch_to_name("client").is_ok());
+        assert_eq!(state.focus, Some(1));
+        assert!(state.switch_to_name("unknown").is_err());
+        assert_eq!(state.focus, Some(1));
+        assert!(state.switch_to_name("server").is_ok());
+        assert_eq!(state.focus, None);
+    }
+
+    #[test]
+    fn focus_commands_are_sent() {
+        let mut state = builder()
+            .focus_command("first".to_owned(), "monitor-input".to_owned())
+            .build()
+            .0
+            .state;
+        let (first, mut receiver) = client(1, "first");
+        state.add(first);
+
+        state.switch(Some(1));
+        let mut commands = Vec::new();
+        while let Ok(message) = receiver.try_recv() {
+            if let Message::RunCommand(command) = message {
+                commands.push(command);
+            }
+        }
+        assert_eq!(commands, vec!["monitor-input"]);
+
+        assert!(state.run_command("second", "monitor-input".to_owned()).is_err());
+        state.run_command("first", "lock".to_owned()).unwrap();
+        assert!(matches!(receiver.try_recv(), Ok(Message::RunCommand(command)) if command == "lock"));
+    }
+
+    #[tokio::test]
+    async fn picker_offers_other_machines() {
+        let mut state = builder().hotkey(Action::Pick, &keys(&[Key::F11])).build().0.state;
+        let (first, _first_receiver) = client(1, "first");
+        let (second, _second_receiver) = client(2, "second");
+        state.add(first);
+        state.add(second);
+        state.switch(Some(1));
+
+        state.handle_local(key(Key::F11, Direction::Down)).await.unwrap();
+        assert_eq!(state.manager.actions, vec!["switch:server", "switch:second"]);
+    }
+
+    #[test]
+    fn notification_actions_switch() {
+        let mut state = state();
+        let (first, _first_receiver) = client(1, "first");
+        let (second, _second_receiver) = client(2, "second");
+        state.add(first);
+        state.add(second);
+        state.switch(Some(1));
+
+        state.handle_action("next");
+        assert_eq!(state.focus, Some(2));
+        state.handle_action("switch:first");
+        assert_eq!(state.focus, Some(1));
+        state.handle_action("switch:server");
+        assert_eq!(state.focus, None);
+    }
+
+    struct StayOnServer;
+
+    impl Policy for StayOnServer {
+        fn allow_switch(&mut self, _from: Option<&str>, to: Option<&str>) -> bool {
+            to.is_none()
+        }
+
+        fn filter_event(&mut self, event: Event) -> Option<Event> {
+            match event {
+                Event::MouseScroll { .. } => None,
+                event => Some(event),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn policies_can_deny_switches_and_drop_events() {
+        let mut state = builder().policy(StayOnServer).build().0.state;
+        let (client, _receiver) = client(1, "client");
+        state.add(client);
+
+        state.switch(Some(1));
+        assert_eq!(state.focus, None);
+
+        state.handle_local(Event::MouseScroll { delta: 1 }).await.unwrap();
+        state.handle_local(motion()).await.unwrap();
+        assert_eq!(state.manager.written.len(), 1);
+    }
+
+    struct Closed;
+
+    impl Policy for Closed {
+        fn allow_switch(&mut self, _from: Option<&str>, _to: Option<&str>) -> bool {
+            false
+        }
+
+        fn allow_forward(&mut self, _to: &str) -> bool {
+            false
+        }
+    }
+
+    #[tokio::test]
+    async fn access_policies_can_be_overridden() {
+        let mut state = builder()
+            .policy(Closed)
+            .hotkey(Action::OverrideAccess, &keys(&[Key::F10]))
+            .build()
+            .0
+            .state;
+        let (client, mut receiver) = client(1, "client");
+        state.add(client);
+
+        // Focus could have moved before the policy started objecting.
+        state.focus = Some(1);
+        state.handle_local(motion()).await.unwrap();
+        assert_eq!(state.manager.written.len(), 1);
+        assert_eq!(events(&mut receiver), 0);
+
+        state.handle_local(key(Key::F10, Direction::Down)).await.unwrap();
+        state.handle_local(motion()).await.unwrap();
+        assert_eq!(events(&mut receiver), 1);
+
+        state.switch(None);
+        state.switch(Some(1));
+        assert_eq!(state.focus, Some(1));
+    }
+
+    struct NoSecrets;
+
+    impl Policy for NoSecrets {
+        fn allow_clipboard(&mut self, _from: Option<&str>, _to: Option<&str>, _mime_type: &str, data: &[u8]) -> bool {
+            data != b"secret"
+        }
+    }
+
+    #[tokio::test]
+    async fn policies_can_block_clipboard_content() {
+        let mut state = builder().policy(NoSecrets).build().0.state;
+        let (first, _first_receiver) = client(1, "first");
+        let (second, mut second_receiver) = client(2, "second");
+        state.add(first);
+        state.add(second);
+        state.switch(Some(2));
+        while second_receiver.try_recv().is_ok() {}
+
+        let data = |text: &[u8]| Message::ClipboardData(clipboard::TEXT.to_owned(), text.to_vec());
+        state.handle_message(1, data(b"secret")).await.unwrap();
+        assert!(second_receiver.try_recv().is_err());
+
+        state.handle_message(1, data(b"public")).await.unwrap();
+        assert!(matches!(second_receiver.try_recv(), Ok(Message::ClipboardData(_, _))));
+    }
+
+    #[tokio::test]
+    async fn paths_are_translated_between_machines() {
+        let translation = PathTranslation::new(
+            Some("first".to_owned()),
+            Some("second".to_owned()),
+            "/home/me".to_owned(),
+            "smb://nas/me".to_owned(),
+        );
+        let mut state = builder().path_translation(translation).build().0.state;
+        let (first, _first_receiver) = client(1, "first");
+        let (second, mut second_receiver) = client(2, "second");
+        state.add(first);
+        state.add(second);
+        state.switch(Some(2));
+        while second_receiver.try_recv().is_ok() {}
+
+        let data = b"/home/me/doc.txt".to_vec();
+        state.handle_message(1, Message::ClipboardData(clipboard::TEXT.to_owned(), data)).await.unwrap();
+        match second_receiver.try_recv() {
+            Ok(Message::ClipboardData(_, data)) => assert_eq!(data, b"smb://nas/me/doc.txt"),
+            message => panic!("Unexpected {:?}", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn clipboard_sync_can_be_paused() {
+        let mut state = builder().hotkey(Action::ToggleClipboard, &keys(&[Key::F12])).build().0.state;
+        let (first, _first_receiver) = client(1, "first");
+        let (second, mut second_receiver) = client(2, "second");
+        state.add(first);
+        state.add(second);
+        state.switch(Some(2));
+        while second_receiver.try_recv().is_ok() {}
+
+        let data = || Message::ClipboardData(clipboard::TEXT.to_owned(), b"password".to_vec());
+        state.handle_local(key(Key::F12, Direction::Down)).await.unwrap();
+        while second_receiver.try_recv().is_ok() {}
+        state.handle_message(1, data()).await.unwrap();
+        assert!(second_receiver.try_recv().is_err());
+        assert_eq!(state.manager.notifications.last().unwrap(), "Clipboard sync paused");
+
+        state.handle_local(key(Key::F12, Direction::Up)).await.unwrap();
+        state.handle_local(key(Key::F12, Direction::Down)).await.unwrap();
+        while second_receiver.try_recv().is_ok() {}
+        state.handle_message(1, data()).await.unwrap();
+        assert!(matches!(second_receiver.try_recv(), Ok(Message::ClipboardData(_, _))));
+    }
+
+    #[tokio::test]
+    async fn activity_reaches_machines_without_focus() {
+        let mut state = builder().sync_activity(true).build().0.state;
+        let (first, mut first_receiver) = client(1, "first");
+        let (second, mut second_receiver) = client(2, "second");
+        state.add(first);
+        state.add(second);
+        state.switch(Some(2));
+        while second_receiver.try_recv().is_ok() {}
+
+        state.sync_activity().await.unwrap();
+        assert!(first_receiver.try_recv().is_err());
+
+        state.handle_local(motion()).await.unwrap();
+        state.sync_activity().await.unwrap();
+        assert!(matches!(first_receiver.try_recv(), Ok(Message::Activity)));
+        assert!(!matches!(second_receiver.try_recv(), Ok(Message::Activity)));
+        assert_eq!(state.manager.written.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn clients_can_request_focus() {
+        let mut state = state();
+        let (first, _first) = client(1, "first");
+        let (second, _second) = client(2, "second");
+        state.add(first);
+        state.add(second);
+
+        state.handle_message(2, Message::RequestFocus(true)).await.unwrap();
+        assert_eq!(state.focus, Some(2));
+
+        // Only the focused client can hand focus back.
+        state.handle_message(1, Message::RequestFocus(false)).await.unwrap();
+        assert_eq!(state.focus, Some(2));
+        state.handle_message(2, Message::RequestFocus(false)).await.unwrap();
+        assert_eq!(state.focus, None);
+    }
+
+    #[tokio::test]
+    async fn lock_state_follows_focus() {
+        let mut state = state();
+        let caps_lock = LockState {
+            caps_lock: true,
+            num_lock: false,
+        };
+        state.manager.lock_state = Some(caps_lock);
+        let (client, mut receiver) = client(1, "client");
+        state.add(client);
+
+        state.switch(Some(1));
+        let mut messages = std::iter::from_fn(|| receiver.try_recv().ok());
+        assert!(messages.any(|message| matches!(message, Message::LockState(state) if state == caps_lock)));
+
+        // Caps Lock was turned off on the client before it lost focus.
+        state.switch(None);
+        state.handle_message(1, Message::LockState(LockState::default())).await.unwrap();
+        assert_eq!(state.manager.written.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn mouse_keys_are_off_while_num_lock_is_on() {
+        let mut state = builder().mouse_keys(5).build().0.state;
+        state.handle_local(key(Key::Kp8, Direction::Down)).await.unwrap();
+        state.handle_local(key(Key::Kp8, Direction::Up)).await.unwrap();
+        assert!(matches!(state.manager.written[..], [Event::MouseMove { axis: Axis::Y, delta: -5 }]));
+
+        state.manager.written.clear();
+        state.manager.lock_state = Some(LockState {
+            caps_lock: false,
+            num_lock: true,
+        });
+        state.handle_local(key(Key::Kp8, Direction::Down)).await.unwrap();
+        state.handle_local(key(Key::Kp8, Direction::Up)).await.unwrap();
+        assert!(matches!(
+            state.manager.written[..],
+            [Event::Key { kind: KeyKind::Key(Key::Kp8), .. }, Event::Key { kind: KeyKind::Key(Key::Kp8), .. }]
+        ));
+    }
+
+    #[test]
+    fn focus_changes_are_announced() {
+        let mut state = state();
+        let mut notifications = state.notifications.subscribe();
+        let (client, _receiver) = client(1, "client");
+        state.add(client);
+
+        state.switch(Some(1));
+        assert!(matches!(notifications.try_recv(), Ok(Notification::Connected { .. })));
+        assert!(matches!(notifications.try_recv(), Ok(Notification::Focused { id: Some(1), .. })));
+
+        state.remove(1);
+        assert!(matches!(notifications.try_recv(), Ok(Notification::Disconnected { .. })));
+        assert!(matches!(notifications.try_recv(), Ok(Notification::Focused { id: None, .. })));
+    }
+}
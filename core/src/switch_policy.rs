@@ -0,0 +1,15 @@
+// What happens to switch events from the grabbed devices, like the lid being closed or the machine going into tablet
+// mode. Grabbing takes them away from the server's desktop like any other event, so it has to be decided explicitly.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum SwitchPolicy {
+    // They stay with the server, as if the devices weren't grabbed.
+    #[default]
+    Local,
+    // They go nowhere.
+    Drop,
+    // They go to whichever machine has focus, like key events.
+    Forward,
+    // The command is run with the switch's Linux code and 1 or 0 appended, e.g. to lock all machines when the lid is
+    // closed. The switches go nowhere else.
+    Hook(Vec<String>),
+}
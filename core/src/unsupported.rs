@@ -0,0 +1,20 @@
+use input::{Capabilities, Event, KeyKind};
+
+// What a client has said its virtual device can't write, events of it are dropped instead of forwarded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub(crate) enum Unsupported {
+    Key(KeyKind),
+    Motion,
+    Scroll,
+}
+
+impl Unsupported {
+    pub(crate) fn of(capabilities: &Capabilities, event: &Event) -> Option<Self> {
+        match *event {
+            Event::Key { kind, .. } if !capabilities.keys.contains(&kind) => Some(Self::Key(kind)),
+            Event::MouseMove { .. } if !capabilities.motion => Some(Self::Motion),
+            Event::MouseScroll { .. } if !capabilities.scroll => Some(Self::Scroll),
+            _ => None,
+        }
+    }
+}
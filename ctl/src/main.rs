@@ -0,0 +1,285 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{self, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Error};
+use gethostname::gethostname;
+use structopt::StructOpt;
+use tokio::net::UnixStream;
+
+use input::Key;
+use net::control::{self, ClientStatus, Hotkey, Notification, Request, Response, SwitchRecord};
+use net::SystemInfo;
+
+async fn run(socket_path: PathBuf, command: Command) -> Result<(), Error> {
+    let mut stream = UnixStream::connect(&socket_path)
+        .await
+        .with_context(|| format!("Failed to connect to {}", socket_path.display()))?;
+
+    let (request, json) = match command {
+        Command::Pick { command } => return pick(stream, &command).await,
+        Command::Switch { name } => (Request::Switch(name), false),
+        Command::List { json } => (Request::List, json),
+        Command::Watch { json } => (Request::Subscribe, json),
+        Command::Profile { group } => (Request::SetProfile(group), false),
+        Command::Wake { name } => (Request::Wake(name), false),
+        Command::UndoSwitch => (Request::UndoSwitch, false),
+        Command::History { json } => (Request::History, json),
+        Command::Run { client, command } => (Request::RunCommand { client, command }, false),
+        Command::LogLevel { level } => (Request::SetLogLevel(level), false),
+        Command::SetKeys {
+            hotkey,
+            keys,
+            persist,
+        } => {
+            let keys = keys.into_iter().collect();
+            (Request::SetHotkey { hotkey, keys, persist }, false)
+        }
+    };
+
+    control::write_request(&mut stream, &request).await?;
+    loop {
+        match control::read_response(&mut stream).await? {
+            Response::Ok => return Ok(()),
+            Response::Error(message) => return Err(Error::msg(message)),
+            Response::Clients(clients) => {
+                if json {
+                    println!("{}", serde_json::to_string(&clients)?);
+                } else {
+                    print_clients(&clients);
+                }
+
+                return Ok(());
+            }
+            Response::History(history) => {
+                if json {
+                    println!("{}", serde_json::to_string(&history)?);
+                } else {
+                    print_history(&history);
+                }
+
+                return Ok(());
+            }
+            // Notifications keep coming until the server goes away.
+            Response::Notification(notification) => {
+                if json {
+                    println!("{}", serde_json::to_string(&notification)?);
+                } else {
+                    print_notification(&notification);
+                }
+            }
+        }
+    }
+}
+
+// Lets an external picker like dmenu choose where to switch, the names are written to its input one per line and the
+// chosen one is read from its output.
+async fn pick(mut stream: UnixStream, command: &[String]) -> Result<(), Error> {
+    control::write_request(&mut stream, &Request::List).await?;
+    let clients = match control::read_response(&mut stream).await? {
+        Response::Clients(clients) => clients,
+        Response::Error(message) => return Err(Error::msg(message)),
+        response => return Err(anyhow::anyhow!("Unexpected response {:?}", response)),
+    };
+
+    // The control socket is local, so the server is this machine.
+    let mut names = vec![gethostname().to_string_lossy().into_owned()];
+    names.extend(clients.into_iter().map(|client| client.name));
+
+    let mut child = process::Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", command[0]))?;
+
+    // Dropping stdin closes it, which tells the picker that the list is complete.
+    let mut input = child.stdin.take().unwrap();
+    for name in &names {
+        writeln!(input, "{}", name)?;
+    }
+    drop(input);
+
+    let mut choice = String::new();
+    child.stdout.take().unwrap().read_to_string(&mut choice)?;
+    child.wait()?;
+
+    // Pickers print nothing when dismissed.
+    let choice = choice.trim();
+    if choice.is_empty() {
+        return Ok(());
+    }
+
+    control::write_request(&mut stream, &Request::Switch(choice.to_owned())).await?;
+    match control::read_response(&mut stream).await? {
+        Response::Ok => Ok(()),
+        Response::Error(message) => Err(Error::msg(message)),
+        response => Err(anyhow::anyhow!("Unexpected response {:?}", response)),
+    }
+}
+
+fn print_clients(clients: &[ClientStatus]) {
+    for client in clients {
+        let format_ms = |ms: Option<f64>| {
+            ms.map(|ms| format!("{:.1} ms", ms))
+                .unwrap_or_else(|| "?".to_owned())
+        };
+        println!(
+            "{}{}: {} {} (rtt {}, clock offset {})",
+            if client.focused { "*" } else { " " },
+            client.id,
+            client.name,
+            client.address,
+            format_ms(client.rtt_ms),
+            format_ms(client.clock_offset_ms)
+        );
+
+        if let Some(info) = &client.system_info {
+            println!("     {}", describe_system_info(info));
+        }
+        if let Some(handshake) = &client.handshake {
+            let mut parts = vec![format!("id {}", handshake.id.as_deref().unwrap_or("unknown"))];
+            parts.extend(handshake.features.iter().cloned());
+            println!("     {}", parts.join(", "));
+        }
+    }
+}
+
+fn print_history(history: &[SwitchRecord]) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+    for record in history {
+        println!("{:>6}s ago: {} -> {}", now.saturating_sub(record.at), record.from, record.to);
+    }
+}
+
+fn describe_system_info(info: &SystemInfo) -> String {
+    let mut parts = vec![info.os.clone()];
+    if let Some(uptime) = info.uptime_secs {
+        parts.push(format!("up {}h {}m", uptime / 3600, uptime / 60 % 60));
+    }
+    if let Some(percent) = info.battery_percent {
+        let state = match info.charging {
+            Some(true) => ", charging",
+            Some(false) => ", discharging",
+            None => "",
+        };
+        parts.push(format!("battery {}%{}", percent, state));
+    }
+
+    parts.join(", ")
+}
+
+fn print_notification(notification: &Notification) {
+    match notification {
+        Notification::Connected { id, name, address } => {
+            println!("{}: {} connected from {}", id, name, address)
+        }
+        Notification::Disconnected { id, name } => println!("{}: {} disconnected", id, name),
+        Notification::Focused { id, name } => println!("{}: {} has focus", id.unwrap_or(0), name),
+        Notification::Transfer { id, name, outgoing, done, total } => println!(
+            "{}: clipboard {} {}: {} of {}",
+            id,
+            if *outgoing { "to" } else { "from" },
+            name,
+            net::clipboard::describe(*done),
+            net::clipboard::describe(*total)
+        ),
+        Notification::TransferCancelled { id, name } => println!("{}: clipboard transfer with {} cancelled", id, name),
+    }
+}
+
+fn parse_hotkey(name: &str) -> Result<Hotkey, String> {
+    match name {
+        "switch" => Ok(Hotkey::Switch),
+        "kill" => Ok(Hotkey::Kill),
+        "pause" => Ok(Hotkey::Pause),
+        "roster" => Ok(Hotkey::Roster),
+        "cancel-transfer" => Ok(Hotkey::CancelTransfer),
+        "clipboard" => Ok(Hotkey::Clipboard),
+        "picker" => Ok(Hotkey::Picker),
+        "profile" => Ok(Hotkey::Profile),
+        "override" => Ok(Hotkey::Override),
+        "lock" => Ok(Hotkey::Lock),
+        "suspend" => Ok(Hotkey::Suspend),
+        "wake" => Ok(Hotkey::Wake),
+        "secure-attention" => Ok(Hotkey::SecureAttention),
+        "undo-switch" => Ok(Hotkey::UndoSwitch),
+        _ => Err(format!("Unknown hotkey {}", name)),
+    }
+}
+
+fn parse_key(name: &str) -> Result<Key, String> {
+    serde_json::from_value(serde_json::Value::String(name.to_owned()))
+        .map_err(|_| format!("Unknown key {}", name))
+}
+
+#[derive(StructOpt)]
+enum Command {
+    #[structopt(about = "Move focus to the client with the given name")]
+    Switch { name: String },
+    #[structopt(about = "List connected clients")]
+    List {
+        #[structopt(long, help = "Print machine readable JSON")]
+        json: bool,
+    },
+    #[structopt(about = "Make the switch keys cycle through the clients in the given group only, or all clients without one")]
+    Profile { group: Option<String> },
+    #[structopt(about = "Wake up a client with Wake-on-LAN, its MAC address has to be in the server config")]
+    Wake { name: String },
+    #[structopt(about = "Move focus back to where it was before the last switch")]
+    UndoSwitch,
+    #[structopt(about = "List the last focus changes, oldest first")]
+    History {
+        #[structopt(long, help = "Print machine readable JSON")]
+        json: bool,
+    },
+    #[structopt(about = "Ask a client to run one of the commands listed in its config")]
+    Run { client: String, command: String },
+    #[structopt(about = "Change the server log level (off, error, warn, info, debug or trace)")]
+    LogLevel { level: String },
+    #[structopt(about = "Rebind a hotkey (switch, kill, pause, roster, cancel-transfer, clipboard, picker, profile, override, lock, suspend, wake, secure-attention or undo-switch) to the given keys")]
+    SetKeys {
+        #[structopt(parse(try_from_str = parse_hotkey))]
+        hotkey: Hotkey,
+        #[structopt(parse(try_from_str = parse_key), help = "Key names as used in the config, e.g. LeftAlt")]
+        keys: Vec<Key>,
+        #[structopt(long, help = "Also save the keys to the server config file")]
+        persist: bool,
+    },
+    #[structopt(about = "Choose a machine to switch to with an external picker, e.g. rkvm-ctl pick rofi -dmenu")]
+    Pick {
+        #[structopt(required = true, help = "The picker and its arguments")]
+        command: Vec<String>,
+    },
+    #[structopt(about = "Print notifications from the server as they happen")]
+    Watch {
+        #[structopt(long, help = "Print machine readable JSON, one notification per line")]
+        json: bool,
+    },
+}
+
+#[derive(StructOpt)]
+#[structopt(name = "rkvm-ctl", about = "Controls a running rkvm server")]
+struct Args {
+    #[structopt(
+        long,
+        help = "Path to the server control socket",
+        default_value = "/run/rkvm/server.sock"
+    )]
+    socket: PathBuf,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args = Args::from_args();
+    if let Err(err) = run(args.socket, args.command).await {
+        eprintln!("Error: {:#}", err);
+        process::exit(1);
+    }
+}
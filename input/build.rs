@@ -6,7 +6,7 @@ use std::path::PathBuf;
 fn main() {
     match env::var("CARGO_CFG_TARGET_OS").unwrap().as_str() {
         "windows" => return,
-        "linux" => {}
+        "linux" | "freebsd" => {}
         _ => panic!("Unsupported target OS"),
     }
 
@@ -0,0 +1,82 @@
+use crate::event::{Capabilities, Event, LockState};
+use async_trait::async_trait;
+use std::io::Error;
+
+// Everything platform specific about rkvm. Each platform provides one implementation, picked in lib.rs
+// with cfg flags, so that adding a platform doesn't require changes to the server or the client.
+#[async_trait]
+pub trait Backend {
+    type Manager: Manager;
+    type Writer: Writer;
+
+    // Takes over the local input devices, used by the server.
+    async fn manager() -> Result<Self::Manager, Error>;
+
+    // Creates a virtual input device, used by the client.
+    async fn writer() -> Result<Self::Writer, Error>;
+}
+
+// The local input devices as seen by the server: the source of all events and the sink for the ones
+// which aren't forwarded.
+#[async_trait]
+pub trait Manager: Send {
+    async fn read(&mut self) -> Result<Event, Error>;
+
+    async fn write(&mut self, event: Event) -> Result<(), Error>;
+
+    fn notify(&mut self, message: String);
+
+    // Like notify, with buttons given as identifiers and labels. The identifier of the button clicked is passed to the
+    // callback, which might be called from another thread. Where notifications can't have buttons only the message
+    // is shown.
+    fn notify_with_actions(
+        &mut self,
+        message: String,
+        _actions: &[(&str, &str)],
+        _callback: Box<dyn FnOnce(String) + Send>,
+    ) {
+        self.notify(message);
+    }
+
+    fn set_indicator_led(&mut self, on: bool);
+
+    // Locks the local desktop, see session::lock.
+    fn lock_session(&mut self) {}
+
+    // The lock state of the local keyboards, None if it can't be determined.
+    fn lock_state(&mut self) -> Option<LockState> {
+        None
+    }
+
+    // What the local devices can produce, None if it can't be determined.
+    fn capabilities(&mut self) -> Option<Capabilities> {
+        None
+    }
+}
+
+// A virtual input device which injects events received by the client.
+#[async_trait]
+pub trait Writer: Send {
+    async fn write(&mut self, event: Event) -> Result<(), Error>;
+
+    fn notify(&mut self, message: String);
+
+    // The lock state of the machine, None if it can't be determined.
+    fn lock_state(&mut self) -> Option<LockState> {
+        None
+    }
+
+    // What the virtual device can write, None if anything can be.
+    fn capabilities(&mut self) -> Option<Capabilities> {
+        None
+    }
+
+    // Makes the virtual device capable of what the devices on the other end are, where a device has to declare that
+    // up front. Elsewhere anything can be written already.
+    async fn mirror(&mut self, _capabilities: &Capabilities) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // Creates the virtual device again after writing to it has failed, capable of the same as before.
+    async fn recreate(&mut self) -> Result<(), Error>;
+}
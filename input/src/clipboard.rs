@@ -1,6 +1,9 @@
 use arboard::Clipboard;
 use log::{info, warn};
 
+// The only format supported so far, others can be added to formats, get and set.
+pub const TEXT: &str = "text/plain;charset=utf-8";
+
 pub fn set_text(text: String) {
     info!("Set clip text to {}", text);
     match Clipboard::new() {
@@ -33,4 +36,61 @@ pub fn get_text() -> Option<String> {
         }
     }
     None
-}
\ No newline at end of file
+}
+
+// The MIME types the current clipboard content is available in, most preferred first.
+pub fn formats() -> Vec<String> {
+    match get_text() {
+        Some(_) => vec![TEXT.to_owned()],
+        None => Vec::new(),
+    }
+}
+
+// The first of the offered MIME types which can be put into the clipboard here.
+pub fn pick(offered: &[String]) -> Option<String> {
+    offered.iter().find(|mime_type| *mime_type == TEXT).cloned()
+}
+
+pub fn get(mime_type: &str) -> Option<Vec<u8>> {
+    match mime_type {
+        TEXT => get_text().map(String::into_bytes),
+        _ => {
+            warn!("Unsupported clipboard format {}", mime_type);
+            None
+        }
+    }
+}
+
+// With sanitize, text is reduced to what pastes the same everywhere, see sanitize.
+pub fn set(mime_type: &str, data: Vec<u8>, sanitize: bool) {
+    match mime_type {
+        TEXT => match String::from_utf8(data) {
+            Ok(text) if sanitize => set_text(self::sanitize(&text)),
+            Ok(text) => set_text(text),
+            Err(e) => warn!("Received invalid clipboard text {}", e),
+        },
+        _ => warn!("Unsupported clipboard format {}", mime_type),
+    }
+}
+
+// Drops control characters other than tabs and line breaks, byte order marks and zero width characters, which tend to
+// cause surprises when pasted, and converts line endings to the ones used on this platform.
+pub fn sanitize(text: &str) -> String {
+    let line_ending = if cfg!(windows) { "\r\n" } else { "\n" };
+    let mut sanitized = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' => {
+                chars.next_if_eq(&'\n');
+                sanitized.push_str(line_ending);
+            }
+            '\n' => sanitized.push_str(line_ending),
+            '\u{feff}' | '\u{200b}' | '\u{2060}' => {}
+            c if c.is_control() && c != '\t' => {}
+            c => sanitized.push(c),
+        }
+    }
+
+    sanitized
+}
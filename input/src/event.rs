@@ -1,16 +1,22 @@
 mod button;
+mod hid;
 mod key;
 
 pub use button::Button;
 pub use key::Key;
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum Event {
     MouseScroll { delta: i32 },
     MouseMove { axis: Axis, delta: i32 },
-    Key { direction: Direction, kind: KeyKind }
+    Key { direction: Direction, kind: KeyKind },
+    // The scancode keyboards report right before the key event it belongs to, which some applications and games go by.
+    Scan { code: u32 },
+    // A switch of the machine, like the lid or tablet mode, by its Linux code. On is closed or in tablet mode.
+    Switch { code: u16, on: bool },
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -30,3 +36,46 @@ pub enum KeyKind {
     Key(Key),
     Button(Button),
 }
+
+// What the input devices of a machine can produce, so that the virtual device on the other end can be made to match.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub keys: HashSet<KeyKind>,
+    pub motion: bool,
+    pub scroll: bool,
+}
+
+impl Capabilities {
+    pub fn merge(&mut self, other: &Capabilities) {
+        self.keys.extend(&other.keys);
+        self.motion |= other.motion;
+        self.scroll |= other.scroll;
+    }
+}
+
+// The state of the lock keys on a machine, kept in line across machines when focus moves.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LockState {
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
+
+impl LockState {
+    // Key presses which turn this state into the wanted one.
+    pub fn toggles(self, wanted: LockState) -> Vec<Event> {
+        let mut keys = Vec::new();
+        if self.caps_lock != wanted.caps_lock {
+            keys.push(Key::CapsLock);
+        }
+        if self.num_lock != wanted.num_lock {
+            keys.push(Key::NumLock);
+        }
+
+        keys.into_iter()
+            .flat_map(|key| {
+                let event = |direction| Event::Key { direction, kind: KeyKind::Key(key) };
+                vec![event(Direction::Down), event(Direction::Up)]
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,227 @@
+use crate::event::Key;
+
+// Keys as USB HID usages, the page in the upper and the usage id in the lower 16 bits. HID is what keyboards speak
+// on the wire and the one numbering every platform already translates from, so a backend for a new platform maps its
+// own codes to usages instead of to each of the keys. Keys without a usage are left out, where several usages produce
+// the same key the first one listed is used.
+const KEYBOARD: u32 = 0x07 << 16;
+const GENERIC_DESKTOP: u32 = 0x01 << 16;
+const CONSUMER: u32 = 0x0C << 16;
+
+const USAGES: &[(Key, u32)] = &[
+    (Key::A, KEYBOARD | 0x04),
+    (Key::B, KEYBOARD | 0x05),
+    (Key::C, KEYBOARD | 0x06),
+    (Key::D, KEYBOARD | 0x07),
+    (Key::E, KEYBOARD | 0x08),
+    (Key::F, KEYBOARD | 0x09),
+    (Key::G, KEYBOARD | 0x0A),
+    (Key::H, KEYBOARD | 0x0B),
+    (Key::I, KEYBOARD | 0x0C),
+    (Key::J, KEYBOARD | 0x0D),
+    (Key::K, KEYBOARD | 0x0E),
+    (Key::L, KEYBOARD | 0x0F),
+    (Key::M, KEYBOARD | 0x10),
+    (Key::N, KEYBOARD | 0x11),
+    (Key::O, KEYBOARD | 0x12),
+    (Key::P, KEYBOARD | 0x13),
+    (Key::Q, KEYBOARD | 0x14),
+    (Key::R, KEYBOARD | 0x15),
+    (Key::S, KEYBOARD | 0x16),
+    (Key::T, KEYBOARD | 0x17),
+    (Key::U, KEYBOARD | 0x18),
+    (Key::V, KEYBOARD | 0x19),
+    (Key::W, KEYBOARD | 0x1A),
+    (Key::X, KEYBOARD | 0x1B),
+    (Key::Y, KEYBOARD | 0x1C),
+    (Key::Z, KEYBOARD | 0x1D),
+    (Key::N1, KEYBOARD | 0x1E),
+    (Key::N2, KEYBOARD | 0x1F),
+    (Key::N3, KEYBOARD | 0x20),
+    (Key::N4, KEYBOARD | 0x21),
+    (Key::N5, KEYBOARD | 0x22),
+    (Key::N6, KEYBOARD | 0x23),
+    (Key::N7, KEYBOARD | 0x24),
+    (Key::N8, KEYBOARD | 0x25),
+    (Key::N9, KEYBOARD | 0x26),
+    (Key::N0, KEYBOARD | 0x27),
+    (Key::Enter, KEYBOARD | 0x28),
+    (Key::Esc, KEYBOARD | 0x29),
+    (Key::Backspace, KEYBOARD | 0x2A),
+    (Key::Tab, KEYBOARD | 0x2B),
+    (Key::Space, KEYBOARD | 0x2C),
+    (Key::Minus, KEYBOARD | 0x2D),
+    (Key::Equal, KEYBOARD | 0x2E),
+    (Key::LeftBrace, KEYBOARD | 0x2F),
+    (Key::RightBrace, KEYBOARD | 0x30),
+    (Key::Backslash, KEYBOARD | 0x31),
+    // The non-US # and ~ key sits where the backslash is on US keyboards.
+    (Key::Backslash, KEYBOARD | 0x32),
+    (Key::Semicolon, KEYBOARD | 0x33),
+    (Key::Apostrophe, KEYBOARD | 0x34),
+    (Key::Grave, KEYBOARD | 0x35),
+    (Key::Comma, KEYBOARD | 0x36),
+    (Key::Dot, KEYBOARD | 0x37),
+    (Key::Slash, KEYBOARD | 0x38),
+    (Key::CapsLock, KEYBOARD | 0x39),
+    (Key::F1, KEYBOARD | 0x3A),
+    (Key::F2, KEYBOARD | 0x3B),
+    (Key::F3, KEYBOARD | 0x3C),
+    (Key::F4, KEYBOARD | 0x3D),
+    (Key::F5, KEYBOARD | 0x3E),
+    (Key::F6, KEYBOARD | 0x3F),
+    (Key::F7, KEYBOARD | 0x40),
+    (Key::F8, KEYBOARD | 0x41),
+    (Key::F9, KEYBOARD | 0x42),
+    (Key::F10, KEYBOARD | 0x43),
+    (Key::F11, KEYBOARD | 0x44),
+    (Key::F12, KEYBOARD | 0x45),
+    // Print Screen, which evdev calls SysRq.
+    (Key::SysRq, KEYBOARD | 0x46),
+    (Key::ScrollLock, KEYBOARD | 0x47),
+    (Key::Pause, KEYBOARD | 0x48),
+    (Key::Insert, KEYBOARD | 0x49),
+    (Key::Home, KEYBOARD | 0x4A),
+    (Key::PageUp, KEYBOARD | 0x4B),
+    (Key::Delete, KEYBOARD | 0x4C),
+    (Key::End, KEYBOARD | 0x4D),
+    (Key::PageDown, KEYBOARD | 0x4E),
+    (Key::Right, KEYBOARD | 0x4F),
+    (Key::Left, KEYBOARD | 0x50),
+    (Key::Down, KEYBOARD | 0x51),
+    (Key::Up, KEYBOARD | 0x52),
+    (Key::NumLock, KEYBOARD | 0x53),
+    (Key::KpSlash, KEYBOARD | 0x54),
+    (Key::KpAsterisk, KEYBOARD | 0x55),
+    (Key::KpMinus, KEYBOARD | 0x56),
+    (Key::KpPlus, KEYBOARD | 0x57),
+    (Key::KpEnter, KEYBOARD | 0x58),
+    (Key::Kp1, KEYBOARD | 0x59),
+    (Key::Kp2, KEYBOARD | 0x5A),
+    (Key::Kp3, KEYBOARD | 0x5B),
+    (Key::Kp4, KEYBOARD | 0x5C),
+    (Key::Kp5, KEYBOARD | 0x5D),
+    (Key::Kp6, KEYBOARD | 0x5E),
+    (Key::Kp7, KEYBOARD | 0x5F),
+    (Key::Kp8, KEYBOARD | 0x60),
+    (Key::Kp9, KEYBOARD | 0x61),
+    (Key::Kp0, KEYBOARD | 0x62),
+    (Key::KpDott, KEYBOARD | 0x63),
+    (Key::N102nd, KEYBOARD | 0x64),
+    // The Application key, which evdev calls Compose.
+    (Key::Compose, KEYBOARD | 0x65),
+    (Key::Power, KEYBOARD | 0x66),
+    (Key::KpEqual, KEYBOARD | 0x67),
+    (Key::F13, KEYBOARD | 0x68),
+    (Key::F14, KEYBOARD | 0x69),
+    (Key::F15, KEYBOARD | 0x6A),
+    (Key::F16, KEYBOARD | 0x6B),
+    (Key::F17, KEYBOARD | 0x6C),
+    (Key::F18, KEYBOARD | 0x6D),
+    (Key::F19, KEYBOARD | 0x6E),
+    (Key::F20, KEYBOARD | 0x6F),
+    (Key::F21, KEYBOARD | 0x70),
+    (Key::F22, KEYBOARD | 0x71),
+    (Key::F23, KEYBOARD | 0x72),
+    (Key::F24, KEYBOARD | 0x73),
+    (Key::Open, KEYBOARD | 0x74),
+    (Key::Help, KEYBOARD | 0x75),
+    (Key::Props, KEYBOARD | 0x76),
+    (Key::Front, KEYBOARD | 0x77),
+    (Key::Stop, KEYBOARD | 0x78),
+    (Key::Again, KEYBOARD | 0x79),
+    (Key::Undo, KEYBOARD | 0x7A),
+    (Key::Cut, KEYBOARD | 0x7B),
+    (Key::Copy, KEYBOARD | 0x7C),
+    (Key::Paste, KEYBOARD | 0x7D),
+    (Key::Find, KEYBOARD | 0x7E),
+    (Key::Mute, KEYBOARD | 0x7F),
+    (Key::VolumeUp, KEYBOARD | 0x80),
+    (Key::VolumeDown, KEYBOARD | 0x81),
+    (Key::KpComma, KEYBOARD | 0x85),
+    (Key::Ro, KEYBOARD | 0x87),
+    (Key::KatakanaHiragana, KEYBOARD | 0x88),
+    (Key::Yen, KEYBOARD | 0x89),
+    (Key::Henkan, KEYBOARD | 0x8A),
+    (Key::Muhenkan, KEYBOARD | 0x8B),
+    (Key::KpJpComma, KEYBOARD | 0x8C),
+    (Key::Hangeul, KEYBOARD | 0x90),
+    (Key::Hanja, KEYBOARD | 0x91),
+    (Key::Katakana, KEYBOARD | 0x92),
+    (Key::Hiragana, KEYBOARD | 0x93),
+    (Key::ZenkakuHankaku, KEYBOARD | 0x94),
+    (Key::KpLeftParen, KEYBOARD | 0xB6),
+    (Key::KpRightParen, KEYBOARD | 0xB7),
+    (Key::LeftCtrl, KEYBOARD | 0xE0),
+    (Key::LeftShift, KEYBOARD | 0xE1),
+    (Key::LeftAlt, KEYBOARD | 0xE2),
+    (Key::LeftMeta, KEYBOARD | 0xE3),
+    (Key::RightCtrl, KEYBOARD | 0xE4),
+    (Key::RightShift, KEYBOARD | 0xE5),
+    (Key::RightAlt, KEYBOARD | 0xE6),
+    (Key::RightMeta, KEYBOARD | 0xE7),
+    (Key::Power, GENERIC_DESKTOP | 0x81),
+    (Key::Sleep, GENERIC_DESKTOP | 0x82),
+    (Key::WakeUp, GENERIC_DESKTOP | 0x83),
+    (Key::Menu, CONSUMER | 0x40),
+    (Key::BrightnessUp, CONSUMER | 0x6F),
+    (Key::BrightnessDown, CONSUMER | 0x70),
+    (Key::Play, CONSUMER | 0xB0),
+    (Key::PauseCd, CONSUMER | 0xB1),
+    (Key::Record, CONSUMER | 0xB2),
+    (Key::FastForward, CONSUMER | 0xB3),
+    (Key::Rewind, CONSUMER | 0xB4),
+    (Key::NextSong, CONSUMER | 0xB5),
+    (Key::PreviousSong, CONSUMER | 0xB6),
+    (Key::StopCd, CONSUMER | 0xB7),
+    (Key::EjectCd, CONSUMER | 0xB8),
+    (Key::PlayPause, CONSUMER | 0xCD),
+    (Key::VoiceCommand, CONSUMER | 0xCF),
+    (Key::Mute, CONSUMER | 0xE2),
+    (Key::VolumeUp, CONSUMER | 0xE9),
+    (Key::VolumeDown, CONSUMER | 0xEA),
+    (Key::Config, CONSUMER | 0x183),
+    (Key::WordProcessor, CONSUMER | 0x184),
+    (Key::Spreadsheet, CONSUMER | 0x186),
+    (Key::Presentation, CONSUMER | 0x188),
+    (Key::Mail, CONSUMER | 0x18A),
+    (Key::Calc, CONSUMER | 0x192),
+    (Key::File, CONSUMER | 0x194),
+    (Key::Www, CONSUMER | 0x196),
+    (Key::LogOff, CONSUMER | 0x19C),
+    (Key::Screenlock, CONSUMER | 0x19E),
+    (Key::Documents, CONSUMER | 0x1A7),
+    (Key::Keyboard, CONSUMER | 0x1AE),
+    (Key::Images, CONSUMER | 0x1B6),
+    (Key::Audio, CONSUMER | 0x1B7),
+    (Key::Video, CONSUMER | 0x1B8),
+    (Key::New, CONSUMER | 0x201),
+    (Key::Open, CONSUMER | 0x202),
+    (Key::Close, CONSUMER | 0x203),
+    (Key::Save, CONSUMER | 0x207),
+    (Key::Print, CONSUMER | 0x208),
+    (Key::Search, CONSUMER | 0x221),
+    (Key::Homepage, CONSUMER | 0x223),
+    (Key::Back, CONSUMER | 0x224),
+    (Key::Forward, CONSUMER | 0x225),
+    (Key::Stop, CONSUMER | 0x226),
+    (Key::Refresh, CONSUMER | 0x227),
+    (Key::Bookmarks, CONSUMER | 0x22A),
+    (Key::ZoomIn, CONSUMER | 0x22D),
+    (Key::ZoomOut, CONSUMER | 0x22E),
+    (Key::ZoomReset, CONSUMER | 0x22F),
+    (Key::Redo, CONSUMER | 0x279),
+    (Key::Reply, CONSUMER | 0x289),
+    (Key::ForwardMail, CONSUMER | 0x28B),
+    (Key::Send, CONSUMER | 0x28C),
+];
+
+impl Key {
+    pub fn hid_usage(self) -> Option<u32> {
+        USAGES.iter().find(|(key, _)| *key == self).map(|(_, usage)| *usage)
+    }
+
+    pub fn from_hid_usage(usage: u32) -> Option<Key> {
+        USAGES.iter().find(|(_, other)| *other == usage).map(|(key, _)| *key)
+    }
+}
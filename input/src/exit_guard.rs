@@ -0,0 +1,34 @@
+use std::panic;
+use std::process;
+
+// Releases the local input devices however the server goes down, so that the physical keyboard and mouse can't stay
+// grabbed by a process which no longer reads them. Closing the devices does the same, this covers the cases where
+// they aren't: a panic in a task other than the one holding them, or unwinding which doesn't get that far.
+pub struct ExitGuard(());
+
+impl ExitGuard {
+    // Installs a panic hook releasing the devices. A server which panicked can't be trusted to keep taking input, so
+    // the hook then exits for the service manager to restart it, instead of running on with the devices released.
+    pub fn install() -> Self {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            release_devices();
+            previous(info);
+            process::exit(101);
+        }));
+
+        ExitGuard(())
+    }
+}
+
+impl Drop for ExitGuard {
+    fn drop(&mut self) {
+        release_devices();
+    }
+}
+
+fn release_devices() {
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    crate::linux::release_devices();
+    // Windows removes the hooks of a process once it's gone.
+}
@@ -1,16 +1,26 @@
+mod backend;
 mod event;
+mod exit_guard;
 
-#[cfg(target_os = "linux")]
+// FreeBSD's evdev and uinput follow the Linux API, so it shares the backend.
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
 mod linux;
 
 #[cfg(target_os = "windows")]
 mod windows;
 pub mod clipboard;
+pub mod osd;
+pub mod pointer;
+pub mod session;
 
-#[cfg(target_os = "linux")]
-pub use linux::{EventManager, EventWriter};
+// The backend of the platform being built for, the server and client only go through the Backend trait.
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+pub use linux::{EventManager, EventWriter, Linux as Platform};
 
 #[cfg(target_os = "windows")]
-pub use windows::{EventManager, EventWriter};
+pub use windows::{EventManager, EventWriter, Windows as Platform};
 
-pub use event::{Axis, Button, Direction, Event, Key, KeyKind};
+pub use backend::{Backend, Manager, Writer};
+pub use exit_guard::ExitGuard;
+
+pub use event::{Axis, Button, Capabilities, Direction, Event, Key, KeyKind, LockState};
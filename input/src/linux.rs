@@ -1,4 +1,6 @@
+mod backend;
 mod device_id;
+mod devices;
 mod event;
 mod event_manager;
 mod event_reader;
@@ -6,5 +8,7 @@ mod event_writer;
 mod glue;
 mod privileges;
 
+pub use backend::Linux;
+pub(crate) use devices::release as release_devices;
 pub use event_manager::EventManager;
 pub use event_writer::EventWriter;
@@ -0,0 +1,89 @@
+use crate::backend::{Backend, Manager, Writer};
+use crate::event::{Capabilities, Event, LockState};
+use crate::linux::{EventManager, EventWriter};
+use async_trait::async_trait;
+use std::io::Error;
+
+// Reads from evdev devices and writes through uinput.
+pub struct Linux;
+
+#[async_trait]
+impl Backend for Linux {
+    type Manager = EventManager;
+    type Writer = EventWriter;
+
+    async fn manager() -> Result<Self::Manager, Error> {
+        EventManager::new().await
+    }
+
+    async fn writer() -> Result<Self::Writer, Error> {
+        EventWriter::new().await
+    }
+}
+
+#[async_trait]
+impl Manager for EventManager {
+    async fn read(&mut self) -> Result<Event, Error> {
+        EventManager::read(self).await
+    }
+
+    async fn write(&mut self, event: Event) -> Result<(), Error> {
+        EventManager::write(self, event).await
+    }
+
+    fn notify(&mut self, message: String) {
+        EventManager::notify(self, message)
+    }
+
+    fn notify_with_actions(
+        &mut self,
+        message: String,
+        actions: &[(&str, &str)],
+        callback: Box<dyn FnOnce(String) + Send>,
+    ) {
+        EventManager::notify_with_actions(self, message, actions, callback)
+    }
+
+    fn lock_session(&mut self) {
+        crate::session::lock();
+    }
+
+    fn set_indicator_led(&mut self, on: bool) {
+        EventManager::set_indicator_led(self, on)
+    }
+
+    fn lock_state(&mut self) -> Option<LockState> {
+        EventManager::lock_state(self)
+    }
+
+    fn capabilities(&mut self) -> Option<Capabilities> {
+        Some(EventManager::capabilities(self))
+    }
+}
+
+#[async_trait]
+impl Writer for EventWriter {
+    async fn write(&mut self, event: Event) -> Result<(), Error> {
+        EventWriter::write(self, event).await
+    }
+
+    fn notify(&mut self, message: String) {
+        EventWriter::notify(self, message)
+    }
+
+    fn lock_state(&mut self) -> Option<LockState> {
+        EventWriter::lock_state(self)
+    }
+
+    fn capabilities(&mut self) -> Option<Capabilities> {
+        Some(EventWriter::capabilities(self))
+    }
+
+    async fn mirror(&mut self, capabilities: &Capabilities) -> Result<(), Error> {
+        EventWriter::mirror(self, capabilities).await
+    }
+
+    async fn recreate(&mut self) -> Result<(), Error> {
+        EventWriter::recreate(self).await
+    }
+}
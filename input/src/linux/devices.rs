@@ -0,0 +1,48 @@
+use std::os::unix::io::RawFd;
+use std::sync::Mutex;
+
+nix::ioctl_write_int!(eviocgrab, b'E', 0x90);
+nix::ioctl_none!(ui_dev_destroy, b'U', 2);
+
+enum Device {
+    // A physical device we hold the grab of.
+    Grabbed(RawFd),
+    // One of our own uinput devices.
+    Virtual(RawFd),
+}
+
+// Devices are tracked by their file descriptors, which is all releasing them takes. They have to be forgotten before
+// the descriptors are closed, since the numbers get reused.
+static DEVICES: Mutex<Vec<Device>> = Mutex::new(Vec::new());
+
+pub(crate) fn grabbed(fd: RawFd) {
+    lock().push(Device::Grabbed(fd));
+}
+
+pub(crate) fn created(fd: RawFd) {
+    lock().push(Device::Virtual(fd));
+}
+
+pub(crate) fn forget(fd: RawFd) {
+    lock().retain(|device| match device {
+        Device::Grabbed(tracked) | Device::Virtual(tracked) => *tracked != fd,
+    });
+}
+
+// Ungrabs the physical devices and destroys the virtual ones, without closing anything. Safe to call from a panic
+// hook while the devices are still in use, reading from them afterwards just fails.
+pub(crate) fn release() {
+    let mut devices = lock();
+    for device in devices.drain(..) {
+        // Nothing to be done if either fails, the device is most likely gone already.
+        let _ = match device {
+            Device::Grabbed(fd) => unsafe { eviocgrab(fd, 0) },
+            Device::Virtual(fd) => unsafe { ui_dev_destroy(fd) },
+        };
+    }
+}
+
+// A panic while holding the lock mustn't keep the devices from being released.
+fn lock() -> std::sync::MutexGuard<'static, Vec<Device>> {
+    DEVICES.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
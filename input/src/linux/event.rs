@@ -24,6 +24,8 @@ impl Event {
                 direction: Direction::Down,
                 kind,
             } => (glue::EV_KEY as _, kind.to_raw(), 1),
+            Event::Scan { code } => (glue::EV_MSC as _, glue::MSC_SCAN as _, code as i32),
+            Event::Switch { code, on } => (glue::EV_SW as _, code, on as i32),
         };
 
         input_event {
@@ -56,6 +58,11 @@ impl Event {
                 direction: Direction::Down,
                 kind: KeyKind::from_raw(code as _)?,
             },
+            (glue::EV_MSC, glue::MSC_SCAN, value) => Event::Scan { code: value as u32 },
+            (glue::EV_SW, code, value) => Event::Switch {
+                code: code as _,
+                on: value != 0,
+            },
             _ => return None,
         };
 
@@ -1,29 +1,62 @@
-use crate::event::Event;
+use crate::event::{Capabilities, Event, LockState};
 use crate::linux::event_reader::{EventReader, OpenError};
 use crate::linux::event_writer::EventWriter;
+use crate::linux::glue;
+#[cfg(target_os = "linux")]
 use futures::StreamExt;
+#[cfg(target_os = "linux")]
 use inotify::{Inotify, WatchMask};
+use std::collections::{HashMap, HashSet};
 use std::io::{Error, ErrorKind};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use nix::libc;
 use tokio::fs;
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
-use tokio::sync::oneshot::{self, Receiver};
+use tokio::sync::watch;
 use tokio::time;
 use crate::linux::privileges::drop_privileges;
 
 const EVENT_PATH: &str = "/dev/input";
+// How often the watchdog looks for devices which aren't being read, in case a hotplug notification was missed
+// or a reader failed while its device stayed around.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(5);
 
 pub struct EventManager {
     writer: EventWriter,
-    event_receiver: UnboundedReceiver<Result<Event, Error>>,
-    watcher_receiver: Receiver<Error>,
+    event_receiver: UnboundedReceiver<Event>,
+    led_sender: watch::Sender<bool>,
+    // Updated by the reader tasks of grabbed keyboards.
+    lock_state: Arc<Mutex<Option<LockState>>>,
+    capabilities: Arc<Mutex<HashMap<PathBuf, Capabilities>>>,
+}
+
+// Everything a reader task needs, shared by all of them.
+#[derive(Clone)]
+struct Readers {
+    sender: UnboundedSender<Event>,
+    led_receiver: watch::Receiver<bool>,
+    lock_state: Arc<Mutex<Option<LockState>>>,
+    // Devices being read, or skipped for good like our own virtual devices. Readers remove their device when they stop.
+    known: Arc<Mutex<HashSet<PathBuf>>>,
+    // What each device being read can produce.
+    capabilities: Arc<Mutex<HashMap<PathBuf, Capabilities>>>,
 }
 
 impl EventManager {
     pub async fn new() -> Result<Self, Error> {
         let (event_sender, event_receiver) = mpsc::unbounded_channel();
+        let (led_sender, led_receiver) = watch::channel(false);
+        let lock_state = Arc::new(Mutex::new(None));
+        let capabilities = Arc::new(Mutex::new(HashMap::new()));
+        let readers = Readers {
+            sender: event_sender,
+            led_receiver,
+            lock_state: lock_state.clone(),
+            known: Arc::new(Mutex::new(HashSet::new())),
+            capabilities: capabilities.clone(),
+        };
 
         // HACK: When rkvm is run from the terminal, a race condition happens where the enter key
         // release event is swallowed and the key will remain in a "pressed" state until the user manually presses it again.
@@ -37,7 +70,7 @@ impl EventManager {
 
         let mut read_dir = fs::read_dir(EVENT_PATH).await?;
         while let Some(entry) = read_dir.next_entry().await? {
-            spawn_reader(&entry.path(), event_sender.clone()).await?;
+            spawn_reader(&entry.path(), &readers).await?;
         }
 
         let writer = EventWriter::new_no_drop().await?;
@@ -45,70 +78,123 @@ impl EventManager {
         // Sleep for a while to give userspace time to register our devices.
         time::sleep(Duration::from_secs(1)).await;
 
-        let (watcher_sender, watcher_receiver) = oneshot::channel();
-        tokio::spawn(async {
-            if let Err(err) = handle_notify(event_sender).await {
-                let _ = watcher_sender.send(err);
+        // FreeBSD has no inotify, new devices are only picked up by the watchdog there.
+        #[cfg(target_os = "linux")]
+        let notify_readers = readers.clone();
+        #[cfg(target_os = "linux")]
+        tokio::spawn(async move {
+            // The watchdog still picks up new devices, just not as quickly.
+            if let Err(err) = handle_notify(&notify_readers).await {
+                log::warn!("Stopped watching for new devices: {}", err);
             }
         });
+        tokio::spawn(watchdog(readers));
 
         drop_privileges();
         Ok(EventManager {
             writer,
             event_receiver,
-            watcher_receiver,
+            led_sender,
+            lock_state,
+            capabilities,
         })
     }
 
     pub async fn read(&mut self) -> Result<Event, Error> {
-        if let Ok(err) = self.watcher_receiver.try_recv() {
-            return Err(err);
-        }
-
         self.event_receiver
             .recv()
             .await
-            .ok_or_else(|| Error::new(ErrorKind::Other, "All devices closed"))?
+            .ok_or_else(|| Error::new(ErrorKind::Other, "All devices closed"))
     }
 
     pub async fn write(&mut self, event: Event) -> Result<(), Error> {
-        self.writer.write(event).await
+        match self.writer.write(event).await {
+            Err(err) if is_device_gone(&err) => {
+                // The uinput node went away underneath us (module reload, container restart), so the old device
+                // is useless.  Create a new one and try again.
+                log::warn!("Virtual device is gone ({}), recreating it", err);
+                self.writer.recreate().await.map_err(|err| {
+                    Error::new(err.kind(), format!("Failed to recreate the virtual device.  {}", err))
+                })?;
+                log::info!("Recreated the virtual device");
+                self.writer.write(event).await
+            }
+            result => result,
+        }
     }
 
     pub fn notify(&mut self, message: String) {
         self.writer.notify(message);
     }
+
+    pub fn notify_with_actions(
+        &mut self,
+        message: String,
+        actions: &[(&str, &str)],
+        callback: Box<dyn FnOnce(String) + Send>,
+    ) {
+        self.writer.notify_with_actions(message, actions, callback);
+    }
+
+    // Lights the Scroll Lock LED on all grabbed keyboards, including ones plugged in later.
+    pub fn set_indicator_led(&mut self, on: bool) {
+        let _ = self.led_sender.send(on);
+    }
+
+    // Everything the grabbed devices can produce between them.
+    pub fn capabilities(&mut self) -> Capabilities {
+        let mut merged = Capabilities::default();
+        for capabilities in self.capabilities.lock().unwrap().values() {
+            merged.merge(capabilities);
+        }
+
+        merged
+    }
+
+    // As last seen on any of the grabbed keyboards, they all share the lock state.
+    pub fn lock_state(&mut self) -> Option<LockState> {
+        *self.lock_state.lock().unwrap()
+    }
+}
+
+fn is_device_gone(err: &Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::ENODEV) | Some(libc::ENOENT) | Some(libc::EBADF) | Some(libc::ENXIO))
 }
 
-async fn spawn_reader(
-    path: &Path,
-    sender: UnboundedSender<Result<Event, Error>>,
-) -> Result<(), Error> {
-    if path.is_dir() {
+async fn spawn_reader(path: &Path, readers: &Readers) -> Result<(), Error> {
+    if readers.known.lock().unwrap().contains(path) {
         return Ok(());
     }
 
     // Skip non input event files.
-    if path
-        .file_name()
-        .and_then(|name| name.to_str())
-        .map(|name| !name.starts_with("event"))
-        .unwrap_or(true)
+    if path.is_dir()
+        || path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| !name.starts_with("event"))
+            .unwrap_or(true)
     {
+        readers.known.lock().unwrap().insert(path.to_owned());
         return Ok(());
     }
 
     let reader = match EventReader::open(&path).await {
         Ok(reader) => reader,
         Err(OpenError::Io(err)) => return Err(Error::new(err.kind(), format!("Failed to open {}.  {}", path.display(), err))),
-        Err(OpenError::AlreadyOpened) => return Ok(()),
+        Err(OpenError::AlreadyOpened) => {
+            readers.known.lock().unwrap().insert(path.to_owned());
+            return Ok(());
+        }
     };
 
-    tokio::spawn(handle_events(reader, sender));
+    readers.known.lock().unwrap().insert(path.to_owned());
+    readers.capabilities.lock().unwrap().insert(path.to_owned(), reader.capabilities());
+    tokio::spawn(handle_events(path.to_owned(), reader, readers.clone()));
     Ok(())
 }
 
-async fn handle_notify(sender: UnboundedSender<Result<Event, Error>>) -> Result<(), Error> {
+#[cfg(target_os = "linux")]
+async fn handle_notify(readers: &Readers) -> Result<(), Error> {
     let mut inotify = Inotify::init()?;
     inotify.add_watch(EVENT_PATH, WatchMask::CREATE)?;
 
@@ -120,23 +206,72 @@ async fn handle_notify(sender: UnboundedSender<Result<Event, Error>>) -> Result<
 
         if let Some(name) = event.name {
             let path = Path::new(EVENT_PATH).join(&name);
-            spawn_reader(&path, sender.clone()).await?;
+            if let Err(err) = spawn_reader(&path, readers).await {
+                log::warn!("{}", err);
+            }
         }
     }
 
     Ok(())
 }
 
-async fn handle_events(mut reader: EventReader, sender: UnboundedSender<Result<Event, Error>>) {
+// Re-grabs devices whose reader has stopped, so that a device failing mid-grab doesn't require a restart.
+async fn watchdog(readers: Readers) {
+    let mut interval = time::interval(RESCAN_INTERVAL);
     loop {
-        let result = match reader.read().await {
-            Ok(event) => sender.send(Ok(event)).is_ok(),
-            // This happens if the device is disconnected.
-            // In that case simply terminate the reading task.
-            Err(ref err) if err.raw_os_error() == Some(libc::ENODEV) => false,
+        interval.tick().await;
+
+        let mut read_dir = match fs::read_dir(EVENT_PATH).await {
+            Ok(read_dir) => read_dir,
             Err(err) => {
-                let _ = sender.send(Err(err));
-                false
+                log::warn!("Failed to list {}: {}", EVENT_PATH, err);
+                continue;
+            }
+        };
+
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            let path = entry.path();
+            if readers.known.lock().unwrap().contains(&path) {
+                continue;
+            }
+
+            match spawn_reader(&path, &readers).await {
+                Ok(()) if readers.known.lock().unwrap().contains(&path) => log::info!("Grabbed {}", path.display()),
+                Ok(()) => {}
+                // Most likely a device we lack the permissions for, which would be logged every time around.
+                Err(err) => log::debug!("{}", err),
+            }
+        }
+    }
+}
+
+async fn handle_events(path: PathBuf, mut reader: EventReader, readers: Readers) {
+    let Readers { sender, mut led_receiver, lock_state, known, capabilities } = readers;
+    if *led_receiver.borrow_and_update() {
+        set_indicator_led(&mut reader, true);
+    }
+
+    loop {
+        // LED changes come in between key events, so this is current by the time a switch is triggered.
+        if let Some(state) = reader.lock_state() {
+            *lock_state.lock().unwrap() = Some(state);
+        }
+
+        let result = tokio::select! {
+            result = reader.read() => match result {
+                Ok(event) => sender.send(event).is_ok(),
+                // This happens if the device is disconnected.
+                // In that case simply terminate the reading task.
+                Err(ref err) if err.raw_os_error() == Some(libc::ENODEV) => false,
+                Err(err) => {
+                    log::warn!("Failed to read {}: {}.  Retrying later", path.display(), err);
+                    false
+                }
+            },
+            changed = led_receiver.changed() => {
+                let on = *led_receiver.borrow();
+                set_indicator_led(&mut reader, on);
+                changed.is_ok()
             }
         };
 
@@ -144,4 +279,15 @@ async fn handle_events(mut reader: EventReader, sender: UnboundedSender<Result<E
             break;
         }
     }
+
+    // Dropping the reader releases the grab, the watchdog picks the device up again if it's still there.
+    drop(reader);
+    capabilities.lock().unwrap().remove(&path);
+    known.lock().unwrap().remove(&path);
+}
+
+fn set_indicator_led(reader: &mut EventReader, on: bool) {
+    if let Err(err) = reader.set_led(glue::LED_SCROLLL, on) {
+        log::warn!("Failed to set indicator LED: {}", err);
+    }
 }
@@ -1,5 +1,5 @@
-use crate::event::Event;
-use crate::linux::device_id;
+use crate::event::{Capabilities, Event, KeyKind, LockState};
+use crate::linux::{device_id, devices};
 use crate::linux::glue::{self, libevdev, libevdev_uinput};
 use std::fs::{File, OpenOptions};
 use std::io::Error;
@@ -88,6 +88,8 @@ impl EventReader {
         }
 
         let uinput = unsafe { uinput.assume_init() };
+        devices::grabbed(file.as_raw_fd());
+        devices::created(unsafe { glue::libevdev_uinput_get_fd(uinput) });
         Ok(Self {
             file,
             evdev,
@@ -121,6 +123,11 @@ impl EventReader {
                 Err(_) => continue, // This means it would block.
             };
 
+            // Key repeats are generated by whichever machine ends up receiving the key, never forward them.
+            if event.type_ as u32 == glue::EV_KEY && event.value == 2 {
+                continue;
+            }
+
             if let Some(event) = Event::from_raw(event) {
                 return Ok(event);
             }
@@ -140,10 +147,49 @@ impl EventReader {
             }
         }
     }
+
+    // None if the device isn't a keyboard. The LED values are kept up to date by libevdev as events are read.
+    pub fn lock_state(&self) -> Option<LockState> {
+        if unsafe { glue::libevdev_has_event_code(self.evdev, glue::EV_LED, glue::LED_CAPSL) } != 1 {
+            return None;
+        }
+
+        let led = |code| unsafe { glue::libevdev_get_event_value(self.evdev, glue::EV_LED, code) } != 0;
+        Some(LockState {
+            caps_lock: led(glue::LED_CAPSL),
+            num_lock: led(glue::LED_NUML),
+        })
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        unsafe { capabilities(self.evdev) }
+    }
+
+    pub fn set_led(&mut self, code: u32, on: bool) -> Result<(), Error> {
+        // Not every device we grab is a keyboard.
+        if unsafe { glue::libevdev_has_event_code(self.evdev, glue::EV_LED, code) } != 1 {
+            return Ok(());
+        }
+
+        let value = if on {
+            glue::libevdev_led_value_LIBEVDEV_LED_ON
+        } else {
+            glue::libevdev_led_value_LIBEVDEV_LED_OFF
+        };
+
+        let ret = unsafe { glue::libevdev_kernel_set_led_value(self.evdev, code, value) };
+        if ret < 0 {
+            return Err(Error::from_raw_os_error(-ret));
+        }
+
+        Ok(())
+    }
 }
 
 impl Drop for EventReader {
     fn drop(&mut self) {
+        devices::forget(self.file.as_raw_fd());
+        devices::forget(unsafe { glue::libevdev_uinput_get_fd(self.uinput) });
         unsafe {
             glue::libevdev_uinput_destroy(self.uinput);
             glue::libevdev_free(self.evdev);
@@ -163,3 +209,16 @@ impl From<Error> for OpenError {
         OpenError::Io(err)
     }
 }
+
+// What a device, grabbed or our own, has enabled.
+pub(crate) unsafe fn capabilities(evdev: *mut libevdev) -> Capabilities {
+    let has = |r#type, code| glue::libevdev_has_event_code(evdev, r#type, code) == 1;
+    Capabilities {
+        keys: (0..=glue::KEY_MAX)
+            .filter(|code| has(glue::EV_KEY, *code))
+            .filter_map(|code| KeyKind::from_raw(code as _))
+            .collect(),
+        motion: has(glue::EV_REL, glue::REL_X) && has(glue::EV_REL, glue::REL_Y),
+        scroll: has(glue::EV_REL, glue::REL_WHEEL),
+    }
+}
@@ -1,15 +1,45 @@
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
 use std::io::{Error, ErrorKind};
 use std::mem::MaybeUninit;
 use std::ops::RangeInclusive;
+use std::os::raw::c_int;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::thread;
 
 use log::debug;
+use nix::libc;
 use notify_rust::Notification;
 
-use crate::event::Event;
-use crate::linux::{device_id, privileges};
+use crate::event::{Capabilities, Event, Key, KeyKind, LockState};
+use crate::linux::event_reader;
+use crate::linux::{device_id, devices, privileges};
 use crate::linux::glue::{self, input_event, libevdev, libevdev_uinput};
 
+// Keys and pointer go through devices of their own, desktops and games tend to get confused by a device which is both,
+// and per-device settings like pointer acceleration can only be applied to one which is only a pointer.
 pub struct EventWriter {
+    keyboard: Device,
+    pointer: Device,
+    // Lid and tablet mode, see Event::Switch.
+    switches: Device,
+    // Our own keyboard opened for reading, the LEDs set on it by the desktop reflect the lock state.
+    leds: Option<(File, *mut libevdev)>,
+    // Both kept for when the devices are created again, the keyboard the repeat was read from may not be accessible
+    // by then.
+    mirrored: Option<Capabilities>,
+    repeat: Repeat,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Keyboard,
+    Pointer,
+    Switches,
+}
+
+struct Device {
     evdev: *mut libevdev,
     uinput: *mut libevdev_uinput,
 }
@@ -17,23 +47,136 @@ pub struct EventWriter {
 impl EventWriter {
     pub async fn new() -> Result<Self, Error> {
         tokio::task::spawn_blocking(|| -> Result<Self, Error> {
-            return Self::new_sync(true);
+            return Self::new_sync(true, None, local_repeat());
         }).await?
     }
 
     pub async fn new_no_drop() -> Result<Self, Error> {
         tokio::task::spawn_blocking(|| -> Result<Self, Error> {
-            return Self::new_sync(false);
+            return Self::new_sync(false, None, local_repeat());
         }).await?
     }
 
-    fn new_sync(drop_privileges: bool) -> Result<Self, Error> {
+    // Creates the devices again, for when they have gone away underneath us, still mirroring the other end's if they
+    // did.
+    pub async fn recreate(&mut self) -> Result<(), Error> {
+        let (mirrored, repeat) = (self.mirrored.clone(), self.repeat);
+        *self = tokio::task::spawn_blocking(move || Self::new_sync(false, mirrored.as_ref(), repeat)).await??;
+
+        Ok(())
+    }
+
+    // Replaces the device with one that can produce what the other end's devices can. Privileges have been dropped by
+    // then, so this needs access to /dev/uinput as the user the client runs as.
+    pub async fn mirror(&mut self, capabilities: &Capabilities) -> Result<(), Error> {
+        let capabilities = capabilities.clone();
+        let repeat = self.repeat;
+        *self = tokio::task::spawn_blocking(move || Self::new_sync(false, Some(&capabilities), repeat)).await??;
+
+        Ok(())
+    }
+
+    fn new_sync(drop_privileges: bool, capabilities: Option<&Capabilities>, repeat: Repeat) -> Result<Self, Error> {
+        let keyboard = Device::new(Kind::Keyboard, capabilities, repeat)?;
+        let pointer = Device::new(Kind::Pointer, capabilities, repeat)?;
+        let switches = Device::new(Kind::Switches, capabilities, repeat)?;
+        // Opened before dropping privileges, the device node is only accessible to root.
+        let leds = unsafe { open_leds(keyboard.uinput) };
+
+        // ok now maybe drop
+        if drop_privileges {
+            privileges::drop_privileges();
+        }
+        Ok(Self {
+            keyboard,
+            pointer,
+            switches,
+            leds,
+            mirrored: capabilities.cloned(),
+            repeat,
+        })
+    }
+
+    pub fn lock_state(&mut self) -> Option<LockState> {
+        let (_, evdev) = self.leds.as_ref()?;
+        let evdev = *evdev;
+
+        // Catch up with LED changes, libevdev updates its state as the events are read.
+        loop {
+            let mut event = MaybeUninit::uninit();
+            let ret = unsafe {
+                glue::libevdev_next_event(evdev, glue::libevdev_read_flag_LIBEVDEV_READ_FLAG_NORMAL, event.as_mut_ptr())
+            };
+            if ret < 0 {
+                break;
+            }
+        }
+
+        let led = |code| unsafe { glue::libevdev_get_event_value(evdev, glue::EV_LED, code) } != 0;
+        Some(LockState {
+            caps_lock: led(glue::LED_CAPSL),
+            num_lock: led(glue::LED_NUML),
+        })
+    }
+
+    pub fn capabilities(&mut self) -> Capabilities {
+        let mut capabilities = unsafe { event_reader::capabilities(self.keyboard.evdev) };
+        capabilities.merge(&unsafe { event_reader::capabilities(self.pointer.evdev) });
+        capabilities
+    }
+
+    pub async fn write(&mut self, event: Event) -> Result<(), Error> {
+        let device = match event {
+            Event::MouseMove { .. } | Event::MouseScroll { .. } | Event::Key { kind: KeyKind::Button(_), .. } => &self.pointer,
+            Event::Key { kind: KeyKind::Key(_), .. } | Event::Scan { .. } => &self.keyboard,
+            Event::Switch { .. } => &self.switches,
+        };
+
+        device.write_raw(event.to_raw())
+    }
+
+    pub fn notify(&mut self, message: String) {
+        if let Err(e) = Notification::new()
+            .summary("RKVM")
+            .body(message.as_str())
+            .show() {
+            debug!("Failed to notify {}", e);
+        }
+    }
+
+    // Waiting for a button to be clicked blocks, so it's done on a thread of its own which ends with the notification.
+    pub fn notify_with_actions(
+        &mut self,
+        message: String,
+        actions: &[(&str, &str)],
+        callback: Box<dyn FnOnce(String) + Send>,
+    ) {
+        let mut notification = Notification::new();
+        notification.summary("RKVM").body(message.as_str());
+        for (identifier, label) in actions {
+            notification.action(identifier, label);
+        }
+
+        thread::spawn(move || match notification.show() {
+            Ok(handle) => handle.wait_for_action(|action| {
+                if action != "__closed" && action != "default" {
+                    callback(action.to_owned());
+                }
+            }),
+            Err(e) => debug!("Failed to notify {}", e),
+        });
+    }
+
+}
+
+impl Device {
+    fn new(kind: Kind, capabilities: Option<&Capabilities>, repeat: Repeat) -> Result<Self, Error> {
         let evdev = unsafe { glue::libevdev_new() };
         if evdev.is_null() {
             return Err(Error::new(ErrorKind::Other, "Failed to create device"));
         }
 
-        if let Err(err) = unsafe { setup_evdev(evdev) } {
+        if let Err(err) = unsafe { setup_evdev(evdev, kind, capabilities, repeat) } {
             unsafe {
                 glue::libevdev_free(evdev);
             }
@@ -52,33 +195,20 @@ impl EventWriter {
 
         if ret < 0 {
             unsafe { glue::libevdev_free(evdev) };
+            if -ret == libc::EACCES || -ret == libc::EPERM {
+                return Err(Error::new(ErrorKind::PermissionDenied,
+                                      "No access to /dev/uinput, run as root or grant the user access with a udev rule"));
+            }
             return Err(Error::new(Error::from_raw_os_error(-ret).kind(),
                                   format!("Failed to create from device ({})", ret)));
         }
 
         let uinput = unsafe { uinput.assume_init() };
-
-        // ok now maybe drop
-        if drop_privileges {
-            privileges::drop_privileges();
-        }
+        devices::created(unsafe { glue::libevdev_uinput_get_fd(uinput) });
         Ok(Self { evdev, uinput })
     }
 
-    pub async fn write(&mut self, event: Event) -> Result<(), Error> {
-        self.write_raw(event.to_raw())
-    }
-
-    pub fn notify(&mut self, message: String) {
-        if let Err(e) = Notification::new()
-            .summary("RKVM")
-            .body(message.as_str())
-            .show() {
-            debug!("Failed to notify {}", e);
-        }
-    }
-
-    pub(crate) fn write_raw(&mut self, event: input_event) -> Result<(), Error> {
+    fn write_raw(&self, event: input_event) -> Result<(), Error> {
         // As far as tokio is concerned, the FD never becomes ready for writing, so just write it normally.
         // If an error happens, it will be propagated to caller and the FD is opened in nonblocking mode anyway,
         // so it shouldn't be an issue.
@@ -87,7 +217,9 @@ impl EventWriter {
             (glue::EV_SYN as _, glue::SYN_REPORT as _, 0), // Include EV_SYN.
         ];
 
-        for (r#type, code, value) in events.iter().cloned() {
+        // A scancode goes out in the same report as the key following it.
+        let count = if event.type_ as u32 == glue::EV_MSC { 1 } else { events.len() };
+        for (r#type, code, value) in events.iter().take(count).cloned() {
             let ret = unsafe {
                 glue::libevdev_uinput_write_event(
                     self.uinput as *const _,
@@ -108,6 +240,15 @@ impl EventWriter {
 
 impl Drop for EventWriter {
     fn drop(&mut self) {
+        if let Some((_, evdev)) = self.leds.take() {
+            unsafe { glue::libevdev_free(evdev) };
+        }
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        devices::forget(unsafe { glue::libevdev_uinput_get_fd(self.uinput) });
         unsafe {
             glue::libevdev_uinput_destroy(self.uinput);
             glue::libevdev_free(self.evdev);
@@ -115,34 +256,216 @@ impl Drop for EventWriter {
     }
 }
 
+// None if the device node can't be found or opened, the lock state is unknown then.
+unsafe fn open_leds(uinput: *mut libevdev_uinput) -> Option<(File, *mut libevdev)> {
+    let path = glue::libevdev_uinput_get_devnode(uinput);
+    if path.is_null() {
+        debug!("No device node for the virtual device");
+        return None;
+    }
+
+    let path = CStr::from_ptr(path).to_string_lossy().into_owned();
+    let file = match OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            debug!("Failed to open {}.  {}", path, e);
+            return None;
+        }
+    };
+
+    let mut evdev = MaybeUninit::uninit();
+    let ret = glue::libevdev_new_from_fd(file.as_raw_fd(), evdev.as_mut_ptr());
+    if ret < 0 {
+        debug!("Failed to open {}.  {}", path, Error::from_raw_os_error(-ret));
+        return None;
+    }
+
+    Some((file, evdev.assume_init()))
+}
+
 unsafe impl Send for EventWriter {}
 
-const TYPES: &[(u32, &[RangeInclusive<u32>])] = &[
+// The codes of EV_KEY are split between the devices, buttons go to the pointer and everything else to the keyboard.
+const KEYBOARD_TYPES: &[(u32, &[RangeInclusive<u32>])] = &[
+    (glue::EV_SYN, &[glue::SYN_REPORT..=glue::SYN_REPORT]),
+    (glue::EV_KEY, &[0..=/*glue::KEY_MAX*/565]),
+    // Lets the desktop set our LEDs, which is how the lock state is read back.
+    (glue::EV_LED, &[glue::LED_NUML..=glue::LED_CAPSL]),
+    (glue::EV_MSC, &[glue::MSC_SCAN..=glue::MSC_SCAN]),
+];
+
+const SWITCH_TYPES: &[(u32, &[RangeInclusive<u32>])] = &[
+    (glue::EV_SYN, &[glue::SYN_REPORT..=glue::SYN_REPORT]),
+    (glue::EV_SW, &[0..=glue::SW_MAX]),
+];
+
+const POINTER_TYPES: &[(u32, &[RangeInclusive<u32>])] = &[
     (glue::EV_SYN, &[glue::SYN_REPORT..=glue::SYN_REPORT]),
     (glue::EV_REL, &[0..=glue::REL_MAX]),
     (glue::EV_KEY, &[0..=/*glue::KEY_MAX*/565]),
 ];
 
-unsafe fn setup_evdev(evdev: *mut libevdev) -> Result<(), Error> {
-    glue::libevdev_set_name(evdev, b"rkvm\0".as_ptr() as *const _);
+// Written by the client itself whatever the server's devices can do, to sync the lock state and for Ctrl+Alt+Del.
+const OWN_KEYS: &[Key] = &[Key::CapsLock, Key::NumLock, Key::LeftAlt, Key::SysRq, Key::K];
+
+// The key repeat delay and period in milliseconds, in the order of EV_REP's codes.
+type Repeat = [c_int; 2];
+
+// Kernel defaults, for when there's no keyboard to take them from.
+const DEFAULT_REPEAT: Repeat = [250, 33];
+
+const EVENT_PATH: &str = "/dev/input";
+
+nix::ioctl_read!(eviocgrep, b'E', 0x03, Repeat);
+
+// The repeat settings of this machine's own keyboard, so that held keys repeat the way they do locally. Needs access
+// to the input devices, which is only there before privileges are dropped.
+fn local_repeat() -> Repeat {
+    let entries = match std::fs::read_dir(EVENT_PATH) {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("Failed to list {}, using the default key repeat.  {}", EVENT_PATH, e);
+            return DEFAULT_REPEAT;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !entry.file_name().to_string_lossy().starts_with("event") {
+            continue;
+        }
+
+        let file = match OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(&path) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+        if !unsafe { is_keyboard(file.as_raw_fd()) } {
+            continue;
+        }
+
+        let mut repeat = DEFAULT_REPEAT;
+        if unsafe { eviocgrep(file.as_raw_fd(), &mut repeat) }.is_ok() {
+            debug!("Using the key repeat of {}: delay {} ms, period {} ms", path.display(), repeat[0], repeat[1]);
+            return repeat;
+        }
+    }
+
+    debug!("No keyboard to take the key repeat from, using the default");
+    DEFAULT_REPEAT
+}
+
+// A real keyboard, as opposed to one of our own devices or a pointer with a few keys.
+unsafe fn is_keyboard(fd: c_int) -> bool {
+    let mut evdev = MaybeUninit::uninit();
+    if glue::libevdev_new_from_fd(fd, evdev.as_mut_ptr()) < 0 {
+        return false;
+    }
+
+    let evdev = evdev.assume_init();
+    let own = glue::libevdev_get_id_vendor(evdev) == device_id::VENDOR as _
+        && glue::libevdev_get_id_product(evdev) == device_id::PRODUCT as _
+        && glue::libevdev_get_id_version(evdev) == device_id::VERSION as _;
+    let keyboard = glue::libevdev_has_event_type(evdev, glue::EV_REP) != 0
+        && glue::libevdev_has_event_code(evdev, glue::EV_KEY, glue::KEY_A) != 0;
+    glue::libevdev_free(evdev);
+
+    !own && keyboard
+}
+
+unsafe fn setup_evdev(
+    evdev: *mut libevdev,
+    kind: Kind,
+    capabilities: Option<&Capabilities>,
+    repeat: Repeat,
+) -> Result<(), Error> {
+    let (name, types): (&[u8], _) = match kind {
+        Kind::Keyboard => (b"rkvm keyboard\0", KEYBOARD_TYPES),
+        Kind::Pointer => (b"rkvm pointer\0", POINTER_TYPES),
+        Kind::Switches => (b"rkvm switches\0", SWITCH_TYPES),
+    };
+    // Kept apart by name, the IDs are what our own devices are recognized by when grabbing.
+    glue::libevdev_set_name(evdev, name.as_ptr() as *const _);
     glue::libevdev_set_id_vendor(evdev, device_id::VENDOR as _);
     glue::libevdev_set_id_product(evdev, device_id::PRODUCT as _);
     glue::libevdev_set_id_version(evdev, device_id::VERSION as _);
     glue::libevdev_set_id_bustype(evdev, glue::BUS_USB as _);
 
-    for (r#type, codes) in TYPES.iter().copied() {
-        let ret = glue::libevdev_enable_event_type(evdev, r#type);
+    // Mirroring replaces the keys and axes of the table with the other end's.
+    let mirrored = |r#type| capabilities.is_some() && (r#type == glue::EV_KEY || r#type == glue::EV_REL);
+    for (r#type, codes) in types.iter().copied() {
+        let codes = if mirrored(r#type) { &[] } else { codes };
+        let codes = codes.iter().cloned().flatten().filter(|code| r#type != glue::EV_KEY || belongs(kind, *code as _));
+        enable(evdev, r#type, codes)?;
+    }
+
+    match (kind, capabilities) {
+        (_, None) | (Kind::Switches, _) => {}
+        (Kind::Keyboard, Some(capabilities)) => {
+            let keys = capabilities
+                .keys
+                .iter()
+                .map(KeyKind::to_raw)
+                .chain(OWN_KEYS.iter().map(|key| KeyKind::Key(*key).to_raw()))
+                .filter(|code| belongs(kind, *code));
+            enable(evdev, glue::EV_KEY, keys.map(u32::from))?;
+        }
+        (Kind::Pointer, Some(capabilities)) => {
+            let buttons = capabilities.keys.iter().map(KeyKind::to_raw).filter(|code| belongs(kind, *code));
+            enable(evdev, glue::EV_KEY, buttons.map(u32::from))?;
+
+            // Pointer motion is always possible, it's how the focus moves between machines at the edges.
+            let mut axes = vec![glue::REL_X, glue::REL_Y];
+            if capabilities.scroll {
+                axes.push(glue::REL_WHEEL);
+            }
+            enable(evdev, glue::EV_REL, axes)?;
+        }
+    }
+
+    // Key repeat only makes sense for the keyboard.
+    if let Kind::Pointer | Kind::Switches = kind {
+        return Ok(());
+    }
+
+    // Have the kernel generate key repeats locally with this machine's settings, repeats are not sent over the network.
+    let ret = glue::libevdev_enable_event_type(evdev, glue::EV_REP);
+    if ret < 0 {
+        return Err(Error::new(Error::from_raw_os_error(-ret).kind(),
+                              format!("Failed to enable key repeat ({})", ret)));
+    }
+
+    for (code, value) in [glue::REP_DELAY, glue::REP_PERIOD].iter().zip(repeat.iter()) {
+        let ret = glue::libevdev_enable_event_code(evdev, glue::EV_REP, *code, value as *const c_int as *const _);
         if ret < 0 {
             return Err(Error::new(Error::from_raw_os_error(-ret).kind(),
-                                  format!("Failed to enable event type {} ({})", r#type, ret)));
+                                  format!("Failed to enable key repeat code {} ({})", code, ret)));
         }
+    }
 
-        for code in codes.iter().cloned().flatten() {
-            let ret = glue::libevdev_enable_event_code(evdev, r#type, code, std::ptr::null_mut());
-            if ret < 0 {
-                return Err(Error::new(Error::from_raw_os_error(-ret).kind(),
-                                      format!("Failed to enable event type {} code {} ({})", r#type, code, ret)));
-            }
+    Ok(())
+}
+
+fn belongs(kind: Kind, code: u16) -> bool {
+    let button = matches!(KeyKind::from_raw(code), Some(KeyKind::Button(_)));
+    match kind {
+        Kind::Keyboard => !button,
+        Kind::Pointer => button,
+        Kind::Switches => false,
+    }
+}
+
+unsafe fn enable(evdev: *mut libevdev, r#type: u32, codes: impl IntoIterator<Item = u32>) -> Result<(), Error> {
+    let ret = glue::libevdev_enable_event_type(evdev, r#type);
+    if ret < 0 {
+        return Err(Error::new(Error::from_raw_os_error(-ret).kind(),
+                              format!("Failed to enable event type {} ({})", r#type, ret)));
+    }
+
+    for code in codes {
+        let ret = glue::libevdev_enable_event_code(evdev, r#type, code, std::ptr::null_mut());
+        if ret < 0 {
+            return Err(Error::new(Error::from_raw_os_error(-ret).kind(),
+                                  format!("Failed to enable event type {} code {} ({})", r#type, code, ret)));
         }
     }
 
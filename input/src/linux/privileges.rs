@@ -1,7 +1,7 @@
 use std::env;
 
 use libc::{gid_t, uid_t};
-use log::{info, warn};
+use log::{debug, info, warn};
 use nix::libc;
 use nix::unistd::{Gid, Group, Uid, User};
 
@@ -62,22 +62,52 @@ fn get_sudo_user() -> Option<User> {
                     get_user(uid)
                 }
                 Err(e) => {
-                    warn!("Failed to read polkit uid.  {}", e);
-                    None
+                    info!("Failed to read polkit uid.  {}.  Trying doas...", e);
+                    get_doas_user()
                 }
             }
         }
     }
 }
 
+// doas, which is common on the BSDs, only passes the name of the invoking user.
+fn get_doas_user() -> Option<User> {
+    match env::var("DOAS_USER") {
+        Ok(name) => {
+            match User::from_name(&name) {
+                Ok(user) => {
+                    return user;
+                }
+                Err(e) => {
+                    warn!("Failed to find user {}.  {}", name, e);
+                }
+            }
+        }
+        Err(e) => {
+            warn!("Failed to read doas user.  {}", e);
+        }
+    }
+    None
+}
+
 pub(crate) fn drop_privileges() {
-    if let Some(group) = get_sudo_group() {
+    // Started as a regular user which was given access to the devices it needs, e.g. through udev rules.
+    if !Uid::effective().is_root() {
+        debug!("Not running as root, keeping user {}", Uid::effective());
+        return;
+    }
+
+    let user = get_sudo_user();
+    // Without sudo, fall back to the primary group of the user we're dropping to.
+    let group = get_sudo_group()
+        .or_else(|| user.as_ref().and_then(|user| Group::from_gid(user.gid).ok().flatten()));
+    if let Some(group) = group {
         info!("Dropping to group {:?}", group.name);
         if let Err(e) = nix::unistd::setgid(group.gid) {
             warn!("Failed to set gid {}", e);
         }
     }
-    if let Some(user) = get_sudo_user() {
+    if let Some(user) = user {
         info!("Dropping to user {:?}", user.name);
         if let Err(e) = nix::unistd::setuid(user.uid) {
             warn!("Failed to set uid {}", e);
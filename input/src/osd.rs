@@ -0,0 +1,45 @@
+use log::warn;
+use std::process::{Child, Command};
+
+// Runs the user's osd-command while this machine has focus, rkvm draws nothing itself. The command (e.g. osd_cat on
+// X11) is started with the machine's name as its last argument and killed once focus moves away.
+pub struct OsdCommand {
+    command: Vec<String>,
+    child: Option<Child>,
+}
+
+impl OsdCommand {
+    pub fn new(command: Vec<String>) -> Self {
+        Self {
+            command,
+            child: None,
+        }
+    }
+
+    pub fn show(&mut self, name: &str) {
+        self.hide();
+
+        let (program, args) = match self.command.split_first() {
+            Some(split) => split,
+            None => return,
+        };
+
+        match Command::new(program).args(args).arg(name).spawn() {
+            Ok(child) => self.child = Some(child),
+            Err(e) => warn!("Failed to start the OSD command {}.  {}", program, e),
+        }
+    }
+
+    pub fn hide(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Drop for OsdCommand {
+    fn drop(&mut self) {
+        self.hide();
+    }
+}
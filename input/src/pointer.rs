@@ -0,0 +1,46 @@
+use crate::event::{Axis, Event};
+use serde::Deserialize;
+
+// Far enough to reach any screen edge, the pointer stops there.
+const PARK_DISTANCE: i32 = 1 << 16;
+
+// Where the pointer is moved on the machine losing focus, so a stale pointer doesn't suggest it's still active.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    // Relative motion which gets the pointer into the corner from anywhere on the screen.
+    pub fn events(self) -> [Event; 2] {
+        let (x, y) = match self {
+            Corner::TopLeft => (-1, -1),
+            Corner::TopRight => (1, -1),
+            Corner::BottomLeft => (-1, 1),
+            Corner::BottomRight => (1, 1),
+        };
+
+        [
+            Event::MouseMove {
+                axis: Axis::X,
+                delta: x * PARK_DISTANCE,
+            },
+            Event::MouseMove {
+                axis: Axis::Y,
+                delta: y * PARK_DISTANCE,
+            },
+        ]
+    }
+}
+
+// Motion which leaves the pointer where it was but still counts as user activity, keeping the screen from blanking.
+pub fn nudge() -> [Event; 2] {
+    [
+        Event::MouseMove { axis: Axis::X, delta: 1 },
+        Event::MouseMove { axis: Axis::X, delta: -1 },
+    ]
+}
@@ -0,0 +1,90 @@
+use log::warn;
+
+use crate::event::Event;
+
+// Locks the desktop of this machine, so that it can be left alone without logging out.
+// rkvm usually runs as root outside of any session, so every session is locked through logind.
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+pub fn lock() {
+    use std::process::Command;
+    use std::thread;
+
+    // Waited for on its own thread, the event loop shouldn't wait for logind.
+    thread::spawn(|| match Command::new("loginctl").arg("lock-sessions").status() {
+        Ok(status) if !status.success() => warn!("loginctl lock-sessions exited with {}", status),
+        Ok(_) => {}
+        Err(e) => warn!("Failed to run loginctl: {}", e),
+    });
+}
+
+// Suspends the machine to RAM through logind, in the background like lock.
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+pub fn suspend() {
+    use std::process::Command;
+    use std::thread;
+
+    thread::spawn(|| match Command::new("systemctl").arg("suspend").status() {
+        Ok(status) if !status.success() => warn!("systemctl suspend exited with {}", status),
+        Ok(_) => {}
+        Err(e) => warn!("Failed to run systemctl: {}", e),
+    });
+}
+
+#[cfg(target_os = "windows")]
+pub fn suspend() {
+    use std::io::Error;
+    use winapi::um::powrprof;
+
+    // Sleep rather than hibernate, with wake events left enabled.
+    if unsafe { powrprof::SetSuspendState(0, 0, 0) } == 0 {
+        warn!("Failed to suspend: {}", Error::last_os_error());
+    }
+}
+
+// Only works from the interactive session, not from a service.
+#[cfg(target_os = "windows")]
+pub fn lock() {
+    use std::io::Error;
+    use winapi::um::winuser;
+
+    if unsafe { winuser::LockWorkStation() } == 0 {
+        warn!("Failed to lock the workstation: {}", Error::last_os_error());
+    }
+}
+
+// The secure attention key, which the kernel answers by killing everything on the current virtual console so that the
+// login prompt coming up next can be trusted. The key is typed on the virtual device as Alt+SysRq+K, which only works
+// if kernel.sysrq allows it.
+#[cfg(target_os = "linux")]
+pub fn secure_attention() -> Vec<Event> {
+    use crate::event::{Direction, Key, KeyKind};
+
+    let keys = [Key::LeftAlt, Key::SysRq, Key::K];
+    let event = |direction, key| Event::Key {
+        direction,
+        kind: KeyKind::Key(key),
+    };
+    let down = keys.iter().map(|key| event(Direction::Down, *key));
+    let up = keys.iter().rev().map(|key| event(Direction::Up, *key));
+    down.chain(up).collect()
+}
+
+#[cfg(target_os = "freebsd")]
+pub fn secure_attention() -> Vec<Event> {
+    warn!("There is no secure attention key on FreeBSD");
+    Vec::new()
+}
+
+// Ctrl+Alt+Del can't be typed through SendInput, Windows generates it for services only. Works when the client runs
+// as a service, or with the SoftwareSASGeneration policy allowing applications to.
+#[cfg(target_os = "windows")]
+pub fn secure_attention() -> Vec<Event> {
+    #[link(name = "sas")]
+    extern "system" {
+        fn SendSAS(as_user: i32);
+    }
+
+    // Called from a service, as_user is false.
+    unsafe { SendSAS(0) };
+    Vec::new()
+}
@@ -1,7 +1,9 @@
+mod backend;
 mod event;
 mod event_manager;
 mod event_writer;
 mod oot;
 
+pub use backend::Windows;
 pub use event_manager::EventManager;
 pub use event_writer::EventWriter;
@@ -0,0 +1,68 @@
+use crate::backend::{Backend, Manager, Writer};
+use crate::event::{Event, LockState};
+use crate::windows::{EventManager, EventWriter};
+use async_trait::async_trait;
+use std::io::Error;
+use winapi::um::winuser;
+
+// Writes through SendInput, reading is not implemented yet.
+pub struct Windows;
+
+#[async_trait]
+impl Backend for Windows {
+    type Manager = EventManager;
+    type Writer = EventWriter;
+
+    async fn manager() -> Result<Self::Manager, Error> {
+        EventManager::new().await
+    }
+
+    async fn writer() -> Result<Self::Writer, Error> {
+        EventWriter::new().await
+    }
+}
+
+#[async_trait]
+impl Manager for EventManager {
+    async fn read(&mut self) -> Result<Event, Error> {
+        EventManager::read(self).await
+    }
+
+    async fn write(&mut self, event: Event) -> Result<(), Error> {
+        EventManager::write(self, event).await
+    }
+
+    fn notify(&mut self, _message: String) {}
+
+    fn lock_session(&mut self) {
+        crate::session::lock();
+    }
+
+    fn set_indicator_led(&mut self, _on: bool) {}
+}
+
+#[async_trait]
+impl Writer for EventWriter {
+    async fn write(&mut self, event: Event) -> Result<(), Error> {
+        EventWriter::write(self, event).await
+    }
+
+    fn notify(&mut self, message: String) {
+        let _ = EventWriter::notify(self, message);
+    }
+
+    // The low bit of the key state is the toggle state.
+    fn lock_state(&mut self) -> Option<LockState> {
+        let toggled = |key| unsafe { winuser::GetKeyState(key) } & 1 != 0;
+        Some(LockState {
+            caps_lock: toggled(winuser::VK_CAPITAL),
+            num_lock: toggled(winuser::VK_NUMLOCK),
+        })
+    }
+
+    // Nothing is mirrored here, a new writer is the same as the old one.
+    async fn recreate(&mut self) -> Result<(), Error> {
+        *self = EventWriter::new().await?;
+        Ok(())
+    }
+}
@@ -9,6 +9,10 @@ const DELTA_FACTOR: i32 = 100;
 impl Event {
     pub(crate) fn to_raw(&self) -> Option<Oot<INPUT>> {
         let inputs = match *self {
+            // SendInput takes the scancode along with the key, if at all.
+            Event::Scan { .. } => return None,
+            // Windows has no way of faking a lid or tablet mode.
+            Event::Switch { .. } => return None,
             Event::MouseScroll { delta } => {
                 let delta = delta * DELTA_FACTOR;
                 unsafe {
@@ -62,6 +66,22 @@ impl Event {
                 Oot::V1([(winuser::INPUT_MOUSE, u)])
             },
             Event::Key { direction, kind } => match kind {
+                KeyKind::Key(key) if key.to_virtual().is_some() => unsafe {
+                    let mut u: INPUT_u = std::mem::zeroed();
+                    *u.ki_mut() = KEYBDINPUT {
+                        wVk: key.to_virtual().unwrap(),
+                        wScan: 0,
+                        dwFlags: winuser::KEYEVENTF_EXTENDEDKEY
+                            | match direction {
+                                Direction::Up => winuser::KEYEVENTF_KEYUP,
+                                Direction::Down => 0,
+                            },
+                        time: 0,
+                        dwExtraInfo: 0,
+                    };
+
+                    Oot::V1([(winuser::INPUT_KEYBOARD, u)])
+                },
                 KeyKind::Key(key) => key.to_raw()?.map(|(code, extended)| unsafe {
                     let mut u: INPUT_u = std::mem::zeroed();
                     *u.ki_mut() = KEYBDINPUT {
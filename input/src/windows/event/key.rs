@@ -1,7 +1,38 @@
 use crate::event::Key;
 use crate::windows::oot::Oot;
+use winapi::um::winuser;
 
 impl Key {
+    // Media and browser keys have no usable scan codes, Windows only acts on them when sent as virtual keys.
+    pub(crate) fn to_virtual(&self) -> Option<u16> {
+        use Key::*;
+
+        let vk = match *self {
+            Mute => winuser::VK_VOLUME_MUTE,
+            VolumeDown => winuser::VK_VOLUME_DOWN,
+            VolumeUp => winuser::VK_VOLUME_UP,
+            NextSong => winuser::VK_MEDIA_NEXT_TRACK,
+            PreviousSong => winuser::VK_MEDIA_PREV_TRACK,
+            StopCd => winuser::VK_MEDIA_STOP,
+            PlayPause | PlayCd | PauseCd | Play => winuser::VK_MEDIA_PLAY_PAUSE,
+            Mail => winuser::VK_LAUNCH_MAIL,
+            Media => winuser::VK_LAUNCH_MEDIA_SELECT,
+            Computer => winuser::VK_LAUNCH_APP1,
+            Calc => winuser::VK_LAUNCH_APP2,
+            Back => winuser::VK_BROWSER_BACK,
+            Forward => winuser::VK_BROWSER_FORWARD,
+            Refresh => winuser::VK_BROWSER_REFRESH,
+            Stop => winuser::VK_BROWSER_STOP,
+            Search => winuser::VK_BROWSER_SEARCH,
+            Bookmarks => winuser::VK_BROWSER_FAVORITES,
+            Homepage => winuser::VK_BROWSER_HOME,
+            Sleep => winuser::VK_SLEEP,
+            _ => return None,
+        };
+
+        Some(vk as u16)
+    }
+
     pub(crate) fn to_raw(&self) -> Option<Oot<(u16, bool)>> {
         use Key::*;
 
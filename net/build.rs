@@ -0,0 +1,12 @@
+use std::env;
+
+fn main() {
+    println!("cargo:rerun-if-changed=proto/rkvm.proto");
+
+    // A bundled protoc is used so that building doesn't require protobuf to be installed.
+    if env::var_os("PROTOC").is_none() {
+        env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+    }
+
+    prost_build::compile_protos(&["proto/rkvm.proto"], &["proto"]).unwrap();
+}
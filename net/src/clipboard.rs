@@ -0,0 +1,95 @@
+use crate::Message;
+use std::io::{Error, ErrorKind};
+
+// Clipboard content is sent in pieces of at most this size, so that input events can be sent in between.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+// Splits clipboard content into ClipboardChunk messages followed by a ClipboardData with the rest.
+pub fn split(mime_type: String, mut data: Vec<u8>) -> Vec<Message> {
+    let total = data.len() as u64;
+    let last = data.len().saturating_sub(1) / CHUNK_SIZE * CHUNK_SIZE;
+    let rest = data.split_off(last);
+
+    data.chunks(CHUNK_SIZE)
+        .map(|chunk| Message::ClipboardChunk(total, chunk.to_vec()))
+        .chain(std::iter::once(Message::ClipboardData(mime_type, rest)))
+        .collect()
+}
+
+// A size in human readable units, for progress notifications.
+pub fn describe(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1048575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1048576.0),
+    }
+}
+
+// Collects ClipboardChunk messages until the ClipboardData completing them arrives.
+#[derive(Default)]
+pub struct Reassembler {
+    data: Vec<u8>,
+    total: u64,
+    // Set once a transfer is cancelled while the sender keeps going, until its ClipboardData arrives.
+    discarding: bool,
+}
+
+impl Reassembler {
+    // Returns the number of bytes received so far. The content is bounded by max_size as a whole,
+    // just like a single message would be.
+    pub fn push(&mut self, total: u64, chunk: Vec<u8>, max_size: u32) -> Result<u64, Error> {
+        if self.discarding {
+            return Ok(0);
+        }
+
+        if total > u64::from(max_size) || self.data.len() + chunk.len() > max_size as usize {
+            self.abort();
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Clipboard content exceeds the maximum of {} bytes", max_size),
+            ));
+        }
+
+        self.total = total;
+        self.data.extend_from_slice(&chunk);
+        Ok(self.data.len() as u64)
+    }
+
+    // Returns None if the transfer was discarded.
+    pub fn finish(&mut self, rest: Vec<u8>) -> Option<Vec<u8>> {
+        if std::mem::take(&mut self.discarding) {
+            return None;
+        }
+
+        let mut data = std::mem::take(&mut self.data);
+        data.extend_from_slice(&rest);
+        self.total = 0;
+        Some(data)
+    }
+
+    // Whether chunks have been received without the ClipboardData completing them.
+    pub fn in_progress(&self) -> bool {
+        !self.data.is_empty()
+    }
+
+    // The total size of the transfer in progress.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    // Drops what has been received so far, for when the sender has stopped. Returns whether anything was in progress.
+    pub fn abort(&mut self) -> bool {
+        let in_progress = self.in_progress();
+        self.data = Vec::new();
+        self.total = 0;
+        in_progress
+    }
+
+    // Drops what has been received so far and whatever still arrives of the same transfer, for when the sender
+    // keeps going. Returns whether anything was in progress.
+    pub fn discard(&mut self) -> bool {
+        let in_progress = self.abort();
+        self.discarding = in_progress;
+        in_progress
+    }
+}
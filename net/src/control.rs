@@ -0,0 +1,149 @@
+use crate::{read_data, write_data, Handshake, SystemInfo, DEFAULT_MAX_MESSAGE_SIZE};
+use input::Key;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::Error;
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+// Messages exchanged over the server's local control socket, used by rkvm-ctl.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Request {
+    // Move focus to the machine with the given name
+    Switch(String),
+    // List connected clients
+    List,
+    // Keep the connection open and receive notifications as things happen
+    Subscribe,
+    // Change the log level (off, error, warn, info, debug or trace)
+    SetLogLevel(String),
+    // Rebind a hotkey, optionally writing the new keys back to the config file
+    SetHotkey {
+        hotkey: Hotkey,
+        keys: HashSet<Key>,
+        persist: bool,
+    },
+    // Restrict cycling to the clients in the named group, None for all clients
+    SetProfile(Option<String>),
+    // Ask a client to run one of the commands it allows
+    RunCommand { client: String, command: String },
+    // Send a Wake-on-LAN packet to a client
+    Wake(String),
+    // Move focus back to where it was before the last switch
+    UndoSwitch,
+    // List the last focus changes, oldest first
+    History,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Hotkey {
+    Switch,
+    Kill,
+    Pause,
+    Roster,
+    CancelTransfer,
+    Clipboard,
+    Picker,
+    Profile,
+    Override,
+    Lock,
+    Suspend,
+    Wake,
+    SecureAttention,
+    UndoSwitch,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Response {
+    Ok,
+    Error(String),
+    Clients(Vec<ClientStatus>),
+    Notification(Notification),
+    History(Vec<SwitchRecord>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Notification {
+    Connected {
+        id: u64,
+        name: String,
+        address: SocketAddr,
+    },
+    Disconnected {
+        id: u64,
+        name: String,
+    },
+    // The id is None when the server itself got focus.
+    Focused {
+        id: Option<u64>,
+        name: String,
+    },
+    // Clipboard content moving to (outgoing) or from a client, sent for every chunk.
+    Transfer {
+        id: u64,
+        name: String,
+        outgoing: bool,
+        done: u64,
+        total: u64,
+    },
+    TransferCancelled {
+        id: u64,
+        name: String,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClientStatus {
+    pub id: u64,
+    pub name: String,
+    pub address: SocketAddr,
+    // Seconds since the Unix epoch.
+    pub connected_since: u64,
+    pub focused: bool,
+    pub rtt_ms: Option<f64>,
+    // Positive if the client's clock is ahead of the server's.
+    pub clock_offset_ms: Option<f64>,
+    // As last reported by the client, with the uptime brought up to date.
+    #[serde(default)]
+    pub system_info: Option<SystemInfo>,
+    // None for clients which didn't send one.
+    #[serde(default)]
+    pub handshake: Option<Handshake>,
+}
+
+// A focus change kept in the server's history, the server itself goes by its hostname.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SwitchRecord {
+    pub from: String,
+    pub to: String,
+    // Seconds since the Unix epoch.
+    pub at: u64,
+}
+
+pub async fn read_request<R>(reader: R) -> Result<Request, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    read_data(reader, DEFAULT_MAX_MESSAGE_SIZE).await
+}
+
+pub async fn write_request<W>(writer: W, request: &Request) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    write_data(writer, request).await
+}
+
+pub async fn read_response<R>(reader: R) -> Result<Response, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    read_data(reader, DEFAULT_MAX_MESSAGE_SIZE).await
+}
+
+pub async fn write_response<W>(writer: W, response: &Response) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    write_data(writer, response).await
+}
@@ -1,4 +1,16 @@
-use input::Event;
+pub mod clipboard;
+pub mod control;
+pub mod motion;
+pub mod sealed;
+pub mod tcp;
+pub mod wol;
+mod proto;
+
+use sealed::Sealed;
+use input::{Capabilities, Event, KeyKind, LockState};
+use serde::de::DeserializeOwned;
+use bincode::Options;
+use prost::Message as _;
 use serde::{Deserialize, Serialize};
 use std::convert::TryInto;
 use std::io::{Error, ErrorKind};
@@ -6,8 +18,16 @@ use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 // Is it bold to assume there won't be more than 65536 protocol versions?
-pub const PROTOCOL_VERSION: u16 = 1;
+// Each side sends the highest version it speaks and both go with the highest one they have in common, see
+// negotiate_version. Releases before negotiation only speak PROTOCOL_VERSION and expect exactly that back.
+pub const PROTOCOL_VERSION: u16 = 2;
+// The oldest version still spoken, peers only offering something older are turned away.
+pub const MIN_PROTOCOL_VERSION: u16 = 2;
 pub const MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+// How often the server pings clients, which also keeps idle connections from timing out.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(2500);
+// Large enough for sizeable clipboard contents, small enough that a bogus length can't exhaust memory.
+pub const DEFAULT_MAX_MESSAGE_SIZE: u32 = 16 * 1024 * 1024;
 
 pub async fn read_version<R>(mut reader: R) -> Result<u16, Error>
 where
@@ -26,7 +46,118 @@ where
     writer.write_all(&version.to_le_bytes()).await
 }
 
-pub async fn read_message<R>(mut reader: R) -> Result<Message, Error>
+// The version to talk to a peer offering the given one, none if there is no version both sides speak.
+pub fn negotiate_version(theirs: u16) -> Option<u16> {
+    let version = theirs.min(PROTOCOL_VERSION);
+    (version >= MIN_PROTOCOL_VERSION).then_some(version)
+}
+
+// With a message key, both sides follow the version exchange with the two offers as they saw them, the client's
+// first, and check the ones they receive against their own. They go in the first authenticated record each way, which
+// can't be forged, replayed from another connection or reflected back without the key. Someone altering an offer on
+// the way to talk both sides into an older version than they have in common then fails the handshake instead of going
+// unnoticed. Without a key there is nothing to authenticate them with, this does nothing then and the offers are only
+// as safe as the connection.
+pub async fn confirm_version<S>(stream: &mut Sealed<S>, client: u16, server: u16) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if !stream.is_authenticated() {
+        return Ok(());
+    }
+
+    let mut transcript = [0; 4];
+    transcript[..2].copy_from_slice(&client.to_le_bytes());
+    transcript[2..].copy_from_slice(&server.to_le_bytes());
+    stream.write_all(&transcript).await?;
+    stream.flush().await?;
+
+    let mut received = [0; 4];
+    stream.read_exact(&mut received).await?;
+    if received != transcript {
+        return Err(Error::new(ErrorKind::InvalidData, "The version exchange was tampered with"));
+    }
+
+    Ok(())
+}
+
+// Frames larger than max_size are rejected before anything is allocated for them.
+pub async fn read_message<R>(
+    mut reader: R,
+    max_size: u32,
+    encoding: Encoding,
+) -> Result<Message, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    loop {
+        let data = read_frame(&mut reader, max_size).await?;
+        let message = match encoding {
+            Encoding::Protobuf => proto::ProtoMessage::decode(data.as_slice())
+                .map(proto::decode)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err))?,
+            Encoding::Compact => Some(
+                compact_options(max_size)
+                    .deserialize(&data)
+                    .map_err(|err| Error::new(ErrorKind::InvalidData, err))?,
+            ),
+        };
+
+        // Protobuf messages unknown to this version are skipped.
+        if let Some(message) = message {
+            return Ok(message);
+        }
+    }
+}
+
+pub async fn write_message<W>(writer: W, message: &Message, encoding: Encoding) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    let data = match encoding {
+        Encoding::Protobuf => proto::encode(message).encode_to_vec(),
+        Encoding::Compact => compact_options(u32::MAX)
+            .serialize(message)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?,
+    };
+
+    write_frame(writer, &data).await
+}
+
+fn compact_options(max_size: u32) -> impl Options {
+    bincode::options()
+        .with_varint_encoding()
+        .with_limit(u64::from(max_size))
+        .reject_trailing_bytes()
+}
+
+async fn read_data<R, T>(reader: R, max_size: u32) -> Result<T, Error>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let data = read_frame(reader, max_size).await?;
+
+    // Same encoding as bincode::deserialize, but a frame must contain exactly one value.
+    bincode::options()
+        .with_fixint_encoding()
+        .with_limit(u64::from(max_size))
+        .reject_trailing_bytes()
+        .deserialize(&data)
+        .map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
+
+async fn write_data<W, T>(writer: W, data: &T) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let data =
+        bincode::serialize(data).map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+    write_frame(writer, &data).await
+}
+
+async fn read_frame<R>(mut reader: R, max_size: u32) -> Result<Vec<u8>, Error>
 where
     R: AsyncRead + Unpin,
 {
@@ -36,28 +167,50 @@ where
         u32::from_le_bytes(bytes)
     };
 
+    if length > max_size {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Message of {} bytes exceeds the maximum of {}", length, max_size),
+        ));
+    }
+
     let mut data = vec![0; length as usize];
     reader.read_exact(&mut data).await?;
 
-    bincode::deserialize(&data).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+    Ok(data)
 }
 
-pub async fn write_message<W>(mut writer: W, message: &Message) -> Result<(), Error>
+async fn write_frame<W>(mut writer: W, data: &[u8]) -> Result<(), Error>
 where
     W: AsyncWrite + Unpin,
 {
-    let data =
-        bincode::serialize(&message).map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
     let length: u32 = data
         .len()
         .try_into()
         .map_err(|_| Error::new(ErrorKind::InvalidInput, "Serialized data is too large"))?;
-    writer.write_all(&length.to_le_bytes()).await?;
-    writer.write_all(&data).await?;
+    // Written at once, so that a sealed stream puts the whole frame in a single record.
+    let mut frame = Vec::with_capacity(4 + data.len());
+    frame.extend_from_slice(&length.to_le_bytes());
+    frame.extend_from_slice(data);
+    writer.write_all(&frame).await?;
 
     Ok(())
 }
 
+// How messages are encoded on the wire.
+//
+// Every connection starts out with Protobuf, which all versions understand. The server offers the encodings
+// it supports in an Encodings message, which older clients simply skip. A client can then switch by sending
+// SetEncoding, which the server acknowledges with the same message. Each side uses the new encoding for
+// everything it sends after its SetEncoding, and for everything it reads after the other side's.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Encoding {
+    // As described in proto/rkvm.proto.
+    Protobuf,
+    // Bincode with variable length integers, which keeps events down to a few bytes.
+    Compact,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Message {
     Event(Event),
@@ -67,8 +220,142 @@ pub enum Message {
     Notify(String),
     // Exchange from the client
     Hello(String),
-    // Request to get the clipboard content from the active client
+    // Asks the client to advertise its clipboard content with a ClipboardOffer
     GetClipboardData,
-    // set clipboard content
-    SetClipboardData(String),
+    // Sent from the server when the client gains (true) or loses (false) focus
+    Focus(bool),
+    // Sent from the server to measure latency, the client echoes the timestamp back in a Pong
+    Ping(u64),
+    // The echoed timestamp and the client's clock in microseconds since the Unix epoch, used to estimate clock offset
+    Pong(u64, u64),
+    // Sent from the server after the handshake, listing the encodings it can switch to
+    Encodings(Vec<Encoding>),
+    // Switches to the given encoding, see Encoding
+    SetEncoding(Encoding),
+    // Sent by the owner of the clipboard content, listing the MIME types it's available in, most preferred first
+    ClipboardOffer(Vec<String>),
+    // Picks one of the offered MIME types, answered with ClipboardData
+    ClipboardRequest(String),
+    // The MIME type and the content, or the rest of it following ClipboardChunk messages
+    ClipboardData(String, Vec<u8>),
+    // The total size and a piece of clipboard content, see clipboard::split
+    ClipboardChunk(u64, Vec<u8>),
+    // Stops the clipboard transfer in progress, whatever has been received of it is dropped
+    CancelTransfer,
+    // Sent from the server to unfocused clients while the user is active elsewhere, keeps them from going idle
+    Activity,
+    // The lock state of the machine losing focus, which the machine gaining it adopts
+    LockState(LockState),
+    // Sent from a client asking for focus (true) or to hand it back to the server (false)
+    RequestFocus(bool),
+    // Sent from the server after the handshake, the key authenticating motion sent over UDP, see motion.rs
+    MotionKey(Vec<u8>),
+    // Sent from a client which wants motion over UDP, the port it receives it on
+    MotionChannel(u16),
+    // Sent from a client after the hello, its desktop scale which the server scales pointer motion by
+    DisplayScale(f64),
+    // Sent from the server, the name of one of the commands the client allows the server to run
+    RunCommand(String),
+    // Sent from the server to have the client lock its screen
+    Lock,
+    // Sent from the server to have the client suspend itself, it can be woken up again with Wake-on-LAN
+    Suspend,
+    // Sent from a client now and then, what it reports about the machine it runs on
+    SystemInfo(SystemInfo),
+    // An event numbered by the connection it's sent over, starting from 1 and going up by one with each event
+    SequencedEvent(u64, Event),
+    // Sent from a client which has noticed events going missing, answered with a KeyStateSync
+    RequestKeyState,
+    // Sent from the server, every key it has pressed on the client, which releases and presses keys to match
+    KeyStateSync(Vec<KeyKind>),
+    // Sent from a client right after the hello, which older servers wait for, everything else about the client
+    Handshake(Handshake),
+    // Sent from either side when shutting down on purpose, as opposed to dropping off the network
+    Goodbye,
+    // Sent from the server to have the client go through its secure attention sequence, e.g. Ctrl+Alt+Del on Windows
+    SecureAttention,
+    // Sent from the server after the handshake, what its grabbed devices can produce, so that the client's virtual
+    // device can be made to match. Sent from a client, what its virtual device can write, the server drops the rest
+    Capabilities(Capabilities),
+    // Sent from the server before an event following motion over UDP, the sequence number of the last datagram sent,
+    // which the client waits for a moment, see motion.rs
+    MotionSent(u64),
+    // Sent from a client receiving motion over UDP now and then, the sequence number of the last datagram received,
+    // see motion.rs
+    MotionAck(u64),
+}
+
+// What a client tells the server about itself when connecting. New fields are optional or empty by default, so
+// that there is a place for future features to be negotiated without another message.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Handshake {
+    // Stays the same across restarts and renames, unlike the name, left out if the client can't tell.
+    pub id: Option<String>,
+    // The operating system, as in std::env::consts::OS.
+    pub platform: String,
+    // The desktop scale, which the server scales pointer motion by. Replaces DisplayScale.
+    pub display_scale: Option<f64>,
+    // Optional parts of the protocol the client supports, see FEATURES. Flags unknown to the server are ignored.
+    pub features: Vec<String>,
+}
+
+// Answers KeyStateSync.
+pub const FEATURE_KEY_STATE_SYNC: &str = "key-state-sync";
+// Checks the numbers of SequencedEvent.
+pub const FEATURE_SEQUENCED_EVENTS: &str = "sequenced-events";
+// Everything this version of the client supports.
+pub const FEATURES: &[&str] = &[FEATURE_KEY_STATE_SYNC, FEATURE_SEQUENCED_EVENTS];
+
+// Shown in rkvm-ctl list, so that e.g. a laptop running out of battery is noticed before it drops off.
+// Anything a client can't tell is left out.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SystemInfo {
+    pub os: String,
+    pub uptime_secs: Option<u64>,
+    pub battery_percent: Option<u8>,
+    pub charging: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sealed::Side;
+    use tokio::io::{self, DuplexStream};
+
+    async fn sealed(key: Option<&[u8]>) -> (Sealed<DuplexStream>, Sealed<DuplexStream>) {
+        let (client, server) = io::duplex(1024);
+        let (client, server) = tokio::join!(
+            sealed::seal(client, key, Side::Client),
+            sealed::seal(server, key, Side::Server)
+        );
+
+        (client.unwrap(), server.unwrap())
+    }
+
+    #[tokio::test]
+    async fn matching_offers_are_confirmed() {
+        let (mut client, mut server) = sealed(Some(b"key")).await;
+        let (client, server) = tokio::join!(confirm_version(&mut client, 3, 2), confirm_version(&mut server, 3, 2));
+
+        client.unwrap();
+        server.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tampered_offers_fail_confirmation() {
+        // The client offered 3, but the server was told 2.
+        let (mut client, mut server) = sealed(Some(b"key")).await;
+        let (client, server) = tokio::join!(confirm_version(&mut client, 3, 2), confirm_version(&mut server, 2, 2));
+
+        assert_eq!(client.unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(server.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn offers_are_not_confirmed_without_a_key() {
+        let (mut client, _server) = sealed(None).await;
+
+        // Nothing is sent or expected, the other side not answering doesn't matter.
+        confirm_version(&mut client, 3, 2).await.unwrap();
+    }
 }
@@ -0,0 +1,206 @@
+use input::{Axis, Event};
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use std::convert::TryInto;
+use std::io::Error;
+use std::time::Duration;
+
+// Relative pointer motion can go over UDP next to the TLS connection, so that a lost packet doesn't hold back
+// everything after it. Each datagram carries a sequence number, the number of the last event sent over the connection
+// before it, the axis and the delta, authenticated with HMAC-SHA256 under a key the server sends over the TLS
+// connection in a MotionKey message.
+// Datagrams arriving late are dropped, the motion they carried no longer matters.
+//
+// The two channels are kept in order on the client: motion arriving ahead of the event it was sent after, e.g. the
+// button press starting a drag, waits for it, and before an event following motion over UDP the server sends a
+// MotionSent, which has the client wait a moment for that motion, so that e.g. a click lands where it should.
+//
+// Datagrams may not get through at all, e.g. because a firewall drops them. The client acknowledges them with a
+// MotionAck at most every ACK_INTERVAL while they arrive, and the server only sends motion over UDP while the last
+// acknowledgement is younger than ACK_TIMEOUT, sending it over the connection otherwise.
+pub const KEY_SIZE: usize = 32;
+pub const ACK_INTERVAL: Duration = Duration::from_secs(1);
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(3);
+
+const TAG_SIZE: usize = 16;
+const PAYLOAD_SIZE: usize = 8 + 8 + 1 + 4;
+pub const PACKET_SIZE: usize = PAYLOAD_SIZE + TAG_SIZE;
+
+pub fn key() -> Result<Vec<u8>, Error> {
+    let mut key = vec![0; KEY_SIZE];
+    openssl::rand::rand_bytes(&mut key)?;
+    Ok(key)
+}
+
+// Motion received over UDP, and the number of the last event sent over the connection before it.
+#[derive(Clone, Copy, Debug)]
+pub struct Motion {
+    pub after: u64,
+    pub event: Event,
+}
+
+// Sends motion with increasing sequence numbers.
+pub struct Sealer {
+    key: Vec<u8>,
+    sequence: u64,
+}
+
+impl Sealer {
+    pub fn new(key: Vec<u8>) -> Self {
+        Self { key, sequence: 0 }
+    }
+
+    // The sequence number of the last datagram sealed, 0 before the first one.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn seal(&mut self, after: u64, axis: Axis, delta: i32) -> Result<Vec<u8>, Error> {
+        self.sequence += 1;
+
+        let mut packet = Vec::with_capacity(PACKET_SIZE);
+        packet.extend_from_slice(&self.sequence.to_be_bytes());
+        packet.extend_from_slice(&after.to_be_bytes());
+        packet.push(match axis {
+            Axis::X => 0,
+            Axis::Y => 1,
+        });
+        packet.extend_from_slice(&delta.to_be_bytes());
+
+        let tag = tag(&self.key, &packet)?;
+        packet.extend_from_slice(&tag);
+        Ok(packet)
+    }
+}
+
+// Accepts motion which is authentic and newer than anything accepted before.
+pub struct Opener {
+    key: Vec<u8>,
+    last: u64,
+}
+
+impl Opener {
+    pub fn new(key: Vec<u8>) -> Self {
+        Self { key, last: 0 }
+    }
+
+    // The sequence number of the last datagram accepted, 0 before the first one.
+    pub fn last(&self) -> u64 {
+        self.last
+    }
+
+    pub fn open(&mut self, packet: &[u8]) -> Option<Motion> {
+        if packet.len() != PACKET_SIZE {
+            return None;
+        }
+
+        let (payload, received) = packet.split_at(PAYLOAD_SIZE);
+        let expected = tag(&self.key, payload).ok()?;
+        if !memcmp::eq(&expected, received) {
+            return None;
+        }
+
+        let sequence = u64::from_be_bytes(payload[..8].try_into().unwrap());
+        if sequence <= self.last {
+            return None;
+        }
+
+        let after = u64::from_be_bytes(payload[8..16].try_into().unwrap());
+        let axis = match payload[16] {
+            0 => Axis::X,
+            1 => Axis::Y,
+            _ => return None,
+        };
+        let delta = i32::from_be_bytes(payload[17..].try_into().unwrap());
+
+        self.last = sequence;
+        Some(Motion {
+            after,
+            event: Event::MouseMove { axis, delta },
+        })
+    }
+}
+
+fn tag(key: &[u8], payload: &[u8]) -> Result<Vec<u8>, Error> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(payload)?;
+
+    let mut tag = signer.sign_to_vec()?;
+    tag.truncate(TAG_SIZE);
+    Ok(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (Sealer, Opener) {
+        let key = key().unwrap();
+        (Sealer::new(key.clone()), Opener::new(key))
+    }
+
+    // What was opened as the event it was sent after and the vertical delta.
+    fn opened(motion: Option<Motion>) -> Option<(u64, i32)> {
+        match motion?.event {
+            Event::MouseMove { axis: Axis::Y, delta } => Some((motion?.after, delta)),
+            event => panic!("Not vertical motion: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn motion_is_opened() {
+        let (mut sealer, mut opener) = pair();
+        assert_eq!(opened(opener.open(&sealer.seal(3, Axis::Y, -7).unwrap())), Some((3, -7)));
+        assert_eq!(opener.last(), sealer.sequence());
+    }
+
+    #[test]
+    fn forged_tags_are_rejected() {
+        let (mut sealer, mut opener) = pair();
+        let mut packet = sealer.seal(0, Axis::Y, 1).unwrap();
+        packet[PACKET_SIZE - 1] ^= 1;
+        assert!(opener.open(&packet).is_none());
+
+        // Nor does a packet sealed under another key get through.
+        let mut other = Sealer::new(key().unwrap());
+        assert!(opener.open(&other.seal(0, Axis::Y, 1).unwrap()).is_none());
+    }
+
+    #[test]
+    fn replayed_and_older_motion_is_rejected() {
+        let (mut sealer, mut opener) = pair();
+        let first = sealer.seal(0, Axis::Y, 1).unwrap();
+        let second = sealer.seal(0, Axis::Y, 2).unwrap();
+
+        assert_eq!(opened(opener.open(&second)), Some((0, 2)));
+        assert!(opener.open(&second).is_none());
+        assert!(opener.open(&first).is_none());
+    }
+
+    #[test]
+    fn packets_of_the_wrong_length_are_rejected() {
+        let (mut sealer, mut opener) = pair();
+        let mut packet = sealer.seal(0, Axis::Y, 1).unwrap();
+        assert!(opener.open(&packet[..PACKET_SIZE - 1]).is_none());
+
+        packet.push(0);
+        assert!(opener.open(&packet).is_none());
+    }
+
+    #[test]
+    fn invalid_axes_are_rejected() {
+        let (_, mut opener) = pair();
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&1u64.to_be_bytes());
+        payload.extend_from_slice(&0u64.to_be_bytes());
+        payload.push(2);
+        payload.extend_from_slice(&1i32.to_be_bytes());
+        let tag = tag(&opener.key, &payload).unwrap();
+        payload.extend_from_slice(&tag);
+
+        assert!(opener.open(&payload).is_none());
+    }
+}
@@ -0,0 +1,278 @@
+// Conversion between the types used throughout rkvm and the ones generated from proto/rkvm.proto.
+use crate::{Encoding, Handshake, Message, SystemInfo};
+use input::{Axis, Button, Capabilities, Direction, Event, Key, KeyKind, LockState};
+use serde::de::value::{Error as ValueError, StrDeserializer};
+use serde::de::IntoDeserializer;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/rkvm.rs"));
+}
+
+pub(crate) use generated::Message as ProtoMessage;
+
+use generated::{event, key, message};
+
+pub(crate) fn encode(message: &Message) -> ProtoMessage {
+    let kind = match message {
+        Message::Event(event) => message::Kind::Event(encode_event(event, 0)),
+        Message::KeepAlive => message::Kind::KeepAlive(generated::Empty {}),
+        Message::Notify(text) => message::Kind::Notify(text.clone()),
+        Message::Hello(name) => message::Kind::Hello(name.clone()),
+        Message::GetClipboardData => message::Kind::GetClipboardData(generated::Empty {}),
+        Message::Focus(focus) => message::Kind::Focus(*focus),
+        Message::Ping(timestamp) => message::Kind::Ping(*timestamp),
+        Message::Pong(sent, client_time) => message::Kind::Pong(generated::Pong {
+            sent: *sent,
+            client_time: *client_time,
+        }),
+        Message::Encodings(encodings) => message::Kind::Encodings(generated::Encodings {
+            encodings: encodings
+                .iter()
+                .map(|encoding| encode_encoding(*encoding) as i32)
+                .collect(),
+        }),
+        Message::SetEncoding(encoding) => {
+            message::Kind::SetEncoding(encode_encoding(*encoding) as i32)
+        }
+        Message::ClipboardOffer(mime_types) => message::Kind::ClipboardOffer(generated::ClipboardOffer {
+            mime_types: mime_types.clone(),
+        }),
+        Message::ClipboardRequest(mime_type) => message::Kind::ClipboardRequest(mime_type.clone()),
+        Message::ClipboardData(mime_type, data) => message::Kind::ClipboardData(generated::ClipboardData {
+            mime_type: mime_type.clone(),
+            data: data.clone(),
+        }),
+        Message::ClipboardChunk(total, data) => message::Kind::ClipboardChunk(generated::ClipboardChunk {
+            total: *total,
+            data: data.clone(),
+        }),
+        Message::CancelTransfer => message::Kind::CancelTransfer(generated::Empty {}),
+        Message::Activity => message::Kind::Activity(generated::Empty {}),
+        Message::LockState(state) => message::Kind::LockState(generated::LockState {
+            caps_lock: state.caps_lock,
+            num_lock: state.num_lock,
+        }),
+        Message::RequestFocus(focus) => message::Kind::RequestFocus(*focus),
+        Message::MotionKey(key) => message::Kind::MotionKey(key.clone()),
+        Message::MotionChannel(port) => message::Kind::MotionChannel(*port as u32),
+        Message::DisplayScale(scale) => message::Kind::DisplayScale(*scale),
+        Message::RunCommand(name) => message::Kind::RunCommand(name.clone()),
+        Message::Lock => message::Kind::Lock(generated::Empty {}),
+        Message::Suspend => message::Kind::Suspend(generated::Empty {}),
+        Message::SystemInfo(info) => message::Kind::SystemInfo(generated::SystemInfo {
+            os: info.os.clone(),
+            uptime_secs: info.uptime_secs,
+            battery_percent: info.battery_percent.map(u32::from),
+            charging: info.charging,
+        }),
+        Message::SequencedEvent(sequence, event) => message::Kind::Event(encode_event(event, *sequence)),
+        Message::RequestKeyState => message::Kind::RequestKeyState(generated::Empty {}),
+        Message::KeyStateSync(keys) => message::Kind::KeyStateSync(generated::KeyStateSync {
+            keys: keys.iter().map(|kind| encode_key(Direction::Down, *kind)).collect(),
+        }),
+        Message::Handshake(handshake) => message::Kind::Handshake(generated::Handshake {
+            id: handshake.id.clone(),
+            platform: handshake.platform.clone(),
+            display_scale: handshake.display_scale,
+            features: handshake.features.clone(),
+        }),
+        Message::Goodbye => message::Kind::Goodbye(generated::Empty {}),
+        Message::SecureAttention => message::Kind::SecureAttention(generated::Empty {}),
+        Message::Capabilities(capabilities) => message::Kind::Capabilities(generated::Capabilities {
+            keys: capabilities.keys.iter().map(|kind| encode_key(Direction::Down, *kind)).collect(),
+            motion: capabilities.motion,
+            scroll: capabilities.scroll,
+        }),
+        Message::MotionSent(sequence) => message::Kind::MotionSent(*sequence),
+        Message::MotionAck(sequence) => message::Kind::MotionAck(*sequence),
+    };
+
+    ProtoMessage { kind: Some(kind) }
+}
+
+// Returns None for messages this version doesn't know about, which are meant to be skipped.
+pub(crate) fn decode(message: ProtoMessage) -> Option<Message> {
+    let message = match message.kind? {
+        message::Kind::Event(event) => match event.sequence {
+            0 => Message::Event(decode_event(event)?),
+            sequence => Message::SequencedEvent(sequence, decode_event(event)?),
+        },
+        message::Kind::KeepAlive(_) => Message::KeepAlive,
+        message::Kind::Notify(text) => Message::Notify(text),
+        message::Kind::Hello(name) => Message::Hello(name),
+        message::Kind::GetClipboardData(_) => Message::GetClipboardData,
+        message::Kind::Focus(focus) => Message::Focus(focus),
+        message::Kind::Ping(timestamp) => Message::Ping(timestamp),
+        message::Kind::Pong(pong) => Message::Pong(pong.sent, pong.client_time),
+        // Encodings unknown to this version are left out, they can't be picked anyway.
+        message::Kind::Encodings(encodings) => Message::Encodings(
+            encodings
+                .encodings
+                .into_iter()
+                .filter_map(decode_encoding)
+                .collect(),
+        ),
+        message::Kind::SetEncoding(encoding) => Message::SetEncoding(decode_encoding(encoding)?),
+        message::Kind::ClipboardOffer(offer) => Message::ClipboardOffer(offer.mime_types),
+        message::Kind::ClipboardRequest(mime_type) => Message::ClipboardRequest(mime_type),
+        message::Kind::ClipboardData(data) => Message::ClipboardData(data.mime_type, data.data),
+        message::Kind::ClipboardChunk(chunk) => Message::ClipboardChunk(chunk.total, chunk.data),
+        message::Kind::CancelTransfer(_) => Message::CancelTransfer,
+        message::Kind::Activity(_) => Message::Activity,
+        message::Kind::LockState(state) => Message::LockState(LockState {
+            caps_lock: state.caps_lock,
+            num_lock: state.num_lock,
+        }),
+        message::Kind::RequestFocus(focus) => Message::RequestFocus(focus),
+        message::Kind::MotionKey(key) => Message::MotionKey(key),
+        message::Kind::MotionChannel(port) => Message::MotionChannel(port.try_into().ok()?),
+        message::Kind::DisplayScale(scale) => Message::DisplayScale(scale),
+        message::Kind::RunCommand(name) => Message::RunCommand(name),
+        message::Kind::Lock(_) => Message::Lock,
+        message::Kind::Suspend(_) => Message::Suspend,
+        message::Kind::SystemInfo(info) => Message::SystemInfo(SystemInfo {
+            os: info.os,
+            uptime_secs: info.uptime_secs,
+            battery_percent: info.battery_percent.and_then(|percent| percent.try_into().ok()),
+            charging: info.charging,
+        }),
+        message::Kind::RequestKeyState(_) => Message::RequestKeyState,
+        message::Kind::KeyStateSync(sync) => Message::KeyStateSync(
+            sync.keys
+                .iter()
+                .filter_map(|key| decode_key(key).map(|(_, kind)| kind))
+                .collect(),
+        ),
+        message::Kind::Handshake(handshake) => Message::Handshake(Handshake {
+            id: handshake.id,
+            platform: handshake.platform,
+            display_scale: handshake.display_scale,
+            features: handshake.features,
+        }),
+        message::Kind::Goodbye(_) => Message::Goodbye,
+        message::Kind::SecureAttention(_) => Message::SecureAttention,
+        message::Kind::Capabilities(capabilities) => Message::Capabilities(Capabilities {
+            keys: capabilities
+                .keys
+                .iter()
+                .filter_map(|key| decode_key(key).map(|(_, kind)| kind))
+                .collect(),
+            motion: capabilities.motion,
+            scroll: capabilities.scroll,
+        }),
+        message::Kind::MotionSent(sequence) => Message::MotionSent(sequence),
+        message::Kind::MotionAck(sequence) => Message::MotionAck(sequence),
+    };
+
+    Some(message)
+}
+
+fn encode_encoding(encoding: Encoding) -> generated::Encoding {
+    match encoding {
+        Encoding::Protobuf => generated::Encoding::Protobuf,
+        Encoding::Compact => generated::Encoding::Compact,
+    }
+}
+
+fn decode_encoding(encoding: i32) -> Option<Encoding> {
+    match generated::Encoding::from_i32(encoding)? {
+        generated::Encoding::Protobuf => Some(Encoding::Protobuf),
+        generated::Encoding::Compact => Some(Encoding::Compact),
+    }
+}
+
+fn encode_event(event: &Event, sequence: u64) -> generated::Event {
+    let kind = match *event {
+        Event::MouseScroll { delta } => event::Kind::MouseScroll(generated::MouseScroll { delta }),
+        Event::MouseMove { axis, delta } => {
+            let axis = match axis {
+                Axis::X => generated::Axis::X,
+                Axis::Y => generated::Axis::Y,
+            };
+
+            event::Kind::MouseMove(generated::MouseMove {
+                axis: axis as i32,
+                delta,
+            })
+        }
+        Event::Key { direction, kind } => event::Kind::Key(encode_key(direction, kind)),
+        Event::Scan { code } => event::Kind::Scan(code),
+        Event::Switch { code, on } => event::Kind::Switch(generated::Switch { code: code.into(), on }),
+    };
+
+    generated::Event {
+        kind: Some(kind),
+        sequence,
+    }
+}
+
+fn decode_event(event: generated::Event) -> Option<Event> {
+    let event = match event.kind? {
+        event::Kind::MouseScroll(scroll) => Event::MouseScroll {
+            delta: scroll.delta,
+        },
+        event::Kind::MouseMove(motion) => Event::MouseMove {
+            axis: match generated::Axis::from_i32(motion.axis)? {
+                generated::Axis::X => Axis::X,
+                generated::Axis::Y => Axis::Y,
+            },
+            delta: motion.delta,
+        },
+        event::Kind::Key(key) => {
+            let (direction, kind) = decode_key(&key)?;
+            Event::Key { direction, kind }
+        }
+        event::Kind::Scan(code) => Event::Scan { code },
+        event::Kind::Switch(switch) => Event::Switch {
+            code: switch.code.try_into().ok()?,
+            on: switch.on,
+        },
+    };
+
+    Some(event)
+}
+
+fn encode_key(direction: Direction, kind: KeyKind) -> generated::Key {
+    let direction = match direction {
+        Direction::Up => generated::Direction::Up,
+        Direction::Down => generated::Direction::Down,
+    };
+    let (kind, usage) = match kind {
+        KeyKind::Key(key) => (key::Kind::Key(name(&key)), key.hid_usage()),
+        KeyKind::Button(button) => (key::Kind::Button(name(&button)), None),
+    };
+
+    generated::Key {
+        direction: direction as i32,
+        kind: Some(kind),
+        usage,
+    }
+}
+
+fn decode_key(key: &generated::Key) -> Option<(Direction, KeyKind)> {
+    let direction = match generated::Direction::from_i32(key.direction)? {
+        generated::Direction::Up => Direction::Up,
+        generated::Direction::Down => Direction::Down,
+    };
+    let kind = match key.kind.as_ref()? {
+        key::Kind::Key(name) => KeyKind::Key(from_name::<Key>(name).or_else(|| Key::from_hid_usage(key.usage?))?),
+        key::Kind::Button(name) => KeyKind::Button(from_name::<Button>(name)?),
+    };
+
+    Some((direction, kind))
+}
+
+// Keys and buttons are identified by their variant names, the same ones used in the config files.
+fn name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => unreachable!("Keys and buttons serialize as plain names"),
+    }
+}
+
+fn from_name<'de, T: Deserialize<'de>>(name: &'de str) -> Option<T> {
+    let deserializer: StrDeserializer<ValueError> = name.into_deserializer();
+    T::deserialize(deserializer).ok()
+}
@@ -0,0 +1,466 @@
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::{PKey, Private};
+use openssl::sign::Signer;
+use std::cmp;
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+// With a key shared by the server and its clients, everything after the version exchange goes in records numbered
+// per direction and authenticated with HMAC-SHA256, on top of whatever protects the connection. Whoever sits between
+// the two ends, e.g. a proxy terminating TLS on the way, can't inject, reorder or replay messages, not even ones
+// captured from an earlier connection: each connection starts with both sides sending a random nonce, and the keys
+// the records are authenticated with are derived from the shared key and both nonces.
+//
+// A record is the length of the data as 4 little endian bytes, the data and the tag. The sequence number isn't sent,
+// both sides count the records, so a record which is replayed, dropped or out of order fails to authenticate.
+const NONCE_SIZE: usize = 16;
+const TAG_SIZE: usize = 16;
+// Larger writes are split into several records.
+const MAX_RECORD_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+// A stream of records, or the stream as it is without a key.
+pub struct Sealed<S> {
+    inner: S,
+    keys: Option<Keys>,
+    // The record being written and how much of it has been, and how much of the caller's data it holds.
+    outgoing: Vec<u8>,
+    written: usize,
+    accepted: usize,
+    // The record being read, and the data of the last one opened which hasn't been read yet.
+    incoming: Vec<u8>,
+    opened: Vec<u8>,
+    read: usize,
+}
+
+struct Keys {
+    outgoing: PKey<Private>,
+    incoming: PKey<Private>,
+    sent: u64,
+    received: u64,
+}
+
+// Sets up the records right after the version exchange, both sides have to be given the same key or none at all.
+pub async fn seal<S>(mut stream: S, key: Option<&[u8]>, side: Side) -> Result<Sealed<S>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let key = match key {
+        Some(key) => key,
+        None => return Ok(Sealed::new(stream, None)),
+    };
+
+    let mut ours = [0; NONCE_SIZE];
+    openssl::rand::rand_bytes(&mut ours)?;
+    stream.write_all(&ours).await?;
+    let mut theirs = [0; NONCE_SIZE];
+    stream.read_exact(&mut theirs).await?;
+
+    let (client, server) = match side {
+        Side::Client => (ours, theirs),
+        Side::Server => (theirs, ours),
+    };
+    let derive = |label: &[u8]| -> Result<Vec<u8>, Error> {
+        let mut input = label.to_vec();
+        input.extend_from_slice(&client);
+        input.extend_from_slice(&server);
+        tag(&PKey::hmac(key)?, &input, usize::MAX)
+    };
+    let (client_key, server_key) = (derive(b"rkvm client")?, derive(b"rkvm server")?);
+    let (outgoing, incoming) = match side {
+        Side::Client => (client_key, server_key),
+        Side::Server => (server_key, client_key),
+    };
+
+    // Without this, a side with a different key would only find out from the first message failing to authenticate.
+    let outgoing = PKey::hmac(&outgoing)?;
+    let incoming = PKey::hmac(&incoming)?;
+    stream.write_all(&tag(&outgoing, b"confirm", TAG_SIZE)?).await?;
+    let mut confirmation = [0; TAG_SIZE];
+    stream.read_exact(&mut confirmation).await?;
+    if !memcmp::eq(&tag(&incoming, b"confirm", TAG_SIZE)?, &confirmation) {
+        return Err(Error::new(ErrorKind::InvalidData, "The message key doesn't match the other side's"));
+    }
+
+    let keys = Keys {
+        outgoing,
+        incoming,
+        sent: 0,
+        received: 0,
+    };
+    Ok(Sealed::new(stream, Some(keys)))
+}
+
+impl<S> Sealed<S> {
+    fn new(inner: S, keys: Option<Keys>) -> Self {
+        Self {
+            inner,
+            keys,
+            outgoing: Vec::new(),
+            written: 0,
+            accepted: 0,
+            incoming: Vec::new(),
+            opened: Vec::new(),
+            read: 0,
+        }
+    }
+
+    // Whether the records are authenticated, i.e. there is a message key.
+    pub fn is_authenticated(&self) -> bool {
+        self.keys.is_some()
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Sealed<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        let keys = match &mut this.keys {
+            Some(keys) => keys,
+            None => return Pin::new(&mut this.inner).poll_read(cx, buf),
+        };
+
+        while this.read == this.opened.len() {
+            let size = match this.incoming.get(..4) {
+                Some(length) => {
+                    let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+                    if length > MAX_RECORD_SIZE {
+                        return Poll::Ready(Err(Error::new(ErrorKind::InvalidData, "Record too large")));
+                    }
+                    4 + length + TAG_SIZE
+                }
+                None => 4,
+            };
+
+            if this.incoming.len() == size {
+                let (data, received) = this.incoming[4..].split_at(size - 4 - TAG_SIZE);
+                keys.received += 1;
+                if !memcmp::eq(&record_tag(&keys.incoming, keys.received, data)?, received) {
+                    return Poll::Ready(Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "A message failed to authenticate, it was tampered with or replayed",
+                    )));
+                }
+
+                this.opened = data.to_vec();
+                this.read = 0;
+                this.incoming.clear();
+                continue;
+            }
+
+            let mut chunk = [0; 4096];
+            let wanted = cmp::min(size - this.incoming.len(), chunk.len());
+            let mut chunk = ReadBuf::new(&mut chunk[..wanted]);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if chunk.filled().is_empty() {
+                // A clean end of the stream between records reads as one, anything else was cut off.
+                if this.incoming.is_empty() {
+                    return Poll::Ready(Ok(()));
+                }
+                return Poll::Ready(Err(ErrorKind::UnexpectedEof.into()));
+            }
+            this.incoming.extend_from_slice(chunk.filled());
+        }
+
+        let size = cmp::min(buf.remaining(), this.opened.len() - this.read);
+        buf.put_slice(&this.opened[this.read..this.read + size]);
+        this.read += size;
+        Poll::Ready(Ok(()))
+    }
+}
+
+// Each write is sealed into a record of its own and written out before it counts as written. Like with TLS, a write
+// which returned pending has to be retried with the same data.
+impl<S: AsyncWrite + Unpin> AsyncWrite for Sealed<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, Error>> {
+        let this = self.get_mut();
+        let keys = match &mut this.keys {
+            Some(keys) => keys,
+            None => return Pin::new(&mut this.inner).poll_write(cx, buf),
+        };
+
+        if this.outgoing.is_empty() {
+            let data = &buf[..cmp::min(buf.len(), MAX_RECORD_SIZE)];
+            keys.sent += 1;
+            this.outgoing.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            this.outgoing.extend_from_slice(data);
+            this.outgoing.extend_from_slice(&record_tag(&keys.outgoing, keys.sent, data)?);
+            this.written = 0;
+            this.accepted = data.len();
+        }
+
+        while this.written < this.outgoing.len() {
+            let size = ready!(Pin::new(&mut this.inner).poll_write(cx, &this.outgoing[this.written..]))?;
+            if size == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+            }
+            this.written += size;
+        }
+
+        this.outgoing.clear();
+        Poll::Ready(Ok(this.accepted))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+fn record_tag(key: &PKey<Private>, sequence: u64, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut input = Vec::with_capacity(8 + data.len());
+    input.extend_from_slice(&sequence.to_le_bytes());
+    input.extend_from_slice(data);
+    tag(key, &input, TAG_SIZE)
+}
+
+fn tag(key: &PKey<Private>, data: &[u8], size: usize) -> Result<Vec<u8>, Error> {
+    let mut signer = Signer::new(MessageDigest::sha256(), key)?;
+    signer.update(data)?;
+
+    let mut tag = signer.sign_to_vec()?;
+    tag.truncate(size);
+    Ok(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Encoding, Message};
+    use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+
+    fn keys(outgoing: &[u8], incoming: &[u8]) -> Option<Keys> {
+        Some(Keys {
+            outgoing: PKey::hmac(outgoing).unwrap(),
+            incoming: PKey::hmac(incoming).unwrap(),
+            sent: 0,
+            received: 0,
+        })
+    }
+
+    // The records written for each of the messages, one message per record.
+    async fn records(messages: &[&[u8]]) -> Vec<Vec<u8>> {
+        let mut writer = Sealed::new(Vec::new(), keys(b"there", b"back"));
+        let mut records = Vec::new();
+        for message in messages {
+            writer.write_all(message).await.unwrap();
+            records.push(writer.inner.split_off(0));
+        }
+
+        records
+    }
+
+    // Reads the messages back from the given bytes, stopping at the first error.
+    async fn open(bytes: &[u8], sizes: &[usize]) -> Result<Vec<Vec<u8>>, Error> {
+        let mut reader = Sealed::new(bytes, keys(b"back", b"there"));
+        let mut messages = Vec::new();
+        for size in sizes {
+            let mut message = vec![0; *size];
+            reader.read_exact(&mut message).await?;
+            messages.push(message);
+        }
+
+        Ok(messages)
+    }
+
+    // Reads and writes a byte at a time, and only every other time it is polled.
+    struct Trickle<S> {
+        inner: S,
+        ready: bool,
+    }
+
+    impl<S> Trickle<S> {
+        // Whether to go ahead this time, otherwise the caller is woken up to try again.
+        fn ready(&mut self, cx: &mut Context<'_>) -> bool {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+            }
+
+            self.ready
+        }
+    }
+
+    impl<S: AsyncRead + Unpin> AsyncRead for Trickle<S> {
+        fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<(), Error>> {
+            let this = self.get_mut();
+            if !this.ready(cx) {
+                return Poll::Pending;
+            }
+
+            let mut byte = [0; 1];
+            let mut one = ReadBuf::new(&mut byte);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut one))?;
+            buf.put_slice(one.filled());
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl<S: AsyncWrite + Unpin> AsyncWrite for Trickle<S> {
+        fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, Error>> {
+            let this = self.get_mut();
+            if !this.ready(cx) {
+                return Poll::Pending;
+            }
+
+            Pin::new(&mut this.inner).poll_write(cx, &buf[..cmp::min(buf.len(), 1)])
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn large_writes_round_trip() {
+        let (client, server) = io::duplex(4096);
+        let (client, server) = tokio::join!(
+            seal(client, Some(b"key"), Side::Client),
+            seal(server, Some(b"key"), Side::Server)
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        let sent: Vec<u8> = (0..3 * MAX_RECORD_SIZE + 5).map(|i| i as u8).collect();
+        let mut received = vec![0; sent.len()];
+        let (written, read) = tokio::join!(client.write_all(&sent), server.read_exact(&mut received));
+        written.unwrap();
+        read.unwrap();
+        assert_eq!(received, sent);
+
+        // And the other way.
+        let (written, read) = tokio::join!(server.write_all(b"back"), client.read_exact(&mut received[..4]));
+        written.unwrap();
+        read.unwrap();
+        assert_eq!(&received[..4], b"back");
+    }
+
+    #[tokio::test]
+    async fn messages_are_sealed_as_one_record() {
+        let mut writer = Sealed::new(Vec::new(), keys(b"there", b"back"));
+        let message = Message::Hello("client".to_owned());
+        crate::write_message(&mut writer, &message, Encoding::Protobuf).await.unwrap();
+
+        let bytes = writer.inner;
+        let length = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize;
+        assert_eq!(bytes.len(), 4 + length + TAG_SIZE);
+
+        let reader = Sealed::new(&bytes[..], keys(b"back", b"there"));
+        let read = crate::read_message(reader, 1024, Encoding::Protobuf).await.unwrap();
+        assert!(matches!(read, Message::Hello(name) if name == "client"));
+    }
+
+    #[tokio::test]
+    async fn streams_pass_through_without_a_key() {
+        let (client, mut server) = io::duplex(1024);
+        let mut client = seal(client, None, Side::Client).await.unwrap();
+        client.write_all(b"plain").await.unwrap();
+
+        let mut received = [0; 5];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"plain");
+    }
+
+    #[tokio::test]
+    async fn mismatched_keys_fail_to_seal() {
+        let (client, server) = io::duplex(1024);
+        let (client, server) = tokio::join!(
+            seal(client, Some(b"one"), Side::Client),
+            seal(server, Some(b"other"), Side::Server)
+        );
+
+        assert_eq!(client.err().unwrap().kind(), ErrorKind::InvalidData);
+        assert_eq!(server.err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn tampered_records_are_rejected() {
+        let record = records(&[b"hello"]).await.remove(0);
+        assert_eq!(open(&record, &[5]).await.unwrap(), vec![b"hello".to_vec()]);
+
+        // A byte of the data, then one of the tag.
+        for index in [4, record.len() - 1] {
+            let mut tampered = record.clone();
+            tampered[index] ^= 1;
+            assert_eq!(open(&tampered, &[5]).await.unwrap_err().kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[tokio::test]
+    async fn replayed_reordered_and_dropped_records_are_rejected() {
+        let records = records(&[b"first", b"second", b"third"]).await;
+        let (first, second, third) = (&records[0], &records[1], &records[2]);
+
+        let replayed = [first.as_slice(), first].concat();
+        assert_eq!(open(&replayed, &[5, 5]).await.unwrap_err().kind(), ErrorKind::InvalidData);
+
+        let reordered = [second.as_slice(), first].concat();
+        assert_eq!(open(&reordered, &[6, 5]).await.unwrap_err().kind(), ErrorKind::InvalidData);
+
+        let dropped = [first.as_slice(), third].concat();
+        assert_eq!(open(&dropped, &[5, 5]).await.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn oversized_records_are_rejected() {
+        let mut bytes = (MAX_RECORD_SIZE as u32 + 1).to_le_bytes().to_vec();
+        bytes.resize(bytes.len() + MAX_RECORD_SIZE + 1 + TAG_SIZE, 0);
+        assert_eq!(open(&bytes, &[1]).await.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn cut_off_records_are_rejected() {
+        let record = records(&[b"hello"]).await.remove(0);
+        assert_eq!(open(&record[..record.len() - 1], &[5]).await.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn partial_and_pending_io_is_survived() {
+        let (client, server) = io::duplex(1024);
+        let (client, server) = (Trickle { inner: client, ready: false }, Trickle { inner: server, ready: false });
+        let (client, server) = tokio::join!(
+            seal(client, Some(b"key"), Side::Client),
+            seal(server, Some(b"key"), Side::Server)
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+        let sent: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let written = async {
+            client.write_all(&sent[..600]).await?;
+            client.write_all(&sent[600..]).await
+        };
+        // Read in pieces which don't line up with the records.
+        let read = async {
+            let mut received = Vec::new();
+            let mut piece = [0; 7];
+            while received.len() < sent.len() {
+                let size = server.read(&mut piece).await?;
+                received.extend_from_slice(&piece[..size]);
+            }
+
+            Ok::<_, Error>(received)
+        };
+
+        let (written, read) = tokio::join!(written, read);
+        written.unwrap();
+        assert_eq!(read.unwrap(), sent);
+    }
+}
@@ -0,0 +1,43 @@
+use serde::Deserialize;
+use socket2::{SockRef, TcpKeepalive};
+use std::io::Error;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+// Socket options for the connection between the server and a client, the [tcp] table in either config.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Options {
+    // Every event is written as soon as it happens, Nagle's algorithm would hold small writes back and make the pointer
+    // move in jumps.
+    pub nodelay: bool,
+    // Idle time before the OS starts probing the connection, unset leaves keepalive off.
+    pub keepalive_secs: Option<u64>,
+    pub send_buffer_size: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive_secs: None,
+            send_buffer_size: None,
+        }
+    }
+}
+
+impl Options {
+    pub fn apply(&self, stream: &TcpStream) -> Result<(), Error> {
+        stream.set_nodelay(self.nodelay)?;
+
+        let socket = SockRef::from(stream);
+        if let Some(secs) = self.keepalive_secs {
+            socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(Duration::from_secs(secs)))?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+
+        Ok(())
+    }
+}
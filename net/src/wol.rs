@@ -0,0 +1,56 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::Error;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::str::FromStr;
+
+// Wake-on-LAN: a sleeping machine whose network card is set up for it wakes up when it sees a magic packet, six 0xFF
+// bytes followed by its MAC address repeated sixteen times, broadcast over UDP.
+const PORT: u16 = 9;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl FromStr for MacAddress {
+    type Err = String;
+
+    // Six hex bytes separated by colons or dashes, e.g. aa:bb:cc:dd:ee:ff.
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid MAC address {:?}", address);
+
+        let mut bytes = [0; 6];
+        let mut parts = address.split(&[':', '-'][..]);
+        for byte in &mut bytes {
+            let part = parts.next().filter(|part| part.len() == 2).ok_or_else(err)?;
+            *byte = u8::from_str_radix(part, 16).map_err(|_| err())?;
+        }
+
+        match parts.next() {
+            Some(_) => Err(err()),
+            None => Ok(MacAddress(bytes)),
+        }
+    }
+}
+
+impl Display for MacAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, g)
+    }
+}
+
+pub fn magic_packet(address: MacAddress) -> Vec<u8> {
+    let mut packet = vec![0xFF; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(&address.0);
+    }
+
+    packet
+}
+
+// Broadcasts the magic packet on the local network, the machine can't be reached through routers this way.
+pub fn wake(address: MacAddress) -> Result<(), Error> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_broadcast(true)?;
+    socket.send_to(&magic_packet(address), (Ipv4Addr::BROADCAST, PORT))?;
+    Ok(())
+}
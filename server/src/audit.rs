@@ -0,0 +1,36 @@
+use std::fmt::Display;
+use std::net::SocketAddr;
+
+// Connection failures are logged in a stable format, one line each, so tools like fail2ban can act on them:
+// AUDIT event=<event> ip=<address> port=<port> reason="<reason>"
+#[derive(Clone, Copy, Debug)]
+pub enum Event {
+    TlsFailure,
+    VersionMismatch,
+    HandshakeFailure,
+    UnknownClient,
+    Throttled,
+}
+
+impl Event {
+    fn as_str(self) -> &'static str {
+        match self {
+            Event::TlsFailure => "tls-failure",
+            Event::VersionMismatch => "version-mismatch",
+            Event::HandshakeFailure => "handshake-failure",
+            Event::UnknownClient => "unknown-client",
+            Event::Throttled => "throttled",
+        }
+    }
+}
+
+pub fn log(event: Event, address: SocketAddr, reason: impl Display) {
+    log::warn!(
+        target: "audit",
+        "AUDIT event={} ip={} port={} reason={:?}",
+        event.as_str(),
+        address.ip(),
+        address.port(),
+        reason.to_string()
+    );
+}
@@ -0,0 +1,99 @@
+use std::path::Path;
+
+use anyhow::{Context, Error};
+use input::{Backend, Platform};
+use openssl::asn1::Asn1Time;
+use openssl::pkcs12::Pkcs12;
+use tokio::fs;
+use tokio::net::TcpListener;
+
+use crate::config::Config;
+
+// Goes through what the server needs to start, printing what passed and what to do about what didn't, instead of
+// failing on the first problem. Returns whether everything passed.
+pub async fn run(config: &Config) -> bool {
+    let mut passed = true;
+    passed &= report(
+        "TLS identity",
+        identity(config).await,
+        "Generate one with certificate-gen and check identity-path and identity-password",
+    );
+    passed &= report(
+        &format!("Listening on {}", config.listen_address),
+        TcpListener::bind(config.listen_address).await.map(drop).map_err(Error::from),
+        "Is another server running, or the address not assigned to this machine? Ports below 1024 need root",
+    );
+    if let Some(path) = &config.control_socket {
+        passed &= report(
+            "Control socket directory",
+            socket_directory(path).await,
+            "Create the directory the control-socket is in",
+        );
+    }
+    // Last, since the manager drops privileges once it has grabbed the devices.
+    passed &= report(
+        "Grabbing the input devices",
+        Platform::manager().await.map(drop).map_err(Error::from),
+        DEVICES_HINT,
+    );
+
+    passed
+}
+
+#[cfg(target_os = "linux")]
+const DEVICES_HINT: &str =
+    "Run as root or a user with access to /dev/input and /dev/uinput (see rkvm-server install), and load uinput with \
+     modprobe uinput";
+
+#[cfg(not(target_os = "linux"))]
+const DEVICES_HINT: &str = "Run as a user with access to the input devices";
+
+async fn identity(config: &Config) -> Result<(), Error> {
+    let identity = fs::read(&config.identity_path)
+        .await
+        .with_context(|| format!("Failed to read {}", config.identity_path.display()))?;
+    let identity = Pkcs12::from_der(&identity)
+        .context("Not a PKCS #12 file")?
+        .parse2(&config.identity_password)
+        .context("Failed to decrypt, wrong password?")?;
+    let certificate = identity.cert.context("No certificate in the identity")?;
+    if identity.pkey.is_none() {
+        return Err(Error::msg("No private key in the identity"));
+    }
+
+    let now = Asn1Time::days_from_now(0)?;
+    if certificate.not_after() < now {
+        return Err(anyhow::anyhow!("The certificate expired on {}", certificate.not_after()));
+    }
+    if certificate.not_before() > now {
+        return Err(anyhow::anyhow!("The certificate isn't valid until {}", certificate.not_before()));
+    }
+
+    Ok(())
+}
+
+async fn socket_directory(path: &Path) -> Result<(), Error> {
+    let directory = path.parent().unwrap_or_else(|| Path::new("."));
+    let metadata = fs::metadata(directory)
+        .await
+        .with_context(|| format!("Failed to access {}", directory.display()))?;
+    if !metadata.is_dir() {
+        return Err(anyhow::anyhow!("{} isn't a directory", directory.display()));
+    }
+
+    Ok(())
+}
+
+fn report(what: &str, result: Result<(), Error>, hint: &str) -> bool {
+    match result {
+        Ok(()) => {
+            println!("ok    {}", what);
+            true
+        }
+        Err(err) => {
+            println!("FAIL  {}: {:#}", what, err);
+            println!("      {}", hint);
+            false
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use anyhow::{Context, Error};
+use openssl::sha::sha256;
+use rkvm_core::Policy;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Records every clipboard transfer to a dedicated file, one line each, never the content itself:
+// CLIPBOARD time=<unix seconds> from=<machine> to=<machine> mime-type=<type> size=<bytes> sha256=<hex>
+// Added after the other policies, so only content which actually moves is recorded.
+pub struct ClipboardAudit {
+    file: File,
+    hostname: String,
+}
+
+impl ClipboardAudit {
+    pub fn new(path: &Path, hostname: String) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open clipboard audit log {}", path.display()))?;
+
+        Ok(Self { file, hostname })
+    }
+}
+
+impl Policy for ClipboardAudit {
+    fn allow_clipboard(&mut self, from: Option<&str>, to: Option<&str>, mime_type: &str, data: &[u8]) -> bool {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or(0);
+        let hash: String = sha256(data).iter().map(|byte| format!("{:02x}", byte)).collect();
+        let line = format!(
+            "CLIPBOARD time={} from={:?} to={:?} mime-type={:?} size={} sha256={}\n",
+            time,
+            from.unwrap_or(&self.hostname),
+            to.unwrap_or(&self.hostname),
+            mime_type,
+            data.len(),
+            hash
+        );
+
+        if let Err(err) = self.file.write_all(line.as_bytes()) {
+            log::warn!("Failed to write the clipboard audit log: {}", err);
+        }
+
+        true
+    }
+}
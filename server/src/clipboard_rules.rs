@@ -0,0 +1,61 @@
+use crate::config::{ClipboardRule, RuleDirection};
+use anyhow::{Context, Error};
+use regex::bytes::Regex;
+use rkvm_core::Policy;
+
+// Blocks clipboard content matching any of the configured rules.
+pub struct ClipboardRules {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    direction: RuleDirection,
+    pattern: Option<Regex>,
+    max_size: Option<usize>,
+}
+
+impl ClipboardRules {
+    pub fn new(rules: &[ClipboardRule]) -> Result<Self, Error> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let pattern = match &rule.pattern {
+                    Some(pattern) => Some(
+                        Regex::new(pattern).with_context(|| format!("Invalid clipboard rule pattern {:?}", pattern))?,
+                    ),
+                    None => None,
+                };
+
+                Ok(Rule {
+                    direction: rule.direction,
+                    pattern,
+                    max_size: rule.max_size,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Self { rules })
+    }
+}
+
+impl Rule {
+    fn applies(&self, from: Option<&str>, to: Option<&str>) -> bool {
+        match self.direction {
+            RuleDirection::Both => true,
+            RuleDirection::ToClients => to.is_some(),
+            RuleDirection::FromClients => from.is_some(),
+        }
+    }
+
+    fn blocks(&self, data: &[u8]) -> bool {
+        let too_large = self.max_size.map(|max| data.len() > max).unwrap_or(false);
+        let matches = self.pattern.as_ref().map(|pattern| pattern.is_match(data)).unwrap_or(false);
+        too_large || matches
+    }
+}
+
+impl Policy for ClipboardRules {
+    fn allow_clipboard(&mut self, from: Option<&str>, to: Option<&str>, _mime_type: &str, data: &[u8]) -> bool {
+        !self.rules.iter().any(|rule| rule.applies(from, to) && rule.blocks(data))
+    }
+}
@@ -1,8 +1,17 @@
+use anyhow::{Context, Error};
+use input::pointer::Corner;
 use input::Key;
+use log::LevelFilter;
+use rkvm_core::{ConnectionSettings, DuplicateNames, RelativeAxis, Sharing, SwitchPolicy};
 use serde::Deserialize;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::env;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio_native_tls::native_tls::Protocol;
+use toml_edit::{value, Array, Document};
 
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -10,7 +19,586 @@ pub struct Config {
     pub listen_address: SocketAddr,
     pub switch_keys: HashSet<Key>,
     pub kill_keys: HashSet<Key>,
+    #[serde(default)]
+    pub pause_keys: HashSet<Key>,
+    #[serde(default)]
+    pub roster_keys: HashSet<Key>,
+    #[serde(default)]
+    pub cancel_transfer_keys: HashSet<Key>,
+    #[serde(default)]
+    pub clipboard_keys: HashSet<Key>,
+    #[serde(default)]
+    pub picker_keys: HashSet<Key>,
+    #[serde(default)]
+    pub profile_keys: HashSet<Key>,
+    #[serde(default)]
+    pub override_keys: HashSet<Key>,
+    #[serde(default)]
+    pub lock_keys: HashSet<Key>,
+    #[serde(default)]
+    pub suspend_keys: HashSet<Key>,
+    #[serde(default)]
+    pub wake_keys: HashSet<Key>,
+    #[serde(default)]
+    pub secure_attention_keys: HashSet<Key>,
+    #[serde(default)]
+    pub undo_switch_keys: HashSet<Key>,
+    #[serde(default)]
+    pub switch_cooldown_ms: u64,
     pub identity_path: PathBuf,
     #[serde(default)]
     pub identity_password: String,
+    #[serde(default)]
+    pub osd_command: Vec<String>,
+    #[serde(default)]
+    pub focus_led: bool,
+    pub park_pointer: Option<Corner>,
+    #[serde(default)]
+    pub sync_activity: bool,
+    #[serde(default)]
+    pub share: Share,
+    #[serde(default)]
+    pub duplicate_names: DuplicateNamePolicy,
+    #[serde(default)]
+    pub switch_events: SwitchEvents,
+    #[serde(default)]
+    pub switch_hook: Vec<String>,
+    #[serde(default)]
+    pub axis_masks: HashMap<String, HashSet<MaskedAxis>>,
+    #[serde(default)]
+    pub key_substitutions: Vec<KeySubstitution>,
+    #[serde(default)]
+    pub local_keys: HashSet<Key>,
+    #[serde(default)]
+    pub display_names: HashMap<String, String>,
+    #[serde(default)]
+    pub groups: HashMap<String, HashSet<String>>,
+    #[serde(default)]
+    pub schedules: HashMap<String, Vec<TimeWindow>>,
+    #[serde(default)]
+    pub focus_commands: HashMap<String, String>,
+    #[serde(default)]
+    pub wake_on_lan: HashMap<String, String>,
+    #[serde(default)]
+    pub reject_unknown_clients: bool,
+    pub max_clients: Option<usize>,
+    pub idle_disconnect_minutes: Option<u64>,
+    pub reconnect_throttle: Option<ReconnectThrottle>,
+    pub control_socket: Option<PathBuf>,
+    pub log_level: Option<LevelFilter>,
+    #[serde(default = "default_max_message_size")]
+    pub max_message_size: u32,
+    pub message_key: Option<String>,
+    #[serde(default = "default_heartbeat_interval_ms")]
+    pub heartbeat_interval_ms: u64,
+    #[serde(default = "default_message_timeout_ms")]
+    pub message_timeout_ms: u64,
+    pub stale_motion_ms: Option<u64>,
+    #[serde(default = "default_stale_motion_queue")]
+    pub stale_motion_queue: usize,
+    pub mouse_keys: Option<MouseKeys>,
+    pub motion_dead_zone: Option<i32>,
+    pub acceleration: Option<Acceleration>,
+    #[serde(default = "default_display_scale")]
+    pub display_scale: f64,
+    #[serde(default)]
+    pub clipboard_rules: Vec<ClipboardRule>,
+    #[serde(default)]
+    pub path_translations: Vec<PathTranslation>,
+    #[serde(default)]
+    pub sanitize_clipboard: bool,
+    pub clipboard_audit_log: Option<PathBuf>,
+    #[serde(default)]
+    pub tls: Tls,
+    #[serde(default)]
+    pub tcp: net::tcp::Options,
+}
+
+// Reads the config, along with the files it includes. Each entry of the top level include array is a glob pattern,
+// relative to the directory of the config, and the matching files are merged in sorted by path. Included files can't
+// include others.
+pub async fn read(path: &Path) -> Result<Config, Error> {
+    let mut config = read_value(path).await?;
+    for path in included(path, &mut config)? {
+        let included = read_value(&path).await?;
+        if included.get("include").is_some() {
+            return Err(anyhow::anyhow!("{} includes other files, which only the main config can", path.display()));
+        }
+
+        log::debug!("Including {}", path.display());
+        merge(&mut config, included);
+    }
+
+    config.try_into().context("Error parsing config")
+}
+
+// Takes the include array out of the config read from path, and returns the files it matches in the order they're
+// merged in.
+fn included(path: &Path, config: &mut toml::Value) -> Result<Vec<PathBuf>, Error> {
+    let patterns: Vec<String> = match config.as_table_mut().and_then(|table| table.remove("include")) {
+        Some(patterns) => patterns.try_into().context("The include key must be an array of glob patterns")?,
+        None => Vec::new(),
+    };
+
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut included = Vec::new();
+    for pattern in patterns {
+        let pattern = directory.join(&pattern);
+        let pattern = pattern.to_str().context("Include patterns must be valid UTF-8")?;
+        let mut paths = glob::glob(pattern)
+            .with_context(|| format!("Invalid include pattern {}", pattern))?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to list included files")?;
+        paths.sort();
+        included.extend(paths);
+    }
+
+    Ok(included)
+}
+
+async fn read_value(path: &Path) -> Result<toml::Value, Error> {
+    let text = fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Error loading {}", path.display()))?;
+    let mut value = parse(path, &text).with_context(|| format!("Error parsing {}", path.display()))?;
+    expand_env_vars(&mut value).with_context(|| format!("Error parsing {}", path.display()))?;
+    Ok(value)
+}
+
+// YAML and JSON configs are told apart by their extension, anything else is TOML. They all end up as TOML values,
+// which the rest of the config handling works with.
+fn parse(path: &Path, text: &str) -> Result<toml::Value, Error> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("yaml") | Some("yml") => serde_yaml::from_str(text).map_err(Error::from),
+        Some("json") => serde_json::from_str(text).map_err(Error::from),
+        _ => toml::from_str(text).map_err(Error::from),
+    }
+}
+
+// Expands environment variable references in every string of the config, see rkvm_core::expand_env_vars.
+fn expand_env_vars(value: &mut toml::Value) -> Result<(), Error> {
+    match value {
+        toml::Value::String(text) => {
+            *text = rkvm_core::expand_env_vars(text, |name| env::var(name).ok()).map_err(Error::msg)?;
+        }
+        toml::Value::Array(values) => {
+            for value in values {
+                expand_env_vars(value)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                expand_env_vars(value)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// Tables are merged key by key and arrays of tables are appended to, so that e.g. each included file can add its own
+// [display-names] entries or [[key-substitutions]]. Anything else in an included file replaces what was there.
+fn merge(config: &mut toml::Value, included: toml::Value) {
+    match (config, included) {
+        (toml::Value::Table(config), toml::Value::Table(included)) => {
+            for (key, value) in included {
+                match config.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        config.insert(key, value);
+                    }
+                }
+            }
+        }
+        (toml::Value::Array(config), toml::Value::Array(included))
+            if config.iter().chain(&included).all(toml::Value::is_table) =>
+        {
+            config.extend(included);
+        }
+        (config, included) => *config = included,
+    }
+}
+
+impl Config {
+    // The name a client is known by from the one it says hello with, none if the client isn't let in.
+    pub fn display_name(&self, name: &str) -> Option<String> {
+        match self.display_names.get(name) {
+            Some(display_name) => Some(display_name.clone()),
+            None if self.reject_unknown_clients => None,
+            None => Some(name.to_owned()),
+        }
+    }
+}
+
+// Restricts the TLS handshake, anything left unset follows the platform's TLS library.
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Tls {
+    pub min_version: Option<TlsVersion>,
+    pub max_version: Option<TlsVersion>,
+    // Protocols offered through ALPN, clients offering none of them are turned away.
+    #[serde(default)]
+    pub alpn: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    V1_2,
+    #[serde(rename = "1.3")]
+    V1_3,
+}
+
+impl TlsVersion {
+    pub fn protocol(self) -> Protocol {
+        match self {
+            TlsVersion::V1_2 => Protocol::Tlsv12,
+            TlsVersion::V1_3 => Protocol::Tlsv13,
+        }
+    }
+}
+
+// What's forwarded to the focused client, the rest stays on the server.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Share {
+    #[default]
+    All,
+    Keyboard,
+    Mouse,
+}
+
+impl Share {
+    pub fn sharing(self) -> Sharing {
+        match self {
+            Share::All => Sharing::All,
+            Share::Keyboard => Sharing::Keyboard,
+            Share::Mouse => Sharing::Mouse,
+        }
+    }
+}
+
+// What happens to a client connecting under a name which is already taken.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateNamePolicy {
+    #[default]
+    Replace,
+    Reject,
+    Suffix,
+}
+
+impl DuplicateNamePolicy {
+    pub fn duplicate_names(self) -> DuplicateNames {
+        match self {
+            DuplicateNamePolicy::Replace => DuplicateNames::Replace,
+            DuplicateNamePolicy::Reject => DuplicateNames::Reject,
+            DuplicateNamePolicy::Suffix => DuplicateNames::Suffix,
+        }
+    }
+}
+
+// What happens to the lid and tablet mode switches of the grabbed devices.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SwitchEvents {
+    #[default]
+    Local,
+    Drop,
+    Forward,
+    Hook,
+}
+
+impl SwitchEvents {
+    pub fn switch_policy(self, hook: &[String]) -> Result<SwitchPolicy, Error> {
+        Ok(match self {
+            SwitchEvents::Local => SwitchPolicy::Local,
+            SwitchEvents::Drop => SwitchPolicy::Drop,
+            SwitchEvents::Forward => SwitchPolicy::Forward,
+            SwitchEvents::Hook if hook.is_empty() => return Err(Error::msg("switch-events = \"hook\" needs a switch-hook")),
+            SwitchEvents::Hook => SwitchPolicy::Hook(hook.to_vec()),
+        })
+    }
+}
+
+// Motion along these axes isn't forwarded to the client, see axis-masks.
+#[derive(Clone, Copy, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MaskedAxis {
+    X,
+    Y,
+    Wheel,
+}
+
+impl MaskedAxis {
+    pub fn axis(self) -> RelativeAxis {
+        match self {
+            MaskedAxis::X => RelativeAxis::X,
+            MaskedAxis::Y => RelativeAxis::Y,
+            MaskedAxis::Wheel => RelativeAxis::Wheel,
+        }
+    }
+}
+
+// A time of day range written as "16:00-20:00" in local time, ranges ending before they start go past midnight.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeWindow {
+    // Minutes since midnight, the end is exclusive.
+    pub start: u32,
+    pub end: u32,
+}
+
+impl TimeWindow {
+    pub fn contains(self, minute: u32) -> bool {
+        if self.start <= self.end {
+            self.start <= minute && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl TryFrom<String> for TimeWindow {
+    type Error = String;
+
+    fn try_from(window: String) -> Result<Self, Self::Error> {
+        let parse = |time: &str| {
+            let (hours, minutes) = time.trim().split_once(':')?;
+            let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+            if hours > 24 || minutes > 59 || (hours == 24 && minutes > 0) {
+                return None;
+            }
+
+            Some(hours * 60 + minutes)
+        };
+
+        let window = window
+            .split_once('-')
+            .and_then(|(start, end)| Some(TimeWindow { start: parse(start)?, end: parse(end)? }))
+            .ok_or_else(|| format!("Invalid time window {:?}, expected e.g. \"16:00-20:00\"", window))?;
+
+        // It would never match, all day is "00:00-24:00".
+        if window.start == window.end {
+            let (hours, minutes) = (window.start / 60, window.start % 60);
+            return Err(format!("The time window starting and ending at {:02}:{:02} is empty", hours, minutes));
+        }
+
+        Ok(window)
+    }
+}
+
+// Paths in clipboard text going from one machine to another starting with the prefix get it replaced, either machine
+// can be left out to match any. The server is named by its hostname.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PathTranslation {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub prefix: String,
+    pub replacement: String,
+}
+
+impl PathTranslation {
+    pub fn translation(&self) -> rkvm_core::PathTranslation {
+        rkvm_core::PathTranslation::new(
+            self.from.clone(),
+            self.to.clone(),
+            self.prefix.clone(),
+            self.replacement.clone(),
+        )
+    }
+}
+
+// The client with the given name gets the to key in place of the from key.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct KeySubstitution {
+    pub client: String,
+    pub from: Key,
+    pub to: Key,
+}
+
+// Clipboard content matching the pattern or larger than max-size isn't synced in the given direction.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ClipboardRule {
+    #[serde(default)]
+    pub direction: RuleDirection,
+    pub pattern: Option<String>,
+    pub max_size: Option<usize>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuleDirection {
+    // Content going to any client, wherever it comes from.
+    ToClients,
+    // Content coming from any client, wherever it goes to.
+    FromClients,
+    #[default]
+    Both,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Acceleration {
+    // Deltas up to this many units go to clients unchanged.
+    #[serde(default = "default_acceleration_threshold")]
+    pub threshold: i32,
+    // The part of a delta above the threshold is multiplied by this.
+    #[serde(default = "default_acceleration_gain")]
+    pub gain: f64,
+}
+
+fn default_display_scale() -> f64 {
+    1.0
+}
+
+fn default_acceleration_threshold() -> i32 {
+    4
+}
+
+fn default_acceleration_gain() -> f64 {
+    2.0
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReconnectThrottle {
+    // Connections allowed from one address within the window.
+    #[serde(default = "default_reconnect_attempts")]
+    pub attempts: usize,
+    #[serde(default = "default_reconnect_window_secs")]
+    pub window_secs: u64,
+    // How long an address connecting more often than that is refused.
+    #[serde(default = "default_reconnect_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+fn default_reconnect_attempts() -> usize {
+    10
+}
+
+fn default_reconnect_window_secs() -> u64 {
+    60
+}
+
+fn default_reconnect_cooldown_secs() -> u64 {
+    300
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MouseKeys {
+    // Pointer movement per step while a direction key is held.
+    #[serde(default = "default_mouse_keys_speed")]
+    pub speed: i32,
+}
+
+fn default_mouse_keys_speed() -> i32 {
+    5
+}
+
+fn default_max_message_size() -> u32 {
+    net::DEFAULT_MAX_MESSAGE_SIZE
+}
+
+fn default_heartbeat_interval_ms() -> u64 {
+    net::HEARTBEAT_INTERVAL.as_millis() as u64
+}
+
+fn default_message_timeout_ms() -> u64 {
+    net::MESSAGE_TIMEOUT.as_millis() as u64
+}
+
+fn default_stale_motion_queue() -> usize {
+    ConnectionSettings::default().stale_motion_queue
+}
+
+// Writes a key binding back to the config file, leaving everything else including comments untouched. That's the
+// last included file setting it if there is one, which would override the main config on the next start otherwise.
+pub async fn save_keys(path: &Path, field: &str, keys: &HashSet<Key>) -> Result<(), Error> {
+    let mut config = read_value(path).await?;
+    let mut target = path.to_owned();
+    for included in included(path, &mut config)? {
+        if read_value(&included).await?.get(field).is_some() {
+            target = included;
+        }
+    }
+
+    let path = target.as_path();
+    if let Some("yaml") | Some("yml") | Some("json") = path.extension().and_then(|extension| extension.to_str()) {
+        return Err(Error::msg("Key bindings can only be saved to TOML configs"));
+    }
+
+    let text = fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut document = text
+        .parse::<Document>()
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    // Key names in the config are the variant names, sorted to keep the file stable.
+    let mut names = keys.iter().map(|key| format!("{:?}", key)).collect::<Vec<_>>();
+    names.sort();
+    document[field] = value(names.into_iter().collect::<Array>());
+
+    fs::write(path, document.to_string())
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(window: &str) -> Result<(u32, u32), String> {
+        TimeWindow::try_from(window.to_owned()).map(|window| (window.start, window.end))
+    }
+
+    #[test]
+    fn time_windows_are_parsed() {
+        assert_eq!(window("16:00-20:30"), Ok((960, 1230)));
+        assert_eq!(window(" 8:05 - 9:00 "), Ok((485, 540)));
+        assert_eq!(window("00:00-24:00"), Ok((0, 1440)));
+    }
+
+    #[test]
+    fn invalid_time_windows_are_rejected() {
+        for invalid in ["", "16:00", "16-20", "16:00-24:01", "25:00-26:00", "16:60-20:00", "a:00-b:00", "-1:00-2:00"] {
+            assert!(window(invalid).is_err(), "{:?} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn empty_time_windows_are_rejected() {
+        assert!(window("08:00-08:00").is_err());
+        assert!(window("24:00-24:00").is_err());
+    }
+
+    #[test]
+    fn time_windows_can_span_midnight() {
+        let window = TimeWindow { start: 22 * 60, end: 2 * 60 };
+        assert!(window.contains(23 * 60));
+        assert!(window.contains(0));
+        assert!(window.contains(2 * 60 - 1));
+        assert!(!window.contains(2 * 60));
+        assert!(!window.contains(12 * 60));
+        assert!(!window.contains(22 * 60 - 1));
+        assert!(window.contains(22 * 60));
+    }
+
+    #[test]
+    fn time_window_ends_are_exclusive() {
+        let window = TimeWindow { start: 16 * 60, end: 20 * 60 };
+        assert!(!window.contains(16 * 60 - 1));
+        assert!(window.contains(16 * 60));
+        assert!(!window.contains(20 * 60));
+
+        let all_day = TimeWindow { start: 0, end: 24 * 60 };
+        assert!(all_day.contains(0));
+        assert!(all_day.contains(24 * 60 - 1));
+    }
 }
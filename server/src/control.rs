@@ -0,0 +1,162 @@
+use crate::config;
+use anyhow::{Context, Error};
+use input::Key;
+use log::LevelFilter;
+use net::control::{self, Hotkey, Notification, Request, Response};
+use rkvm_core::{Action, Handle};
+use std::collections::HashSet;
+use std::fs::Permissions;
+use std::io::ErrorKind;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+// The config path is where rebound hotkeys are saved to.
+pub async fn listen(path: &Path, handle: Handle, config_path: PathBuf) -> Result<(), Error> {
+    // A socket left behind by a previous run would make the bind fail, but anything else at the path isn't ours.
+    match fs::symlink_metadata(path).await {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(Error::msg(format!("Refusing to replace {}, which is not a socket", path.display())));
+        }
+        Ok(_) => fs::remove_file(path)
+            .await
+            .with_context(|| format!("Failed to remove stale control socket {}", path.display()))?,
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err).with_context(|| format!("Failed to inspect {}", path.display())),
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+    // The socket can switch clients and run commands on them, so only its owner may connect.
+    fs::set_permissions(path, Permissions::from_mode(0o600))
+        .await
+        .with_context(|| format!("Failed to restrict control socket {}", path.display()))?;
+
+    log::info!("Control socket listening on {}", path.display());
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    log::error!("Control socket error: {}", err);
+                    return;
+                }
+            };
+
+            let handle = handle.clone();
+            let config_path = config_path.clone();
+            tokio::spawn(async move {
+                if let Err(err) = handle_connection(stream, handle, &config_path).await {
+                    log::warn!("Control connection error: {:#}", err);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle_connection(mut stream: UnixStream, handle: Handle, config_path: &Path) -> Result<(), Error> {
+    loop {
+        let response = match control::read_request(&mut stream).await {
+            Ok(Request::Subscribe) => return subscribe(stream, handle.subscribe()).await,
+            Ok(Request::Switch(name)) => match handle.switch(&name).await {
+                Ok(()) => Response::Ok,
+                Err(err) => Response::Error(err.to_string()),
+            },
+            Ok(Request::SetProfile(group)) => match handle.set_profile(group).await {
+                Ok(()) => Response::Ok,
+                Err(err) => Response::Error(err.to_string()),
+            },
+            Ok(Request::RunCommand { client, command }) => match handle.run_command(&client, command).await {
+                Ok(()) => Response::Ok,
+                Err(err) => Response::Error(err.to_string()),
+            },
+            Ok(Request::Wake(name)) => match handle.wake(&name).await {
+                Ok(()) => Response::Ok,
+                Err(err) => Response::Error(err.to_string()),
+            },
+            Ok(Request::UndoSwitch) => match handle.undo_switch().await {
+                Ok(()) => Response::Ok,
+                Err(err) => Response::Error(err.to_string()),
+            },
+            Ok(Request::History) => Response::History(handle.history().await?),
+            Ok(Request::List) => Response::Clients(handle.clients().await?),
+            Ok(Request::SetLogLevel(level)) => match level.parse::<LevelFilter>() {
+                Ok(level) => {
+                    log::set_max_level(level);
+                    log::info!("Log level set to {}", level);
+                    Response::Ok
+                }
+                Err(_) => Response::Error(format!("Invalid log level {}", level)),
+            },
+            Ok(Request::SetHotkey { hotkey, keys, persist }) => {
+                match set_hotkey(&handle, hotkey, keys, persist, config_path).await {
+                    Ok(()) => Response::Ok,
+                    Err(err) => Response::Error(format!("{:#}", err)),
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        control::write_response(&mut stream, &response).await?;
+    }
+}
+
+async fn set_hotkey(
+    handle: &Handle,
+    hotkey: Hotkey,
+    keys: HashSet<Key>,
+    persist: bool,
+    config_path: &Path,
+) -> Result<(), Error> {
+    let (action, field) = match hotkey {
+        Hotkey::Switch => (Action::Switch, "switch-keys"),
+        Hotkey::Kill => (Action::Kill, "kill-keys"),
+        Hotkey::Pause => (Action::Pause, "pause-keys"),
+        Hotkey::Roster => (Action::Roster, "roster-keys"),
+        Hotkey::CancelTransfer => (Action::CancelTransfer, "cancel-transfer-keys"),
+        Hotkey::Clipboard => (Action::ToggleClipboard, "clipboard-keys"),
+        Hotkey::Picker => (Action::Pick, "picker-keys"),
+        Hotkey::Profile => (Action::NextProfile, "profile-keys"),
+        Hotkey::Override => (Action::OverrideAccess, "override-keys"),
+        Hotkey::Lock => (Action::LockAll, "lock-keys"),
+        Hotkey::Suspend => (Action::Suspend, "suspend-keys"),
+        Hotkey::Wake => (Action::Wake, "wake-keys"),
+        Hotkey::SecureAttention => (Action::SecureAttention, "secure-attention-keys"),
+        Hotkey::UndoSwitch => (Action::UndoSwitch, "undo-switch-keys"),
+    };
+
+    // Losing these would leave no way to leave a client or stop the server from the keyboard.
+    if keys.is_empty() && matches!(hotkey, Hotkey::Switch | Hotkey::Kill) {
+        return Err(Error::msg(format!("The {} keys can't be empty", field)));
+    }
+
+    if persist {
+        config::save_keys(config_path, field, &keys).await?;
+    }
+
+    handle.set_hotkey(action, keys).await?;
+    Ok(())
+}
+
+async fn subscribe(
+    mut stream: UnixStream,
+    mut receiver: broadcast::Receiver<Notification>,
+) -> Result<(), Error> {
+    loop {
+        let notification = match receiver.recv().await {
+            Ok(notification) => notification,
+            Err(RecvError::Lagged(count)) => {
+                log::warn!("Control subscriber lagged behind, skipped {} notifications", count);
+                continue;
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+
+        control::write_response(&mut stream, &Response::Notification(notification)).await?;
+    }
+}
@@ -0,0 +1,101 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Error};
+use structopt::StructOpt;
+use tokio::fs;
+
+const UNIT_PATH: &str = "etc/systemd/system/rkvm-server.service";
+const UDEV_RULE_PATH: &str = "etc/udev/rules.d/60-rkvm.rules";
+pub const DEFAULT_CONFIG: &str = include_str!("../../example/server.toml");
+
+#[derive(StructOpt)]
+pub struct InstallArgs {
+    #[structopt(long, default_value = "rkvm", help = "User the service runs as, it's given access to uinput and input devices")]
+    user: String,
+    #[structopt(long, default_value = "/", help = "Root directory to install into, useful for packaging")]
+    root: PathBuf,
+    #[structopt(long, help = "Path the service runs the server from, needed with --root [default: this executable]")]
+    executable: Option<PathBuf>,
+}
+
+// Sets up everything needed to run the server as a service without sudo: a systemd unit running as an unprivileged
+// user, a udev rule giving that user access to uinput and the input devices, and a default config.
+pub async fn install(args: &InstallArgs, config_path: &Path) -> Result<(), Error> {
+    if cfg!(not(target_os = "linux")) {
+        return Err(Error::msg("Installing is only supported on Linux"));
+    }
+
+    // When packaging, this executable is in the build directory rather than where the package puts it.
+    let executable = match &args.executable {
+        Some(executable) => executable.clone(),
+        None if args.root == Path::new("/") => env::current_exe().context("Failed to find the server executable")?,
+        None => return Err(Error::msg("Installing into another root needs --executable, where the server ends up")),
+    };
+
+    write(&args.root.join(UNIT_PATH), &unit(&executable, config_path, &args.user), true).await?;
+    write(&args.root.join(UDEV_RULE_PATH), &udev_rule(&args.user), true).await?;
+
+    // Never overwrite an existing config, it most likely has the user's changes.
+    let config = args.root.join(config_path.strip_prefix("/").unwrap_or(config_path));
+    write(&config, DEFAULT_CONFIG, false).await?;
+
+    log::info!("Done, to finish the installation:");
+    log::info!("  Create the user if needed: useradd --system --groups input {}", args.user);
+    log::info!("  Reload the udev rules: udevadm control --reload && udevadm trigger");
+    log::info!("  Generate an identity with certificate-gen and point {} to it", config_path.display());
+    log::info!("  Start the service: systemctl daemon-reload && systemctl enable --now rkvm-server");
+    Ok(())
+}
+
+async fn write(path: &Path, contents: &str, overwrite: bool) -> Result<(), Error> {
+    if !overwrite && fs::metadata(path).await.is_ok() {
+        log::info!("Keeping existing {}", path.display());
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    fs::write(path, contents)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    log::info!("Wrote {}", path.display());
+    Ok(())
+}
+
+fn unit(executable: &Path, config_path: &Path, user: &str) -> String {
+    format!(
+        "[Unit]
+Description=rkvm server
+After=network-online.target
+
+[Service]
+ExecStart={} {}
+User={}
+SupplementaryGroups=input
+# Holds the default control socket, which the user can't create in /run itself.
+RuntimeDirectory=rkvm
+Restart=always
+
+[Install]
+WantedBy=multi-user.target
+",
+        executable.display(),
+        config_path.display(),
+        user,
+    )
+}
+
+// uinput is only accessible to root by default, while the event devices already belong to the input group.
+fn udev_rule(user: &str) -> String {
+    format!(
+        "KERNEL==\"uinput\", SUBSYSTEM==\"misc\", OWNER=\"{}\", MODE=\"0600\", OPTIONS+=\"static_node=uinput\"
+SUBSYSTEM==\"input\", KERNEL==\"event*\", GROUP=\"input\", MODE=\"0660\"
+",
+        user,
+    )
+}
@@ -1,249 +1,378 @@
-use std::collections::{HashMap, HashSet};
 use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Error};
-use log::{error, LevelFilter};
+use gethostname::gethostname;
+use log::LevelFilter;
 use structopt::StructOpt;
 use tokio::fs;
-use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::TcpListener;
-use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::net::{TcpListener, TcpStream};
 use tokio::time;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
 use tokio_native_tls::native_tls::{Identity, TlsAcceptor};
+use tokio_native_tls::TlsStream;
 
-use config::Config;
-use input::{clipboard, Direction, Event, EventManager, Key, KeyKind};
-use net::{self, Message, PROTOCOL_VERSION};
+use audit::Event as AuditEvent;
+use clipboard_audit::ClipboardAudit;
+use clipboard_rules::ClipboardRules;
+use config::{Config, TlsVersion};
+use input::{Backend, ExitGuard, Platform};
+use net::sealed::{Sealed, Side};
+use net::wol::MacAddress;
+use net::{self, Encoding, Message, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use rkvm_core::{Action, ConnectionSettings, DeadZone, Handle, Kvm};
+use schedule::Schedules;
+use throttle::{Throttle, Verdict};
 
+mod audit;
+mod check;
+mod clipboard_audit;
+mod clipboard_rules;
 mod config;
+#[cfg(unix)]
+mod control;
+mod install;
+mod schedule;
+mod throttle;
 
-#[derive(Clone, Debug)]
-struct Client {
-    name: String,
-    sender: UnboundedSender<Message>,
-}
+// Returns once stopped by a signal, the kill hotkey is an error.
+// The config path is only needed by the control socket.
+#[cfg_attr(not(unix), allow(unused_variables))]
+async fn run(config: Arc<Config>, config_path: &Path) -> Result<(), Error> {
+    let identity = fs::read(&config.identity_path)
+        .await
+        .context("Failed to read identity")?;
+    let identity = Identity::from_pkcs12(&identity, &config.identity_password)
+        .context("Failed to parse identity")?;
+    let mut acceptor = TlsAcceptor::builder(identity);
+    acceptor
+        .min_protocol_version(config.tls.min_version.map(TlsVersion::protocol))
+        .max_protocol_version(config.tls.max_version.map(TlsVersion::protocol));
+    if !config.tls.alpn.is_empty() {
+        acceptor.accept_alpn(&config.tls.alpn);
+    }
+    let acceptor: tokio_native_tls::TlsAcceptor = acceptor
+        .build()
+        .context("Failed to create TLS acceptor")
+        .map(Into::into)?;
+    log::info!(
+        "TLS versions {:?} to {:?}, ALPN {:?}",
+        config.tls.min_version,
+        config.tls.max_version,
+        config.tls.alpn
+    );
+    let listener = TcpListener::bind(config.listen_address).await?;
 
-async fn handle_connection<T>(
-    mut stream: T,
-    mut receiver: UnboundedReceiver<Message>,
-    sender: UnboundedSender<Message>,
-) -> Result<(), Error>
-    where
-        T: AsyncRead + AsyncWrite + Unpin,
-{
-    loop {
-        tokio::select! {
-            out_message = time::timeout(net::MESSAGE_TIMEOUT / 2, receiver.recv()) => {
-                let message = match out_message {
-                    Ok(Some(message)) => message,
-                    Ok(None) => return Ok(()),
-                    Err(_) => Message::KeepAlive,
-                };
-
-                time::timeout(net::MESSAGE_TIMEOUT, net::write_message(&mut stream, &message))
-                    .await
-                    .context("Write timeout")??;
-            }
-            in_message = net::read_message(&mut stream) => {
-                sender.send(in_message?)?;
-            }
+    log::info!("Listening on {}", config.listen_address);
+
+    let settings = ConnectionSettings {
+        max_message_size: config.max_message_size,
+        heartbeat_interval: Duration::from_millis(config.heartbeat_interval_ms),
+        timeout: Duration::from_millis(config.message_timeout_ms),
+        stale_motion_age: config.stale_motion_ms.map(Duration::from_millis),
+        stale_motion_queue: config.stale_motion_queue,
+    };
+    if settings.heartbeat_interval >= settings.timeout {
+        log::warn!("The heartbeat interval should be shorter than the message timeout, idle clients will be disconnected");
+    }
+
+    let manager = Platform::manager().await?;
+    let hostname = gethostname().to_string_lossy().into_owned();
+    let mut builder = Kvm::builder(manager, hostname.clone())
+        .hotkey(Action::Switch, &config.switch_keys)
+        .hotkey(Action::Kill, &config.kill_keys)
+        .hotkey(Action::Pause, &config.pause_keys)
+        .hotkey(Action::Roster, &config.roster_keys)
+        .hotkey(Action::CancelTransfer, &config.cancel_transfer_keys)
+        .hotkey(Action::ToggleClipboard, &config.clipboard_keys)
+        .hotkey(Action::Pick, &config.picker_keys)
+        .hotkey(Action::NextProfile, &config.profile_keys)
+        .hotkey(Action::OverrideAccess, &config.override_keys)
+        .hotkey(Action::LockAll, &config.lock_keys)
+        .hotkey(Action::Suspend, &config.suspend_keys)
+        .hotkey(Action::Wake, &config.wake_keys)
+        .hotkey(Action::SecureAttention, &config.secure_attention_keys)
+        .hotkey(Action::UndoSwitch, &config.undo_switch_keys)
+        .hotkey_cooldown(Action::Switch, Duration::from_millis(config.switch_cooldown_ms))
+        .focus_led(config.focus_led)
+        .sync_activity(config.sync_activity)
+        .idle_timeout(config.idle_disconnect_minutes.map(|minutes| Duration::from_secs(minutes * 60)))
+        .sharing(config.share.sharing())
+        .duplicate_names(config.duplicate_names.duplicate_names())
+        .switch_policy(config.switch_events.switch_policy(&config.switch_hook)?)
+        .local_keys(&config.local_keys)
+        .display_scale(config.display_scale)
+        .sanitize_clipboard(config.sanitize_clipboard)
+        .osd_command(config.osd_command.clone())
+        .connection_settings(settings);
+    for substitution in &config.key_substitutions {
+        builder = builder.key_substitution(substitution.client.clone(), substitution.from, substitution.to);
+    }
+    for translation in &config.path_translations {
+        builder = builder.path_translation(translation.translation());
+    }
+    for (client, address) in &config.wake_on_lan {
+        let address = address.parse::<MacAddress>().map_err(Error::msg)?;
+        builder = builder.wake_on_lan(client.clone(), address);
+    }
+    for (client, command) in &config.focus_commands {
+        builder = builder.focus_command(client.clone(), command.clone());
+    }
+    for (name, clients) in &config.groups {
+        builder = builder.group(name.clone(), clients.clone());
+    }
+    for (client, axes) in &config.axis_masks {
+        builder = builder.axis_mask(client.clone(), axes.iter().map(|axis| axis.axis()).collect());
+    }
+    if let Some(acceleration) = &config.acceleration {
+        builder = builder.acceleration(acceleration.threshold, acceleration.gain);
+    }
+    if let Some(mouse_keys) = &config.mouse_keys {
+        builder = builder.mouse_keys(mouse_keys.speed);
+    }
+    if let Some(corner) = config.park_pointer {
+        builder = builder.park_pointer(corner);
+    }
+    if let Some(threshold) = config.motion_dead_zone {
+        builder = builder.policy(DeadZone::new(threshold));
+    }
+    if !config.clipboard_rules.is_empty() {
+        builder = builder.policy(ClipboardRules::new(&config.clipboard_rules)?);
+    }
+    if !config.schedules.is_empty() {
+        builder = builder.policy(Schedules::new(config.schedules.clone()));
+    }
+    if let Some(path) = &config.clipboard_audit_log {
+        builder = builder.policy(ClipboardAudit::new(path, hostname)?);
+    }
+    let (kvm, handle) = builder.build();
+
+    // Bound after the event manager has dropped privileges, so that the socket is owned by the invoking user.
+    #[cfg(unix)]
+    if let Some(path) = &config.control_socket {
+        control::listen(path, handle.clone(), config_path.to_owned()).await?;
+    }
+    #[cfg(not(unix))]
+    if config.control_socket.is_some() {
+        log::warn!("The control socket is only supported on unix, ignoring control-socket");
+    }
+
+    let kvm = kvm.run();
+    tokio::pin!(kvm);
+    tokio::select! {
+        result = &mut kvm => {
+            result?;
+            return Err(Error::msg("Kilt"));
         }
+        result = accept(listener, acceptor, handle.clone(), settings, config.clone()) => match result? {},
+        result = terminated() => {
+            result.context("Failed to set up signal handlers")?;
+            log::info!("Shutting down on signal");
+            handle.shutdown();
+        }
+    }
+
+    // The KVM releases the held keys and says goodbye, the connections then get a moment to deliver that.
+    kvm.await?;
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while handle.connections() > 0 && Instant::now() < deadline {
+        time::sleep(Duration::from_millis(10)).await;
     }
+
+    Ok(())
 }
 
-async fn run(
-    listen_address: SocketAddr,
-    switch_keys: &HashSet<Key>,
-    kill_keys: &HashSet<Key>,
-    identity_path: &Path,
-    identity_password: &str,
-) -> Result<Infallible, Error> {
-    let identity = fs::read(identity_path)
-        .await
-        .context("Failed to read identity")?;
-    let identity =
-        Identity::from_pkcs12(&identity, identity_password).context("Failed to parse identity")?;
-    let acceptor: tokio_native_tls::TlsAcceptor = TlsAcceptor::new(identity)
-        .context("Failed to create TLS acceptor")
-        .map(Into::into)?;
-    let listener = TcpListener::bind(listen_address).await?;
+// How long connections are waited for when shutting down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
 
-    log::info!("Listening on {}", listen_address);
+// Resolves on SIGTERM, which systemd stops the service with, or SIGINT.
+#[cfg(unix)]
+async fn terminated() -> Result<(), Error> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = interrupt.recv() => {}
+    }
 
-    let (client_sender, mut client_receiver) = mpsc::unbounded_channel();
-    let (in_sender, mut in_receiver) = mpsc::unbounded_channel();
-    tokio::spawn(async move {
-        loop {
-            let (stream, address) = match listener.accept().await {
-                Ok(sa) => sa,
-                Err(err) => {
-                    let _ = client_sender.send(Err(err));
-                    return;
-                }
-            };
+    Ok(())
+}
 
-            let mut stream = match acceptor.accept(stream).await {
-                Ok(stream) => stream,
-                Err(err) => {
-                    log::error!("{}: TLS error: {}", address, err);
-                    continue;
-                }
-            };
+#[cfg(not(unix))]
+async fn terminated() -> Result<(), Error> {
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
 
-            if let Err(e) = net::write_version(&mut stream, PROTOCOL_VERSION).await {
-                error!("{}: Failed to write version: {}", address, e);
+// Accepts connections and hands them over to the KVM once the handshake is done.
+#[tracing::instrument(name = "accept", skip_all)]
+async fn accept(
+    listener: TcpListener,
+    acceptor: tokio_native_tls::TlsAcceptor,
+    handle: Handle,
+    settings: ConnectionSettings,
+    config: Arc<Config>,
+) -> Result<Infallible, Error> {
+    let mut throttle = config.reconnect_throttle.as_ref().map(Throttle::new);
+    let handshakes = Arc::new(AtomicUsize::new(0));
+    loop {
+        let (stream, address) = listener.accept().await?;
+        // Refused before the TLS handshake, which is the expensive part.
+        match throttle.as_mut().map(|throttle| throttle.check(address.ip())) {
+            Some(Verdict::CoolDown) => {
+                audit::log(AuditEvent::Throttled, address, "Too many connections, cooling down");
                 continue;
             }
-
-            match net::read_version(&mut stream).await {
-                Ok(version) => {
-                    if version != PROTOCOL_VERSION {
-                        error!("Incompatible protocol version (got {}, expecting {})", version, PROTOCOL_VERSION);
-                        continue;
-                    }
-                }
-                Err(e) => {
-                    error!("{}: Failed to read version: {}", address, e);
-                    continue;
-                }
+            Some(Verdict::Refuse) => {
+                log::debug!("Refusing {}, still cooling down", address);
+                continue;
             }
+            Some(Verdict::Allow) | None => {}
+        }
 
-            let client_name = match net::read_message(&mut stream).await {
-                Ok(Message::Hello(name)) => name,
-                Ok(message) => {
-                    error!("{}: Failed to read name.  Read {:?}", address, message);
-                    continue;
-                }
-                Err(e) => {
-                    error!("{}: Failed to read name: {}", address, e);
-                    continue;
-                }
-            };
-
-            let (out_sender, out_receiver) = mpsc::unbounded_channel();
-            if client_sender.send(Ok(Client {name: client_name.clone(), sender: out_sender})).is_err() {
-                return;
+        // Every connection costs a few tasks and buffers, too many of them can exhaust a small server. Like the
+        // throttle it's checked before the TLS handshake, counting the handshakes which are still going on.
+        if let Some(max_clients) = config.max_clients {
+            if handle.connections() + handshakes.load(Ordering::Relaxed) >= max_clients {
+                log::warn!("Turning away {}, already serving the maximum of {} clients", address, max_clients);
+                continue;
             }
+        }
 
-            let message_sender = in_sender.clone();
-            tokio::spawn(async move {
-                log::info!("{} {}: connected", client_name, address);
-                let message = handle_connection(stream, out_receiver, message_sender)
-                    .await
-                    .err()
-                    .map(|err| format!(" ({})", err))
-                    .unwrap_or_else(String::new);
-                log::info!("{} {}: disconnected{}", client_name, address, message);
-            });
+        if let Err(err) = config.tcp.apply(&stream) {
+            log::warn!("Failed to set socket options for {}: {}", address, err);
         }
-    });
 
-    let mut clients: Vec<Client> = Vec::new();
-    let mut current = 0;
-    let mut manager = EventManager::new().await?;
-    let mut switch_key_states: HashMap<_, _> = switch_keys
-        .iter()
-        .map(|key| (key.clone(), false))
-        .collect();
-    let mut kill_key_states: HashMap<_, _> = kill_keys
-        .iter()
-        .map(|key| (key.clone(), false))
-        .collect();
-    loop {
-        tokio::select! {
-            message = in_receiver.recv() => {
-                if let Some(message) = message {
-                    match message {
-                        Message::SetClipboardData(text) => {
-                            if current == 0 {
-                                clipboard::set_text(text);
-                            } else {
-                                let idx = current - 1;
-                                if let Err(e) = clients[idx].sender.send(Message::SetClipboardData(text)) {
-                                    log::warn!("{:?}", e);
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
+        // Each handshake runs in its own task under a single timeout, so that a peer which stalls at any step holds up
+        // neither everyone connecting after it nor a task forever.
+        let acceptor = acceptor.clone();
+        let handle = handle.clone();
+        let config = config.clone();
+        let handshakes = handshakes.clone();
+        handshakes.fetch_add(1, Ordering::Relaxed);
+        tokio::spawn(async move {
+            let handshake = handshake(stream, address, &acceptor, settings, &config);
+            match time::timeout(settings.timeout, handshake).await {
+                Ok(Some((client_name, stream))) => {
+                    handle.add_client(client_name, address, stream);
                 }
+                Ok(None) => {}
+                Err(_) => audit::log(AuditEvent::HandshakeFailure, address, "Handshake timed out"),
             }
-            event = manager.read() => {
-                let event = event?;
-                if let Event::Key { direction, kind: KeyKind::Key(key) } = event {
-                    if let Some(state) = switch_key_states.get_mut(&key) {
-                        *state = direction == Direction::Down;
-                    } else if let Some(state) = kill_key_states.get_mut(&key) {
-                        *state = direction == Direction::Down;
-                    }
-                }
+            // Only once the client has been added, so that it's always counted by one or the other.
+            handshakes.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
 
-                // TODO: This won't work with multiple keys.
-                if switch_key_states.iter().filter(|(_, state)| **state).count() == switch_key_states.len() {
-                    for state in switch_key_states.values_mut() {
-                        *state = false;
-                    }
-
-                    let previous = current;
-                    current = (current + 1) % (clients.len() + 1);
-                    log::info!("Switching to client {} from {}", current, previous);
-
-                    if current == 0 {
-                        manager.notify("I'm over here now!".to_string());
-                    } else {
-                        let idx = current - 1;
-                        if let Err(e) = clients[idx].sender.send(Message::Notify("I'm over here now!".to_string())) {
-                            log::warn!("{:?}", e);
-                        } else {
-                            manager.notify(format!("Switched to {}", clients[idx].name).to_string());
-                            log::debug!("Notify client {}", current);
-                        }
-                    }
-
-                    if previous == 0 {
-                        if let Some(text) = clipboard::get_text() {
-                            let idx = current - 1;
-                            if let Err(e) = clients[idx].sender.send(Message::SetClipboardData(text)) {
-                                log::warn!("{:?}", e);
-                            }
-                        }
-                    } else {
-                        let idx = previous - 1;
-                        if let Err(e) = clients[idx].sender.send(Message::GetClipboardData) {
-                            log::warn!("{:?}", e);
-                        }
-                    }
-                    continue;
-                } else if kill_key_states.iter().filter(|(_, state)| **state).count() == kill_key_states.len() {
-                    for state in kill_key_states.values_mut() {
-                        *state = false;
-                    }
-                    return Err(Error::msg("Kilt"));
-                }
+// Runs the handshake up to the client's hello, none if the client isn't let in, which has been logged already.
+async fn handshake(
+    stream: TcpStream,
+    address: SocketAddr,
+    acceptor: &tokio_native_tls::TlsAcceptor,
+    settings: ConnectionSettings,
+    config: &Config,
+) -> Option<(String, Sealed<TlsStream<TcpStream>>)> {
+    let mut stream = match acceptor.accept(stream).await {
+        Ok(stream) => stream,
+        Err(err) => {
+            audit::log(AuditEvent::TlsFailure, address, err);
+            return None;
+        }
+    };
 
-                if current != 0 {
-                    let idx = current - 1;
-                    if let Err(e) = clients[idx].sender.send(Message::Event(event)) {
-                        log::warn!("{:?}.  Removing client {}", e, current);
-                        clients.remove(idx);
-                        current = 0;
-                    } else {
-                        log::debug!("Send client {} {:?}", current, event);
-                        continue;
-                    }
-                }
+    // A client offering none of the configured protocols still completes the handshake without ALPN.
+    if !config.tls.alpn.is_empty() && !matches!(stream.get_ref().negotiated_alpn(), Ok(Some(_))) {
+        audit::log(AuditEvent::TlsFailure, address, "No ALPN protocol negotiated");
+        return None;
+    }
 
-                log::debug!("Send manager {:?}", event);
-                manager.write(event).await?;
-            }
-            sender = client_receiver.recv() => {
-                clients.push(sender.unwrap()?);
+    if let Err(e) = net::write_version(&mut stream, PROTOCOL_VERSION).await {
+        audit::log(AuditEvent::HandshakeFailure, address, format!("Failed to write version: {}", e));
+        return None;
+    }
+
+    let offered = match net::read_version(&mut stream).await {
+        Ok(offered) => match net::negotiate_version(offered) {
+            Some(_) => offered,
+            None => {
+                let reason = format!("Incompatible protocol version (got {}, expecting at least {})", offered, MIN_PROTOCOL_VERSION);
+                audit::log(AuditEvent::VersionMismatch, address, reason);
+                return None;
             }
+        },
+        Err(e) => {
+            audit::log(AuditEvent::HandshakeFailure, address, format!("Failed to read version: {}", e));
+            return None;
         }
+    };
+
+    let key = config.message_key.as_deref().map(str::as_bytes);
+    let mut stream = match net::sealed::seal(stream, key, Side::Server).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            audit::log(AuditEvent::HandshakeFailure, address, format!("Failed to authenticate messages: {}", e));
+            return None;
+        }
+    };
+
+    if let Err(e) = net::confirm_version(&mut stream, offered, PROTOCOL_VERSION).await {
+        audit::log(AuditEvent::VersionMismatch, address, format!("Failed to confirm version: {}", e));
+        return None;
     }
+
+    let client_name = match net::read_message(&mut stream, settings.max_message_size, Encoding::Protobuf).await {
+        Ok(Message::Hello(name)) => name,
+        Ok(message) => {
+            audit::log(AuditEvent::HandshakeFailure, address, format!("Expected hello, got {:?}", message));
+            return None;
+        }
+        Err(e) => {
+            audit::log(AuditEvent::HandshakeFailure, address, format!("Failed to read name: {}", e));
+            return None;
+        }
+    };
+
+    let client_name = match config.display_name(&client_name) {
+        Some(display_name) => display_name,
+        None => {
+            audit::log(AuditEvent::UnknownClient, address, format!("Unknown client name {}", client_name));
+            return None;
+        }
+    };
+
+    Some((client_name, stream))
+}
+
+// SIGUSR1 toggles debug logging, so that the event flow can be inspected without a restart.
+#[cfg(unix)]
+fn toggle_debug_on_signal(level: LevelFilter) {
+    tokio::spawn(async move {
+        let mut signal = match signal(SignalKind::user_defined1()) {
+            Ok(signal) => signal,
+            Err(err) => {
+                log::warn!("Failed to set up SIGUSR1 handler: {}", err);
+                return;
+            }
+        };
+
+        while signal.recv().await.is_some() {
+            let new_level = if log::max_level() < LevelFilter::Debug {
+                LevelFilter::Debug
+            } else {
+                level
+            };
+
+            log::set_max_level(new_level);
+            log::info!("Log level set to {}", new_level);
+        }
+    });
 }
 
 #[derive(StructOpt)]
@@ -255,50 +384,79 @@ struct Args {
     structopt(default_value = "/etc/rkvm/server.toml")
     )]
     #[cfg_attr(
+    target_os = "freebsd",
+    structopt(default_value = "/usr/local/etc/rkvm/server.toml")
+    )]
+    #[cfg_attr(
     target_os = "windows",
     structopt(default_value = "C:/rkvm/server.toml")
     )]
     config_path: PathBuf,
+    #[structopt(long, help = "Print a default config with every option explained and exit")]
+    print_default_config: bool,
+    #[structopt(long, help = "Check the config, the TLS identity, the listen address and access to the input devices, then exit")]
+    check: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    #[structopt(about = "Install a systemd service, udev rule and default config")]
+    Install(install::InstallArgs),
 }
 
 #[tokio::main]
 async fn main() {
+    #[cfg(feature = "console")]
+    console_subscriber::init();
+
     env_logger::builder()
         .format_timestamp(None)
-        .filter(None, LevelFilter::Info)
+        .filter(None, LevelFilter::Trace)
         .init();
+    // The actual level is controlled through the global max level, so that it can be changed at runtime.
+    log::set_max_level(LevelFilter::Info);
 
     let args = Args::from_args();
-    let config = match fs::read_to_string(&args.config_path).await {
-        Ok(config) => config,
-        Err(err) => {
-            log::error!("Error loading config: {}", err);
+    if args.print_default_config {
+        print!("{}", install::DEFAULT_CONFIG);
+        return;
+    }
+
+    if let Some(Command::Install(install_args)) = &args.command {
+        if let Err(err) = install::install(install_args, &args.config_path).await {
+            log::error!("Error: {:#}", err);
             process::exit(1);
         }
-    };
 
-    let config: Config = match toml::from_str(&config) {
+        return;
+    }
+
+    let config = match config::read(&args.config_path).await {
         Ok(config) => config,
         Err(err) => {
-            log::error!("Error parsing config: {}", err);
+            log::error!("{:#}", err);
             process::exit(1);
         }
     };
 
-    tokio::select! {
-        result = run(config.listen_address, &config.switch_keys, &config.kill_keys, &config.identity_path, &config.identity_password) => {
-            if let Err(err) = result {
-                log::error!("Error: {:#}", err);
-                process::exit(1);
-            }
-        }
-        result = tokio::signal::ctrl_c() => {
-            if let Err(err) = result {
-                log::error!("Error setting up signal handler: {}", err);
-                process::exit(1);
-            }
+    if args.check {
+        let passed = check::run(&config).await;
+        process::exit(if passed { 0 } else { 1 });
+    }
 
-            log::info!("Exiting on signal");
-        }
+    let log_level = config.log_level.unwrap_or(LevelFilter::Info);
+    log::set_max_level(log_level);
+    #[cfg(unix)]
+    toggle_debug_on_signal(log_level);
+
+    let _guard = ExitGuard::install();
+
+    if let Err(err) = run(Arc::new(config), &args.config_path).await {
+        log::error!("Error: {:#}", err);
+        process::exit(1);
     }
+
+    // The guard ungrabs the devices on the way out.
 }
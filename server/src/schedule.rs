@@ -0,0 +1,126 @@
+use crate::config::TimeWindow;
+use rkvm_core::Policy;
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::mem::MaybeUninit;
+use std::time::{Duration, Instant};
+
+// Lets clients with a schedule take input only during their time windows. Outside of them they can't be switched to,
+// and input stays on the server if one of them still has focus when its window closes.
+pub struct Schedules {
+    schedules: HashMap<String, Vec<TimeWindow>>,
+    // Whether telling the local time failed the last time, so that it's only logged once.
+    clock_failing: bool,
+    // The local minute is checked for every forwarded event, so it's only read again once it has turned over.
+    minute: Option<u32>,
+    refresh: Option<Instant>,
+}
+
+impl Schedules {
+    pub fn new(schedules: HashMap<String, Vec<TimeWindow>>) -> Self {
+        Self {
+            schedules,
+            clock_failing: false,
+            minute: None,
+            refresh: None,
+        }
+    }
+
+    fn allows(&mut self, client: &str) -> bool {
+        if !self.schedules.contains_key(client) {
+            return true;
+        }
+
+        // Without the time, the schedules are lifted rather than locking everyone out.
+        let minute = match self.minute_at(Instant::now(), local_time) {
+            Some(minute) => minute,
+            None => {
+                if !self.clock_failing {
+                    log::warn!("Failed to tell the local time, ignoring the schedules until it works again");
+                }
+                self.clock_failing = true;
+                return true;
+            }
+        };
+
+        self.clock_failing = false;
+        self.schedules[client].iter().any(|window| window.contains(minute))
+    }
+
+    fn minute_at(&mut self, now: Instant, read: impl FnOnce() -> Option<(u32, u32)>) -> Option<u32> {
+        if !matches!(self.refresh, Some(refresh) if now < refresh) {
+            let time = read();
+            self.minute = time.map(|(minute, _)| minute);
+            // A time that couldn't be told is retried a minute later.
+            let left = time.map_or(60, |(_, second)| 60 - second.min(59));
+            self.refresh = Some(now + Duration::from_secs(left.into()));
+        }
+
+        self.minute
+    }
+}
+
+impl Policy for Schedules {
+    fn allow_switch(&mut self, _from: Option<&str>, to: Option<&str>) -> bool {
+        to.map(|to| self.allows(to)).unwrap_or(true)
+    }
+
+    fn allow_forward(&mut self, to: &str) -> bool {
+        self.allows(to)
+    }
+}
+
+// Minutes since local midnight and the second within that minute, none if the local time can't be told.
+#[cfg(unix)]
+fn local_time() -> Option<(u32, u32)> {
+    let mut tm = MaybeUninit::<libc::tm>::uninit();
+    // SAFETY: localtime_r only writes to the given tm, which is only read if it succeeded.
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        if libc::localtime_r(&now, tm.as_mut_ptr()).is_null() {
+            return None;
+        }
+
+        tm.assume_init()
+    };
+
+    Some(((tm.tm_hour * 60 + tm.tm_min) as u32, tm.tm_sec as u32))
+}
+
+// Schedules are lifted where the local time isn't available.
+#[cfg(not(unix))]
+fn local_time() -> Option<(u32, u32)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn minute_is_read_once_it_turns_over() {
+        let mut schedules = Schedules::new(HashMap::new());
+        let reads = Cell::new(0);
+        let read = |time| {
+            let reads = &reads;
+            move || {
+                reads.set(reads.get() + 1);
+                time
+            }
+        };
+
+        let start = Instant::now();
+        assert_eq!(schedules.minute_at(start, read(Some((600, 50)))), Some(600));
+        assert_eq!(schedules.minute_at(start + Duration::from_secs(9), read(Some((601, 0)))), Some(600));
+        assert_eq!(reads.get(), 1);
+
+        assert_eq!(schedules.minute_at(start + Duration::from_secs(10), read(Some((601, 0)))), Some(601));
+        assert_eq!(reads.get(), 2);
+
+        assert_eq!(schedules.minute_at(start + Duration::from_secs(70), read(None)), None);
+        assert_eq!(schedules.minute_at(start + Duration::from_secs(129), read(Some((603, 0)))), None);
+        assert_eq!(schedules.minute_at(start + Duration::from_secs(130), read(Some((603, 0)))), Some(603));
+        assert_eq!(reads.get(), 4);
+    }
+}
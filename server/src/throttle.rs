@@ -0,0 +1,148 @@
+use crate::config::ReconnectThrottle;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Verdict {
+    Allow,
+    // Too many connections in the window, the address has just been put on cool-down.
+    CoolDown,
+    // Still cooling down from earlier.
+    Refuse,
+}
+
+#[derive(Default)]
+struct Attempts {
+    recent: VecDeque<Instant>,
+    cooling_until: Option<Instant>,
+}
+
+// Keeps a client stuck in a crash loop from hammering the accept loop. Clients are told apart by their address, since
+// connections are refused before the handshake which would tell their name. An address connecting more than the
+// allowed number of times within the window is refused for the cool-down.
+pub struct Throttle {
+    attempts: usize,
+    window: Duration,
+    cooldown: Duration,
+    addresses: HashMap<IpAddr, Attempts>,
+}
+
+impl Throttle {
+    pub fn new(config: &ReconnectThrottle) -> Self {
+        Self {
+            attempts: config.attempts,
+            window: Duration::from_secs(config.window_secs),
+            cooldown: Duration::from_secs(config.cooldown_secs),
+            addresses: HashMap::new(),
+        }
+    }
+
+    // Records a connection from the address and tells whether to go ahead with it.
+    pub fn check(&mut self, address: IpAddr) -> Verdict {
+        self.check_at(address, Instant::now())
+    }
+
+    fn check_at(&mut self, address: IpAddr, now: Instant) -> Verdict {
+        let (window, cooldown) = (self.window, self.cooldown);
+
+        // Addresses which haven't connected in a while are forgotten, so that the map doesn't grow forever.
+        self.addresses.retain(|_, attempts| {
+            while attempts.recent.front().map(|first| now.duration_since(*first) > window).unwrap_or(false) {
+                attempts.recent.pop_front();
+            }
+            if attempts.cooling_until.map(|until| until <= now).unwrap_or(false) {
+                attempts.cooling_until = None;
+            }
+            !attempts.recent.is_empty() || attempts.cooling_until.is_some()
+        });
+
+        let attempts = self.addresses.entry(address).or_default();
+        if attempts.cooling_until.is_some() {
+            return Verdict::Refuse;
+        }
+
+        attempts.recent.push_back(now);
+        if attempts.recent.len() > self.attempts {
+            attempts.recent.clear();
+            attempts.cooling_until = Some(now + cooldown);
+            return Verdict::CoolDown;
+        }
+
+        Verdict::Allow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    // 3 connections within 10 seconds, then a minute of cool-down.
+    fn throttle() -> Throttle {
+        Throttle::new(&ReconnectThrottle {
+            attempts: 3,
+            window_secs: 10,
+            cooldown_secs: 60,
+        })
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn attempts_up_to_the_limit_are_allowed() {
+        let mut throttle = throttle();
+        let start = Instant::now();
+        for i in 0..3 {
+            assert_eq!(throttle.check_at(ADDRESS, start + secs(i)), Verdict::Allow);
+        }
+
+        // Other addresses have limits of their own.
+        assert_eq!(throttle.check_at("10.0.0.1".parse().unwrap(), start + secs(3)), Verdict::Allow);
+    }
+
+    #[test]
+    fn attempts_beyond_the_limit_cool_down() {
+        let mut throttle = throttle();
+        let start = Instant::now();
+        for i in 0..3 {
+            throttle.check_at(ADDRESS, start + secs(i));
+        }
+
+        assert_eq!(throttle.check_at(ADDRESS, start + secs(3)), Verdict::CoolDown);
+        assert_eq!(throttle.check_at(ADDRESS, start + secs(4)), Verdict::Refuse);
+        assert_eq!(throttle.check_at(ADDRESS, start + secs(62)), Verdict::Refuse);
+        assert_eq!(throttle.check_at(ADDRESS, start + secs(63)), Verdict::Allow);
+    }
+
+    #[test]
+    fn attempts_outside_the_window_are_forgotten() {
+        let mut throttle = throttle();
+        let start = Instant::now();
+        for i in 0..3 {
+            throttle.check_at(ADDRESS, start + secs(i));
+        }
+
+        // The first attempt has left the window by now.
+        assert_eq!(throttle.check_at(ADDRESS, start + secs(11)), Verdict::Allow);
+        assert_eq!(throttle.addresses[&ADDRESS].recent.len(), 3);
+    }
+
+    #[test]
+    fn addresses_are_forgotten_once_quiet() {
+        let mut throttle = throttle();
+        let start = Instant::now();
+        for i in 0..4 {
+            throttle.check_at(ADDRESS, start + secs(i));
+        }
+
+        // Still cooling down, then neither in the window nor cooling down.
+        throttle.check_at("10.0.0.1".parse().unwrap(), start + secs(30));
+        assert!(throttle.addresses.contains_key(&ADDRESS));
+        throttle.check_at("10.0.0.1".parse().unwrap(), start + secs(64));
+        assert!(!throttle.addresses.contains_key(&ADDRESS));
+    }
+}
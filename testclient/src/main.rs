@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Error};
+use structopt::StructOpt;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time;
+use tokio_native_tls::native_tls::{Certificate, TlsConnector};
+
+use net::{self, Encoding, Message, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+
+// Same as input::clipboard::TEXT, the only format offered.
+const TEXT: &str = "text/plain;charset=utf-8";
+
+// Behaves like a regular client, but prints the messages it receives instead of acting on them.
+async fn run(args: Args) -> Result<(), Error> {
+    let (hostname, port) = args
+        .server
+        .rsplit_once(':')
+        .and_then(|(hostname, port)| Some((hostname, port.parse::<u16>().ok()?)))
+        .context("Invalid server description (expected hostname:port)")?;
+
+    let certificate = fs::read(&args.certificate)
+        .await
+        .context("Failed to read certificate")?;
+    let certificate = Certificate::from_der(&certificate)
+        .or_else(|_| Certificate::from_pem(&certificate))
+        .context("Failed to parse certificate")?;
+
+    let connector: tokio_native_tls::TlsConnector = TlsConnector::builder()
+        .add_root_certificate(certificate)
+        .build()
+        .context("Failed to create connector")?
+        .into();
+
+    let stream = TcpStream::connect((hostname, port)).await?;
+    let stream = BufReader::new(stream);
+    let mut stream = connector
+        .connect(hostname, stream)
+        .await
+        .context("Failed to connect")?;
+
+    net::write_version(&mut stream, PROTOCOL_VERSION).await?;
+    let offered = net::read_version(&mut stream).await?;
+    net::negotiate_version(offered).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Incompatible protocol version (got {}, expecting at least {})",
+            offered,
+            MIN_PROTOCOL_VERSION
+        )
+    })?;
+
+    net::write_message(&mut stream, &Message::Hello(args.name.clone()), Encoding::Protobuf).await?;
+    eprintln!("Connected to {}:{} as {}", hostname, port, args.name);
+
+    let mut record = match &args.record {
+        Some(path) => Some(
+            File::create(path)
+                .await
+                .with_context(|| format!("Failed to create {}", path.display()))?,
+        ),
+        None => None,
+    };
+
+    let start = Instant::now();
+    let mut read_encoding = Encoding::Protobuf;
+    let mut write_encoding = Encoding::Protobuf;
+    loop {
+        let message = time::timeout(
+            net::MESSAGE_TIMEOUT,
+            net::read_message(&mut stream, net::DEFAULT_MAX_MESSAGE_SIZE, read_encoding),
+        )
+        .await
+        .context("Read timed out")??;
+
+        // Heartbeats would drown out everything else.
+        if !matches!(message, Message::Ping(_) | Message::KeepAlive) || args.verbose {
+            let elapsed = start.elapsed().as_secs_f64();
+            if args.json {
+                println!("{}", serde_json::to_string(&message)?);
+            } else {
+                println!("{:10.3} {:?}", elapsed, message);
+            }
+
+            if let Some(record) = &mut record {
+                let line = serde_json::json!({ "time": elapsed, "message": message });
+                record.write_all(format!("{}\n", line).as_bytes()).await?;
+            }
+        }
+
+        match message {
+            Message::Ping(timestamp) => {
+                let time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|since| since.as_micros() as u64)
+                    .unwrap_or(0);
+                net::write_message(&mut stream, &Message::Pong(timestamp, time), write_encoding).await?;
+            }
+            Message::GetClipboardData => {
+                let mime_types = args.clipboard.iter().map(|_| TEXT.to_owned()).collect();
+                net::write_message(&mut stream, &Message::ClipboardOffer(mime_types), write_encoding).await?;
+            }
+            Message::ClipboardRequest(mime_type) => {
+                if let (Some(text), TEXT) = (&args.clipboard, mime_type.as_str()) {
+                    for message in net::clipboard::split(mime_type, text.clone().into_bytes()) {
+                        net::write_message(&mut stream, &message, write_encoding).await?;
+                    }
+                }
+            }
+            Message::Encodings(encodings) if !args.protobuf && encodings.contains(&Encoding::Compact) => {
+                net::write_message(&mut stream, &Message::SetEncoding(Encoding::Compact), write_encoding).await?;
+                write_encoding = Encoding::Compact;
+            }
+            Message::SetEncoding(encoding) => read_encoding = encoding,
+            _ => {}
+        }
+
+        if let Some(duration) = args.duration {
+            if start.elapsed() >= Duration::from_secs(duration) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[derive(StructOpt)]
+#[structopt(
+    name = "rkvm-testclient",
+    about = "Connects to an rkvm server and prints the received events instead of injecting them"
+)]
+struct Args {
+    #[structopt(help = "Server to connect to (hostname:port)")]
+    server: String,
+    #[structopt(long, help = "Path to the server certificate (PEM or DER)")]
+    certificate: PathBuf,
+    #[structopt(long, default_value = "rkvm-testclient", help = "Name announced to the server")]
+    name: String,
+    #[structopt(long, help = "Also write every message as a JSON line with a timestamp to this file")]
+    record: Option<PathBuf>,
+    #[structopt(long, help = "Print machine readable JSON, one message per line")]
+    json: bool,
+    #[structopt(long, help = "Also print pings and keepalives")]
+    verbose: bool,
+    #[structopt(long, help = "Text to answer clipboard requests with")]
+    clipboard: Option<String>,
+    #[structopt(long, help = "Stay on the protobuf encoding instead of switching to the compact one")]
+    protobuf: bool,
+    #[structopt(long, help = "Disconnect after this many seconds")]
+    duration: Option<u64>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args = Args::from_args();
+    if let Err(err) = run(args).await {
+        eprintln!("Error: {:#}", err);
+        process::exit(1);
+    }
+}